@@ -116,6 +116,7 @@
             [
               pkgs.dockerTools.caCertificates
               pkgsTarget.imagemagick
+              pkgsTarget.ffmpeg-headless
               backend
               ./docker
             ];
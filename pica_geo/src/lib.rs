@@ -2,37 +2,89 @@ use std::sync::OnceLock;
 
 use anyhow::{anyhow, Result};
 use arcstr::ArcStr;
-use ordered_float::OrderedFloat;
 use serde::Deserialize;
 
 static DATA: &[u8] = include_bytes!("./worldcities.csv.gz");
 
+/// Mean radius of the earth in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Locations further away from any known city than this are not assigned to a city.
+pub const MAX_DISTANCE_KM: f64 = 100.0;
+
 #[derive(Deserialize)]
 pub struct City {
     #[serde(rename = "city")]
     pub name: ArcStr,
     pub country: ArcStr,
+
+    // the state or region the city belongs to
+    #[serde(rename = "admin_name")]
+    pub region: Option<ArcStr>,
+
     #[serde(rename = "lat")]
     pub latitude: f32,
     #[serde(rename = "lng")]
     pub longitude: f32,
 }
 
+/// Finds the city closest to the given location, if there is one within [MAX_DISTANCE_KM].
 pub fn nearest_city(latitude: f32, longitude: f32) -> Result<Option<&'static City>> {
-    Ok(cities()?
-        .iter()
-        .min_by_key(|&city| OrderedFloat(distance_sqr(city, latitude, longitude))))
+    nearest_city_within(latitude, longitude, MAX_DISTANCE_KM)
+}
+
+/// Finds the city closest to the given location, if there is one within the given distance.
+pub fn nearest_city_within(latitude: f32, longitude: f32, max_distance_km: f64) -> Result<Option<&'static City>> {
+    let index = index()?;
+
+    let Some(idx) = index.tree.nearest(to_unit_vector(latitude, longitude)) else {
+        return Ok(None);
+    };
+
+    let city = &index.cities[idx];
+    let distance = haversine_km(latitude, longitude, city.latitude, city.longitude);
+
+    Ok((distance <= max_distance_km).then_some(city))
+}
+
+/// Great circle distance between two locations in kilometers.
+pub fn haversine_km(lat1: f32, lon1: f32, lat2: f32, lon2: f32) -> f64 {
+    let (lat1, lon1) = (f64::from(lat1).to_radians(), f64::from(lon1).to_radians());
+    let (lat2, lon2) = (f64::from(lat2).to_radians(), f64::from(lon2).to_radians());
+
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Position on the unit sphere. The straight line distance between two of these
+/// grows with the great circle distance, without any special cases at the poles
+/// or the antimeridian.
+fn to_unit_vector(latitude: f32, longitude: f32) -> [f64; 3] {
+    let (lat, lon) = (f64::from(latitude).to_radians(), f64::from(longitude).to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+fn distance_sqr(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
 }
 
-fn distance_sqr(city: &City, latitude: f32, longitude: f32) -> f32 {
-    let d_lat = city.latitude - latitude;
-    let d_long = city.longitude - longitude;
-    d_lat * d_lat + d_long * d_long
+struct Index {
+    cities: Vec<City>,
+    tree: KdTree,
 }
 
-fn cities() -> Result<&'static [City]> {
-    static CITIES: OnceLock<Result<Vec<City>>> = OnceLock::new();
-    CITIES.get_or_init(parse).as_deref().map_err(|err| anyhow!("{:?}", err))
+fn index() -> Result<&'static Index> {
+    static INDEX: OnceLock<Result<Index>> = OnceLock::new();
+
+    INDEX
+        .get_or_init(|| {
+            let cities = parse()?;
+            let tree = KdTree::new(&cities);
+            Ok(Index { cities, tree })
+        })
+        .as_ref()
+        .map_err(|err| anyhow!("{:?}", err))
 }
 
 fn parse() -> Result<Vec<City>> {
@@ -45,3 +97,138 @@ fn parse() -> Result<Vec<City>> {
 
     Ok(cities)
 }
+
+/// A k-d tree over the positions of the cities. The tree is stored implicitly:
+/// the median of every slice is its node, the halves left and right of it are
+/// its subtrees.
+struct KdTree {
+    nodes: Vec<([f64; 3], usize)>,
+}
+
+impl KdTree {
+    fn new(cities: &[City]) -> Self {
+        let mut nodes = cities
+            .iter()
+            .enumerate()
+            .map(|(idx, city)| (to_unit_vector(city.latitude, city.longitude), idx))
+            .collect::<Vec<_>>();
+
+        Self::build(&mut nodes, 0);
+
+        Self { nodes }
+    }
+
+    fn build(nodes: &mut [([f64; 3], usize)], depth: usize) {
+        if nodes.len() <= 1 {
+            return;
+        }
+
+        let axis = depth % 3;
+        let mid = nodes.len() / 2;
+        nodes.select_nth_unstable_by(mid, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+
+        let (left, right) = nodes.split_at_mut(mid);
+        Self::build(left, depth + 1);
+        Self::build(&mut right[1..], depth + 1);
+    }
+
+    /// Returns the index of the city closest to the target.
+    fn nearest(&self, target: [f64; 3]) -> Option<usize> {
+        let mut best = None;
+        Self::search(&self.nodes, target, 0, &mut best);
+        best.map(|(idx, _)| idx)
+    }
+
+    fn search(nodes: &[([f64; 3], usize)], target: [f64; 3], depth: usize, best: &mut Option<(usize, f64)>) {
+        if nodes.is_empty() {
+            return;
+        }
+
+        let mid = nodes.len() / 2;
+        let (point, idx) = nodes[mid];
+
+        let distance = distance_sqr(point, target);
+        if best.is_none_or(|(_, best)| distance < best) {
+            *best = Some((idx, distance));
+        }
+
+        let axis = depth % 3;
+        let diff = target[axis] - point[axis];
+
+        let (near, far) = match diff < 0.0 {
+            true => (&nodes[..mid], &nodes[mid + 1..]),
+            false => (&nodes[mid + 1..], &nodes[..mid]),
+        };
+
+        Self::search(near, target, depth + 1, best);
+
+        // the other side can only contain a closer city if the splitting plane is closer
+        if best.is_none_or(|(_, best)| diff * diff < best) {
+            Self::search(far, target, depth + 1, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn nearest_linear(latitude: f32, longitude: f32) -> &'static City {
+        let cities = &index().unwrap().cities;
+
+        cities
+            .iter()
+            .min_by(|a, b| {
+                let a = haversine_km(latitude, longitude, a.latitude, a.longitude);
+                let b = haversine_km(latitude, longitude, b.latitude, b.longitude);
+                a.total_cmp(&b)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn haversine() {
+        // hamburg to berlin
+        let distance = haversine_km(53.55, 9.99, 52.52, 13.40);
+        assert!((distance - 255.0).abs() < 2.0, "distance was {}", distance);
+
+        // across the antimeridian
+        let distance = haversine_km(0.0, 179.5, 0.0, -179.5);
+        assert!((distance - 111.2).abs() < 0.5, "distance was {}", distance);
+    }
+
+    #[test]
+    fn nearest_city_with_region() {
+        let city = nearest_city(47.26, 11.39).unwrap().expect("city");
+        assert_eq!(city.name, "Innsbruck");
+        assert_eq!(city.country, "Austria");
+        assert_eq!(city.region.as_deref(), Some("Tirol"));
+    }
+
+    #[test]
+    fn nearest_city_far_away() {
+        // the middle of the pacific
+        assert!(nearest_city(-30.0, -140.0).unwrap().is_none());
+        assert!(nearest_city_within(-30.0, -140.0, 20_000.0).unwrap().is_some());
+    }
+
+    #[test]
+    fn same_as_linear_search() {
+        for lat in (-85..=85).step_by(17) {
+            for lon in (-180..=180).step_by(23) {
+                let (lat, lon) = (lat as f32, lon as f32);
+
+                let expected = nearest_linear(lat, lon);
+                let actual = nearest_city_within(lat, lon, f64::MAX).unwrap().expect("city");
+
+                assert_eq!(
+                    (actual.latitude, actual.longitude),
+                    (expected.latitude, expected.longitude),
+                    "nearest city of {}, {}",
+                    lat,
+                    lon,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Result;
+use byteorder::{BigEndian, ReadBytesExt};
+
+/// A box of the ISO base media file format, as used by mp4, mov and cr3 files.
+pub struct Atom<'a, R> {
+    pub name: [u8; 4],
+    pub uuid: Option<[u8; 16]>,
+    pub payload: &'a mut R,
+    pub payload_start: u64,
+    pub payload_end: u64,
+}
+
+impl<'a, R> Atom<'a, R> {
+    pub fn name(&self) -> Option<&str> {
+        std::str::from_utf8(&self.name).ok()
+    }
+}
+
+impl<'a, R> Atom<'a, R>
+    where
+        R: Read + Seek,
+{
+    pub fn into_iter(self, offset: u64) -> AtomIter<'a, R> {
+        AtomIter {
+            stream: self.payload,
+            next: self.payload_start + offset,
+            end: Some(self.payload_end),
+        }
+    }
+}
+
+pub struct AtomIter<'a, R> {
+    stream: &'a mut R,
+    next: u64,
+    end: Option<u64>,
+}
+
+impl<'a, R> AtomIter<'a, R> {
+    pub fn new(stream: &'a mut R) -> Self {
+        Self {
+            stream,
+            next: 0,
+            end: None,
+        }
+    }
+}
+
+impl<'a, R> AtomIter<'a, R>
+    where
+        R: Read + Seek,
+{
+    pub fn next(&mut self) -> Result<Option<Atom<R>>> {
+        // seek to start of the next box
+        self.stream.seek(SeekFrom::Start(self.next))?;
+
+        // if we're limited to a specific length, we might need to stop now
+        if let Some(length) = self.end.as_mut() {
+            if self.next >= *length {
+                return Ok(None);
+            }
+        }
+
+        // read the length and handle eof
+        let mut length = match self.stream.read_u32::<BigEndian>() {
+            // length of zero means "end of the box"
+            Ok(0) => self.end.unwrap_or_default() - self.next,
+            Ok(length) => length as u64,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => Err(err)?,
+        };
+
+        // read name of this box
+        let mut name = [0_u8; 4];
+        self.stream.read_exact(&mut name)?;
+
+        // capture start of payload
+        let mut payload_start = self.next + 8;
+
+        // read size64 if needed
+        if length == 1 {
+            length = self.stream.read_u64::<BigEndian>()?;
+            payload_start += 8;
+        }
+
+        let payload_end = self.next + length;
+
+        // point to the start of the next box
+        self.next += length;
+
+        // if it is a uuid chunk, read the uuid too
+        let mut uuid = None;
+
+        if &name == b"uuid" {
+            let mut uuid_bytes = [0; 16];
+            self.stream.read_exact(&mut uuid_bytes)?;
+            uuid = Some(uuid_bytes);
+
+            payload_start += 16;
+        }
+
+        // and return the box
+        Ok(Some(Atom {
+            payload_start,
+            payload_end,
+            name,
+            uuid,
+            payload: self.stream,
+        }))
+    }
+}
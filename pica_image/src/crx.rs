@@ -1,4 +1,3 @@
-use std::io;
 use std::io::{Read, Seek, SeekFrom};
 
 use anyhow::{anyhow, Result};
@@ -6,118 +5,20 @@ use byteorder::{BigEndian, ReadBytesExt};
 use hex_literal::hex;
 use tracing::debug;
 
-struct Atom<'a, R> {
-    name: [u8; 4],
-    uuid: Option<[u8; 16]>,
-    payload: &'a mut R,
-    payload_start: u64,
-    payload_end: u64,
-}
-
-impl<'a, R> Atom<'a, R> {
-    pub fn name(&self) -> Option<&str> {
-        std::str::from_utf8(&self.name).ok()
-    }
-}
-
-impl<'a, R> Atom<'a, R>
-    where
-        R: Read + Seek,
-{
-    pub fn into_iter(self, offset: u64) -> AtomIter<'a, R> {
-        AtomIter {
-            stream: self.payload,
-            next: self.payload_start + offset,
-            end: Some(self.payload_end),
-        }
-    }
-}
-
-struct AtomIter<'a, R> {
-    stream: &'a mut R,
-    next: u64,
-    end: Option<u64>,
-}
-
-impl<'a, R> AtomIter<'a, R> {
-    pub fn new(stream: &'a mut R) -> Self {
-        Self {
-            stream,
-            next: 0,
-            end: None,
-        }
-    }
-}
-
-impl<'a, R> AtomIter<'a, R>
-    where
-        R: Read + Seek,
-{
-    pub fn next(&mut self) -> Result<Option<Atom<R>>> {
-        // seek to start of the next box
-        self.stream.seek(SeekFrom::Start(self.next))?;
-
-        // if we're limited to a specific length, we might need to stop now
-        if let Some(length) = self.end.as_mut() {
-            if self.next >= *length {
-                return Ok(None);
-            }
-        }
-
-        // read the length and handle eof
-        let mut length = match self.stream.read_u32::<BigEndian>() {
-            // length of zero means "end of the box"
-            Ok(0) => self.end.unwrap_or_default() - self.next,
-            Ok(length) => length as u64,
-            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
-            Err(err) => Err(err)?,
-        };
-
-        // read name of this box
-        let mut name = [0_u8; 4];
-        self.stream.read_exact(&mut name)?;
-
-        // capture start of payload
-        let mut payload_start = self.next + 8;
-
-        // read size64 if needed
-        if length == 1 {
-            length = self.stream.read_u64::<BigEndian>()?;
-            payload_start += 8;
-        }
-
-        let payload_end = self.next + length;
-
-        // point to the start of the next box
-        self.next += length;
-
-        // if it is a uuid chunk, read the uuid too
-        let mut uuid = None;
-
-        if &name == b"uuid" {
-            let mut uuid_bytes = [0; 16];
-            self.stream.read_exact(&mut uuid_bytes)?;
-            uuid = Some(uuid_bytes);
+use crate::bmff::AtomIter;
 
-            payload_start += 16;
-        }
+// uuid of the box holding the preview image
+const UUID_PREVIEW: [u8; 16] = hex!("eaf42b5e1c984b88b9fbb7dc406e4d16");
 
-        // and return the box
-        Ok(Some(Atom {
-            payload_start,
-            payload_end,
-            name,
-            uuid,
-            payload: self.stream,
-        }))
-    }
-}
+// uuid of the box within moov that holds the image metadata
+const UUID_METADATA: [u8; 16] = hex!("85c0b687820f11e08111f4ce462b6a48");
 
-pub fn read_preview(mut fp: impl Read + Seek) -> Result<Option<impl Read>> {
+/// Returns offset and length of the jpeg preview.
+pub fn find_preview(mut fp: impl Read + Seek) -> Result<Option<(u64, u64)>> {
     let mut iter = AtomIter::new(&mut fp);
 
     while let Some(atom) = iter.next()? {
-        if &atom.name == b"uuid" && atom.uuid == Some(hex!("eaf42b5e1c984b88b9fbb7dc406e4d16")) {
+        if &atom.name == b"uuid" && atom.uuid == Some(UUID_PREVIEW) {
             // found the preview chunk, get iter over children
             let mut iter = atom.into_iter(8);
 
@@ -136,23 +37,64 @@ pub fn read_preview(mut fp: impl Read + Seek) -> Result<Option<impl Read>> {
             let jpeg_size = prvw_box.payload.read_u32::<BigEndian>()? as u64;
             debug!("jpeg size is {}", jpeg_size);
 
-            return Ok(Some(fp.take(jpeg_size)));
+            let offset = prvw_box.payload.stream_position()?;
+            return Ok(Some((offset, jpeg_size)));
         }
     }
 
     Ok(None)
 }
 
+/// Reads the tiff structures holding the metadata of the image. They are
+/// returned in order, as ifd0, exif ifd, maker notes and gps ifd.
+pub fn read_metadata(mut fp: impl Read + Seek) -> Result<Vec<([u8; 4], Vec<u8>)>> {
+    let mut result = Vec::new();
+
+    let mut iter = AtomIter::new(&mut fp);
+
+    while let Some(atom) = iter.next()? {
+        if &atom.name != b"moov" {
+            continue;
+        }
+
+        let mut iter = atom.into_iter(0);
+
+        while let Some(atom) = iter.next()? {
+            if &atom.name != b"uuid" || atom.uuid != Some(UUID_METADATA) {
+                continue;
+            }
+
+            let mut iter = atom.into_iter(0);
+
+            while let Some(atom) = iter.next()? {
+                if !atom.name.starts_with(b"CMT") {
+                    continue;
+                }
+
+                atom.payload.seek(SeekFrom::Start(atom.payload_start))?;
+
+                let mut data = Vec::new();
+                atom.payload.take(atom.payload_end - atom.payload_start).read_to_end(&mut data)?;
+
+                result.push((atom.name, data));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod test {
     use std::fs::File;
     use std::io;
-    use std::io::{Read, Seek};
+    use std::io::{Read, Seek, SeekFrom};
 
     use anyhow::Result;
     use hex_literal::hex;
 
-    use crate::crx::{Atom, AtomIter, read_preview};
+    use crate::bmff::{Atom, AtomIter};
+    use crate::crx::find_preview;
 
     fn has_children<R>(b: &Atom<R>) -> Option<u64> {
         match b.name.as_slice() {
@@ -193,8 +135,9 @@ mod test {
     pub fn test_parse_crx() -> Result<()> {
         let fp = include_bytes!("../data/test.CR3");
         let mut cursor = io::Cursor::new(fp);
-        let mut preview = read_preview(&mut cursor)?.unwrap();
-        std::io::copy(&mut preview, &mut File::create("/tmp/preview.jpg")?)?;
+        let (offset, len) = find_preview(&mut cursor)?.unwrap();
+        cursor.seek(SeekFrom::Start(offset))?;
+        std::io::copy(&mut cursor.take(len), &mut File::create("/tmp/preview.jpg")?)?;
         Ok(())
     }
 }
@@ -1,30 +1,30 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::path::Path;
 
 use anyhow::Result;
 use chrono::{DateTime, NaiveDateTime, Utc};
-use exif::{Field, In, Tag, Value};
+use exif::{Context, Field, In, Tag, Value};
 use tracing::instrument;
 
+use crate::MediaType;
+
+// how much of a tiff based raw file to read to get the metadata
+const TIFF_HEADER_SIZE: u64 = 1024 * 1024;
+
 /// raw field/value exif data.
 pub struct ExifRaw(pub HashMap<String, String>);
 
 #[instrument(skip_all, fields(? path))]
 pub fn parse_exif_generic(path: impl AsRef<Path> + Debug) -> Result<Option<ExifRaw>> {
-    let mut fp = BufReader::new(File::open(path)?);
-
-    let parsed = match exif::Reader::new().read_from_container(&mut fp) {
-        Ok(data) => data,
-        Err(exif::Error::NotFound(_)) => return Ok(None),
-        Err(err) => return Err(err.into()),
+    let Some(fields) = read_fields(path.as_ref())? else {
+        return Ok(None);
     };
 
-    let fields = parsed
-        .fields()
-        .filter(|field| field.ifd_num == In::PRIMARY)
+    let fields = fields
+        .iter()
         .map(|field| (field.tag.to_string(), field.display_value().to_string()))
         .collect();
 
@@ -33,17 +33,13 @@ pub fn parse_exif_generic(path: impl AsRef<Path> + Debug) -> Result<Option<ExifR
 
 #[instrument(skip_all, fields(? path))]
 pub fn parse_exif(path: impl AsRef<Path> + Debug) -> anyhow::Result<Option<ExifSummary>> {
-    let mut fp = BufReader::new(File::open(path)?);
-
-    // TODO needs to be optimized, read_from_container sometimes read the full file
-    //  into memory, which is not really that good
-    let parsed = match exif::Reader::new().read_from_container(&mut fp) {
-        Ok(data) => data,
-        Err(exif::Error::NotFound(_)) => return Ok(None),
-        Err(err) => return Err(err.into()),
+    let Some(fields) = read_fields(path.as_ref())? else {
+        return Ok(None);
     };
 
-    let timestamp = match parsed.get_field(Tag::DateTimeOriginal, In::PRIMARY) {
+    let get = |tag: Tag| fields.iter().find(|field| field.tag == tag);
+
+    let timestamp = match get(Tag::DateTimeOriginal) {
         Some(Field {
             value: Value::Ascii(ascii_values),
             ..
@@ -55,28 +51,115 @@ pub fn parse_exif(path: impl AsRef<Path> + Debug) -> anyhow::Result<Option<ExifS
         _ => None,
     };
 
-    let latitude = parse_gps_coordinate_value(parsed.get_field(Tag::GPSLatitude, In::PRIMARY));
-    let latitude_ref = parse_gps_coordinate_ref(parsed.get_field(Tag::GPSLatitudeRef, In::PRIMARY));
-    let longitude = parse_gps_coordinate_value(parsed.get_field(Tag::GPSLongitude, In::PRIMARY));
-    let longitude_ref = parse_gps_coordinate_ref(parsed.get_field(Tag::GPSLongitudeRef, In::PRIMARY));
+    let latitude = parse_gps_coordinate_value(get(Tag::GPSLatitude));
+    let latitude_ref = parse_gps_coordinate_ref(get(Tag::GPSLatitudeRef));
+    let longitude = parse_gps_coordinate_value(get(Tag::GPSLongitude));
+    let longitude_ref = parse_gps_coordinate_ref(get(Tag::GPSLongitudeRef));
 
     // multiply with east/west and north/south factor
     let latitude = latitude.and_then(|value| Some(value * latitude_ref?));
     let longitude = longitude.and_then(|value| Some(value * longitude_ref?));
 
-    let orientation = parsed
-        .get_field(Tag::Orientation, In::PRIMARY)
+    let orientation = get(Tag::Orientation)
         .map(|f| parse_orientation(f.value.get_uint(0)))
         .unwrap_or(Orientation::Original);
 
+    let camera = camera_name(parse_ascii(get(Tag::Make)), parse_ascii(get(Tag::Model)));
+
     Ok(Some(ExifSummary {
         timestamp,
         orientation,
         latitude,
         longitude,
+        camera,
     }))
 }
 
+/// Reads the exif fields of the primary image. For raw files, this is the
+/// metadata of the raw file itself, not the one of the embedded preview.
+fn read_fields(path: &Path) -> Result<Option<Vec<Field>>> {
+    let parsed = match MediaType::from_path(path) {
+        Some(MediaType::Cr3) => return read_fields_cr3(path).map(Some),
+
+        // only read the start of the file. The metadata is in front of the raw data.
+        Some(MediaType::Arw | MediaType::Nef | MediaType::Dng | MediaType::Orf | MediaType::Rw2) => {
+            let mut buf = Vec::new();
+            File::open(path)?.take(TIFF_HEADER_SIZE).read_to_end(&mut buf)?;
+
+            // orf and rw2 use a custom magic number, but are tiff files otherwise
+            let little_endian = buf.starts_with(b"II");
+            if let Some(magic) = buf.get_mut(2..4) {
+                magic.copy_from_slice(if little_endian { &[42, 0] } else { &[0, 42] });
+            }
+
+            exif::Reader::new().continue_on_error(true).read_raw(buf)
+        }
+
+        // the embedded jpeg carries the exif data of the raw file
+        Some(MediaType::Raf) => {
+            let preview = crate::raw::extract_preview(path)?;
+            exif::Reader::new().read_from_container(&mut BufReader::new(preview.open()?))
+        }
+
+        _ => {
+            // TODO needs to be optimized, read_from_container sometimes read the full file
+            //  into memory, which is not really that good
+            let mut fp = BufReader::new(File::open(path)?);
+            exif::Reader::new().read_from_container(&mut fp)
+        }
+    };
+
+    // take what we can get from partially broken metadata
+    let parsed = match parsed.or_else(|err| err.distill_partial_result(|_| ())) {
+        Ok(data) => data,
+        Err(exif::Error::NotFound(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let fields = parsed
+        .fields()
+        .filter(|field| field.ifd_num == In::PRIMARY)
+        .cloned()
+        .collect();
+
+    Ok(Some(fields))
+}
+
+/// CR3 files store each ifd as a separate tiff structure.
+fn read_fields_cr3(path: &Path) -> Result<Vec<Field>> {
+    let mut fp = BufReader::new(File::open(path)?);
+
+    let mut fields = Vec::new();
+
+    for (name, data) in crate::crx::read_metadata(&mut fp)? {
+        let context = match &name {
+            b"CMT1" => Context::Tiff,
+            b"CMT2" => Context::Exif,
+            b"CMT4" => Context::Gps,
+            _ => continue,
+        };
+
+        let parsed = exif::Reader::new()
+            .continue_on_error(true)
+            .read_raw(data)
+            .or_else(|err| err.distill_partial_result(|_| ()))?;
+
+        // the tags are parsed as if they were in ifd0, put them into the right context
+        fields.extend(
+            parsed
+                .fields()
+                .filter(|field| field.ifd_num == In::PRIMARY)
+                .map(|field| Field {
+                    tag: Tag(context, field.tag.number()),
+                    ifd_num: field.ifd_num,
+                    value: field.value.clone(),
+                }),
+        );
+    }
+
+    Ok(fields)
+}
+
 fn parse_gps_coordinate_value(field: Option<&Field>) -> Option<f32> {
     let values = match &field?.value {
         Value::Rational(values) => values.get(..3)?,
@@ -99,6 +182,37 @@ fn parse_gps_coordinate_ref(field: Option<&Field>) -> Option<f32> {
     }
 }
 
+fn parse_ascii(field: Option<&Field>) -> Option<String> {
+    let value = match &field?.value {
+        Value::Ascii(values) => values.first()?,
+        _ => return None,
+    };
+
+    let value = String::from_utf8_lossy(value);
+    let value = value.trim_matches(|ch: char| ch == '\0' || ch.is_whitespace());
+
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+/// Combines make and model into a camera name. Most vendors
+/// already include the make in the model, e.g. "Canon EOS R6".
+fn camera_name(make: Option<String>, model: Option<String>) -> Option<String> {
+    match (make, model) {
+        (Some(make), Some(model)) => {
+            // e.g. "NIKON CORPORATION" and "NIKON Z 6"
+            let vendor = make.split_whitespace().next().unwrap_or_default();
+
+            if model.to_lowercase().starts_with(&vendor.to_lowercase()) {
+                Some(model)
+            } else {
+                Some(format!("{} {}", make, model))
+            }
+        }
+
+        (make, model) => model.or(make),
+    }
+}
+
 fn parse_orientation(value: Option<u32>) -> Orientation {
     match value {
         Some(2) => Orientation::FlipH,
@@ -137,4 +251,42 @@ pub struct ExifSummary {
     pub timestamp: Option<DateTime<Utc>>,
     pub latitude: Option<f32>,
     pub longitude: Option<f32>,
+
+    // make and model of the camera
+    pub camera: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use crate::exif::{camera_name, parse_exif, Orientation};
+
+    #[test]
+    fn test_parse_exif_orf() -> anyhow::Result<()> {
+        // olympus raw with a custom tiff magic and a single orientation tag in ifd0
+        let mut orf = b"IIRO\x08\0\0\0".to_vec();
+        orf.extend_from_slice(&[1, 0]);
+        orf.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        orf.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut file = tempfile::Builder::new().suffix(".orf").tempfile()?;
+        file.write_all(&orf)?;
+
+        let exif = parse_exif(file.path())?.expect("exif");
+        assert!(matches!(exif.orientation, Orientation::Rotate90));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_camera_name() {
+        let name = |make: &str, model: &str| camera_name(Some(make.into()), Some(model.into()));
+
+        assert_eq!(name("Canon", "Canon EOS R6").as_deref(), Some("Canon EOS R6"));
+        assert_eq!(name("NIKON CORPORATION", "NIKON Z 6").as_deref(), Some("NIKON Z 6"));
+        assert_eq!(name("Google", "Pixel 7").as_deref(), Some("Google Pixel 7"));
+        assert_eq!(camera_name(None, Some("ILCE-7M3".into())).as_deref(), Some("ILCE-7M3"));
+        assert_eq!(camera_name(None, None), None);
+    }
 }
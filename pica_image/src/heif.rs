@@ -0,0 +1,614 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use tracing::{debug, instrument};
+
+use crate::bmff::{Atom, AtomIter};
+use crate::MediaFileRef;
+
+#[derive(Debug)]
+pub struct HeifInfo {
+    // dimensions of the primary image, already rotated for display
+    pub width: u32,
+    pub height: u32,
+
+    // a small hevc encoded version of the primary image
+    pub thumbnail: Option<HeifThumbnail>,
+}
+
+#[derive(Debug)]
+pub struct HeifThumbnail {
+    // dimensions of the thumbnail, already rotated for display
+    pub width: u32,
+    pub height: u32,
+
+    // counter-clockwise rotation in multiples of 90 degrees
+    rotation: u8,
+
+    // the hevc decoder configuration, see hvcC box
+    config: Vec<u8>,
+
+    // file offset and length of the data of the thumbnail item
+    extents: Vec<(u64, u64)>,
+}
+
+/// Parses the dimensions of the primary image and the location of its thumbnail
+/// from the metadata of a heic/heif file, without decoding any image data.
+#[instrument(skip_all, fields(? path))]
+pub fn parse_heif_info(path: impl AsRef<Path> + std::fmt::Debug) -> Result<HeifInfo> {
+    let mut fp = BufReader::new(File::open(path)?);
+    read_heif_info(&mut fp)
+}
+
+pub fn read_heif_info(mut fp: impl Read + Seek) -> Result<HeifInfo> {
+    let mut iter = AtomIter::new(&mut fp);
+
+    while let Some(atom) = iter.next()? {
+        if &atom.name == b"meta" {
+            return Meta::parse(atom)?.into_info();
+        }
+    }
+
+    Err(anyhow!("no meta box found"))
+}
+
+#[derive(Default)]
+struct Meta {
+    primary: u32,
+
+    // item id to item type
+    items: HashMap<u32, [u8; 4]>,
+
+    // item id to file extents
+    locations: HashMap<u32, Vec<(u64, u64)>>,
+
+    // (from, to) pairs of thumbnail references
+    thumbnails: Vec<(u32, u32)>,
+
+    // item properties and the indices of the properties associated with each item
+    properties: Vec<Property>,
+    associations: HashMap<u32, Vec<usize>>,
+}
+
+enum Property {
+    Size(u32, u32),
+    Rotation(u8),
+    HevcConfig(Vec<u8>),
+    Other,
+}
+
+impl Meta {
+    fn parse<R: Read + Seek>(meta: Atom<R>) -> Result<Self> {
+        let mut result = Meta::default();
+
+        // skip version and flags of the meta box
+        let mut iter = meta.into_iter(4);
+
+        while let Some(atom) = iter.next()? {
+            match &atom.name {
+                b"pitm" => result.primary = parse_pitm(atom)?,
+                b"iinf" => result.items = parse_iinf(atom)?,
+                b"iloc" => result.locations = parse_iloc(atom)?,
+                b"iref" => result.thumbnails = parse_iref(atom)?,
+                b"iprp" => (result.properties, result.associations) = parse_iprp(atom)?,
+                _ => (),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn into_info(self) -> Result<HeifInfo> {
+        let (width, height) = self
+            .size_of(self.primary)
+            .ok_or_else(|| anyhow!("no size for primary item {}", self.primary))?;
+
+        let thumbnail = self
+            .thumbnails
+            .iter()
+            .filter(|(_, to)| *to == self.primary)
+            .filter(|(from, _)| self.items.get(from) == Some(b"hvc1"))
+            .find_map(|&(from, _)| self.thumbnail(from));
+
+        Ok(HeifInfo {
+            width,
+            height,
+            thumbnail,
+        })
+    }
+
+    fn thumbnail(&self, id: u32) -> Option<HeifThumbnail> {
+        let (width, height) = self.size_of(id)?;
+
+        let config = self.properties_of(id).find_map(|prop| match prop {
+            Property::HevcConfig(config) => Some(config.clone()),
+            _ => None,
+        })?;
+
+        Some(HeifThumbnail {
+            width,
+            height,
+            rotation: self.rotation_of(id),
+            config,
+            extents: self.locations.get(&id)?.clone(),
+        })
+    }
+
+    fn properties_of(&self, id: u32) -> impl Iterator<Item = &Property> {
+        self.associations
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .filter_map(|&idx| self.properties.get(idx))
+    }
+
+    fn rotation_of(&self, id: u32) -> u8 {
+        self.properties_of(id)
+            .find_map(|prop| match prop {
+                Property::Rotation(rotation) => Some(*rotation),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Size of an item as displayed, e.g. with rotation applied
+    fn size_of(&self, id: u32) -> Option<(u32, u32)> {
+        let (width, height) = self.properties_of(id).find_map(|prop| match prop {
+            Property::Size(width, height) => Some((*width, *height)),
+            _ => None,
+        })?;
+
+        match self.rotation_of(id) % 2 {
+            1 => Some((height, width)),
+            _ => Some((width, height)),
+        }
+    }
+}
+
+/// Reads version and flags of a full box
+fn read_full_box_header<R: Read + Seek>(atom: &mut Atom<R>) -> Result<(u8, u32)> {
+    atom.payload.seek(SeekFrom::Start(atom.payload_start))?;
+    let version = atom.payload.read_u8()?;
+    let flags = atom.payload.read_u24::<BigEndian>()?;
+    Ok((version, flags))
+}
+
+fn read_id<R: Read>(r: &mut R, large: bool) -> Result<u32> {
+    Ok(match large {
+        true => r.read_u32::<BigEndian>()?,
+        false => r.read_u16::<BigEndian>()? as u32,
+    })
+}
+
+fn read_sized<R: Read>(r: &mut R, size: u8) -> Result<u64> {
+    Ok(match size {
+        0 => 0,
+        4 => r.read_u32::<BigEndian>()? as u64,
+        8 => r.read_u64::<BigEndian>()?,
+        _ => return Err(anyhow!("unsupported field size {}", size)),
+    })
+}
+
+fn parse_pitm<R: Read + Seek>(mut atom: Atom<R>) -> Result<u32> {
+    let (version, _) = read_full_box_header(&mut atom)?;
+    read_id(atom.payload, version > 0)
+}
+
+fn parse_iinf<R: Read + Seek>(mut atom: Atom<R>) -> Result<HashMap<u32, [u8; 4]>> {
+    let (version, _) = read_full_box_header(&mut atom)?;
+    let skip = if version == 0 { 2 } else { 4 };
+
+    let mut items = HashMap::new();
+
+    let mut iter = atom.into_iter(4 + skip);
+
+    while let Some(mut infe) = iter.next()? {
+        if &infe.name != b"infe" {
+            continue;
+        }
+
+        // older versions do not carry an item type
+        let (version, _) = read_full_box_header(&mut infe)?;
+        if version < 2 {
+            continue;
+        }
+
+        let id = read_id(infe.payload, version > 2)?;
+        let _protection_index = infe.payload.read_u16::<BigEndian>()?;
+
+        let mut typ = [0_u8; 4];
+        infe.payload.read_exact(&mut typ)?;
+
+        items.insert(id, typ);
+    }
+
+    Ok(items)
+}
+
+fn parse_iloc<R: Read + Seek>(mut atom: Atom<R>) -> Result<HashMap<u32, Vec<(u64, u64)>>> {
+    let (version, _) = read_full_box_header(&mut atom)?;
+    let r = atom.payload;
+
+    let sizes = r.read_u8()?;
+    let (offset_size, length_size) = (sizes >> 4, sizes & 0xf);
+
+    let sizes = r.read_u8()?;
+    let base_offset_size = sizes >> 4;
+    let index_size = if version > 0 { sizes & 0xf } else { 0 };
+
+    let count = read_id(r, version > 1)?;
+
+    let mut locations = HashMap::new();
+
+    for _ in 0..count {
+        let id = read_id(r, version > 1)?;
+
+        // we only support data stored in the file itself
+        let construction_method = match version {
+            1 | 2 => r.read_u16::<BigEndian>()? & 0xf,
+            _ => 0,
+        };
+
+        let _data_reference_index = r.read_u16::<BigEndian>()?;
+        let base_offset = read_sized(r, base_offset_size)?;
+
+        let extent_count = r.read_u16::<BigEndian>()?;
+
+        let mut extents = Vec::new();
+
+        for _ in 0..extent_count {
+            let _index = read_sized(r, index_size)?;
+            let offset = read_sized(r, offset_size)?;
+            let length = read_sized(r, length_size)?;
+            extents.push((base_offset + offset, length));
+        }
+
+        if construction_method == 0 {
+            locations.insert(id, extents);
+        }
+    }
+
+    Ok(locations)
+}
+
+fn parse_iref<R: Read + Seek>(mut atom: Atom<R>) -> Result<Vec<(u32, u32)>> {
+    let (version, _) = read_full_box_header(&mut atom)?;
+
+    let mut thumbnails = Vec::new();
+
+    let mut iter = atom.into_iter(4);
+
+    while let Some(reference) = iter.next()? {
+        if &reference.name != b"thmb" {
+            continue;
+        }
+
+        let r = reference.payload;
+        r.seek(SeekFrom::Start(reference.payload_start))?;
+
+        let from = read_id(r, version > 0)?;
+        let count = r.read_u16::<BigEndian>()?;
+
+        for _ in 0..count {
+            thumbnails.push((from, read_id(r, version > 0)?));
+        }
+    }
+
+    Ok(thumbnails)
+}
+
+type Associations = HashMap<u32, Vec<usize>>;
+
+fn parse_iprp<R: Read + Seek>(atom: Atom<R>) -> Result<(Vec<Property>, Associations)> {
+    let mut properties = Vec::new();
+    let mut associations = HashMap::new();
+
+    let mut iter = atom.into_iter(0);
+
+    while let Some(atom) = iter.next()? {
+        match &atom.name {
+            b"ipco" => properties = parse_ipco(atom)?,
+            b"ipma" => parse_ipma(atom, &mut associations)?,
+            _ => (),
+        }
+    }
+
+    Ok((properties, associations))
+}
+
+fn parse_ipco<R: Read + Seek>(atom: Atom<R>) -> Result<Vec<Property>> {
+    let mut properties = Vec::new();
+
+    let mut iter = atom.into_iter(0);
+
+    while let Some(mut atom) = iter.next()? {
+        let property = match &atom.name {
+            b"ispe" => {
+                read_full_box_header(&mut atom)?;
+                let width = atom.payload.read_u32::<BigEndian>()?;
+                let height = atom.payload.read_u32::<BigEndian>()?;
+                Property::Size(width, height)
+            }
+
+            b"irot" => {
+                atom.payload.seek(SeekFrom::Start(atom.payload_start))?;
+                Property::Rotation(atom.payload.read_u8()? & 0x3)
+            }
+
+            b"hvcC" => {
+                atom.payload.seek(SeekFrom::Start(atom.payload_start))?;
+
+                let mut config = Vec::new();
+                let len = atom.payload_end - atom.payload_start;
+                atom.payload.take(len).read_to_end(&mut config)?;
+
+                Property::HevcConfig(config)
+            }
+
+            _ => Property::Other,
+        };
+
+        properties.push(property);
+    }
+
+    Ok(properties)
+}
+
+fn parse_ipma<R: Read + Seek>(mut atom: Atom<R>, associations: &mut Associations) -> Result<()> {
+    let (version, flags) = read_full_box_header(&mut atom)?;
+    let r = atom.payload;
+
+    let count = r.read_u32::<BigEndian>()?;
+
+    for _ in 0..count {
+        let id = read_id(r, version > 0)?;
+        let association_count = r.read_u8()?;
+
+        let mut indices = Vec::new();
+
+        for _ in 0..association_count {
+            // the highest bit marks the property as essential
+            let idx = match flags & 1 {
+                1 => r.read_u16::<BigEndian>()? as usize & 0x7fff,
+                _ => r.read_u8()? as usize & 0x7f,
+            };
+
+            // indices are one based, zero means 'no property'
+            if idx > 0 {
+                indices.push(idx - 1);
+            }
+        }
+
+        associations.insert(id, indices);
+    }
+
+    Ok(())
+}
+
+impl HeifThumbnail {
+    /// Converts the thumbnail item into a raw hevc stream with start codes, as expected by decoders.
+    fn to_annex_b(&self, mut fp: impl Read + Seek) -> Result<Vec<u8>> {
+        let mut stream = Vec::new();
+
+        let mut config = self.config.as_slice();
+
+        // skip to the length size and the parameter set arrays
+        let header = config.get(..23).ok_or_else(|| anyhow!("hvcC too short"))?;
+        let length_size = (header[21] & 0x3) as usize + 1;
+        let array_count = header[22];
+        config = &config[23..];
+
+        for _ in 0..array_count {
+            let _nal_type = config.read_u8()?;
+            let nal_count = config.read_u16::<BigEndian>()?;
+
+            for _ in 0..nal_count {
+                let len = config.read_u16::<BigEndian>()? as usize;
+                let nal = config.get(..len).ok_or_else(|| anyhow!("hvcC nal unit too short"))?;
+
+                stream.write_all(&[0, 0, 0, 1])?;
+                stream.write_all(nal)?;
+                config = &config[len..];
+            }
+        }
+
+        // the item data is a sequence of length prefixed nal units
+        let mut data = Vec::new();
+
+        for &(offset, len) in &self.extents {
+            fp.seek(SeekFrom::Start(offset))?;
+            (&mut fp).take(len).read_to_end(&mut data)?;
+        }
+
+        let mut data = data.as_slice();
+
+        while !data.is_empty() {
+            let len = data.read_uint::<BigEndian>(length_size)? as usize;
+            let nal = data.get(..len).ok_or_else(|| anyhow!("nal unit too short"))?;
+
+            stream.write_all(&[0, 0, 0, 1])?;
+            stream.write_all(nal)?;
+            data = &data[len..];
+        }
+
+        Ok(stream)
+    }
+}
+
+/// Decodes the embedded thumbnail of a heif file into a jpeg.
+/// This requires `ffmpeg` on the PATH.
+#[instrument(skip_all, fields(? path))]
+pub fn extract_thumbnail(path: &Path, thumbnail: &HeifThumbnail) -> Result<MediaFileRef> {
+    let stream = thumbnail.to_annex_b(BufReader::new(File::open(path)?))?;
+
+    let jpeg = tempfile::Builder::new().suffix(".jpg").tempfile()?.into_temp_path();
+
+    // irot rotates counter-clockwise
+    let filter = match thumbnail.rotation {
+        1 => "transpose=2",
+        2 => "hflip,vflip",
+        3 => "transpose=1",
+        _ => "null",
+    };
+
+    let mut child = Command::new("ffmpeg")
+        .arg("-loglevel").arg("error")
+        .arg("-f").arg("hevc")
+        .arg("-i").arg("pipe:0")
+        .arg("-frames:v").arg("1")
+        .arg("-vf").arg(filter)
+        .arg("-q:v").arg("2")
+        .arg("-f").arg("image2")
+        .arg("-y")
+        .arg(&jpeg)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    // closes stdin after writing, so ffmpeg sees the end of the stream
+    child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?.write_all(&stream)?;
+
+    let res = child.wait_with_output()?;
+    if !res.status.success() {
+        return Err(anyhow!("ffmpeg failed with status {:?}", res.status.code()));
+    }
+
+    debug!("Decoded heif thumbnail of size {}x{}", thumbnail.width, thumbnail.height);
+
+    Ok(MediaFileRef::Temporary(jpeg))
+}
+
+/// Decodes the primary image of a heif file into a jpeg.
+/// This requires image magick with heif support on the PATH.
+#[instrument(skip_all, fields(? path))]
+pub fn convert_to_jpeg(path: &Path) -> Result<MediaFileRef> {
+    let jpeg = tempfile::Builder::new().suffix(".jpg").tempfile()?.into_temp_path();
+
+    // the decoder already applies the rotation. Strip the exif orientation
+    // so that it will not be applied a second time.
+    let res = Command::new("convert")
+        .arg(path)
+        .arg("-strip")
+        .arg("-quality").arg("95")
+        .arg(&jpeg)
+        .output()?;
+
+    if !res.status.success() {
+        return Err(anyhow!("convert failed with status {:?}", res.status.code()));
+    }
+
+    Ok(MediaFileRef::Temporary(jpeg))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use crate::heif::read_heif_info;
+
+    fn atom(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u32::<BigEndian>(payload.len() as u32 + 8).unwrap();
+        buf.write_all(name).unwrap();
+        buf.write_all(payload).unwrap();
+        buf
+    }
+
+    fn full_atom(name: &[u8; 4], version: u8, payload: &[u8]) -> Vec<u8> {
+        atom(name, &[&[version, 0, 0, 0], payload].concat())
+    }
+
+    fn infe(id: u16, typ: &[u8; 4]) -> Vec<u8> {
+        full_atom(b"infe", 2, &[&id.to_be_bytes(), &[0, 0][..], typ, b"\0"].concat())
+    }
+
+    fn ispe(width: u32, height: u32) -> Vec<u8> {
+        full_atom(b"ispe", 0, &[width.to_be_bytes(), height.to_be_bytes()].concat())
+    }
+
+    fn hvcc() -> Vec<u8> {
+        let mut config = vec![0; 23];
+        // four bytes nal unit length
+        config[21] = 3;
+        // a single array with a single parameter set
+        config[22] = 1;
+        config.extend_from_slice(&[32, 0, 1, 0, 1, 0xaa]);
+        atom(b"hvcC", &config)
+    }
+
+    fn heif(rotation: u8, data_offset: u32) -> Vec<u8> {
+        let iinf = full_atom(b"iinf", 0, &[&2_u16.to_be_bytes(), &infe(1, b"grid")[..], &infe(2, b"hvc1")].concat());
+
+        let iloc = {
+            let mut buf = vec![0x44, 0x00];
+            buf.write_u16::<BigEndian>(1).unwrap();
+            buf.write_u16::<BigEndian>(2).unwrap();
+            buf.write_u16::<BigEndian>(0).unwrap();
+            buf.write_u16::<BigEndian>(1).unwrap();
+            buf.write_u32::<BigEndian>(data_offset).unwrap();
+            buf.write_u32::<BigEndian>(6).unwrap();
+            full_atom(b"iloc", 0, &buf)
+        };
+
+        let iref = full_atom(b"iref", 0, &atom(b"thmb", &[0, 2, 0, 1, 0, 1]));
+
+        let ipco = atom(b"ipco", &[ispe(4032, 3024), atom(b"irot", &[rotation]), ispe(320, 240), hvcc()].concat());
+
+        // item 1: ispe + irot, item 2: ispe + irot + hvcC
+        let ipma = full_atom(b"ipma", 0, &[0, 0, 0, 2, 0, 1, 2, 0x81, 2, 0, 2, 3, 3, 0x82, 0x84]);
+
+        let meta = full_atom(
+            b"meta",
+            0,
+            &[
+                full_atom(b"pitm", 0, &[0, 1]),
+                iinf,
+                iloc,
+                iref,
+                atom(b"iprp", &[ipco, ipma].concat()),
+            ]
+            .concat(),
+        );
+
+        [atom(b"ftyp", b"heic"), meta].concat()
+    }
+
+    #[test]
+    fn test_read_heif_info() -> anyhow::Result<()> {
+        let info = read_heif_info(Cursor::new(heif(0, 0)))?;
+        assert_eq!((info.width, info.height), (4032, 3024));
+
+        let thumbnail = info.thumbnail.expect("thumbnail");
+        assert_eq!((thumbnail.width, thumbnail.height), (320, 240));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_heif_info_rotated() -> anyhow::Result<()> {
+        let info = read_heif_info(Cursor::new(heif(1, 0)))?;
+        assert_eq!((info.width, info.height), (3024, 4032));
+        assert_eq!(info.thumbnail.map(|thumb| (thumb.width, thumb.height)), Some((240, 320)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_to_annex_b() -> anyhow::Result<()> {
+        // the item data directly follows the metadata
+        let offset = heif(0, 0).len() as u32;
+        let file = [heif(0, offset), vec![0, 0, 0, 2, 0x26, 0x01]].concat();
+
+        let thumbnail = read_heif_info(Cursor::new(&file))?.thumbnail.expect("thumbnail");
+        let stream = thumbnail.to_annex_b(Cursor::new(&file))?;
+        assert_eq!(stream, [0, 0, 0, 1, 0xaa, 0, 0, 0, 1, 0x26, 0x01]);
+
+        Ok(())
+    }
+}
@@ -1,82 +1,64 @@
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use tempfile::TempPath;
-use tracing::{info, instrument};
 
+use crate::range::RangeReader;
+use tracing::{instrument, warn};
+
+mod bmff;
 mod crx;
 pub mod exif;
+pub mod heif;
+pub mod range;
+pub mod raw;
+pub mod video;
 
 pub fn get(path: impl AsRef<Path> + Into<PathBuf>) -> Result<MediaFileRef> {
     match MediaType::from_path(path.as_ref()) {
         Some(MediaType::GenericImage) => Ok(MediaFileRef::Persistent(path.into())),
-        Some(MediaType::GenericVideo) => todo!(),
-        Some(MediaType::Arw) => extract_thumbnail_arw(path.as_ref()),
+        Some(MediaType::GenericVideo) => video::extract_poster_frame(path.as_ref()),
+        Some(MediaType::Heif) => heif::convert_to_jpeg(path.as_ref()),
         Some(MediaType::Cr3) => extract_thumbnail_cr3(path.as_ref()),
+        Some(MediaType::Arw | MediaType::Nef | MediaType::Dng | MediaType::Raf | MediaType::Orf | MediaType::Rw2) => {
+            raw::extract_preview(path.as_ref())
+        }
         None => Err(anyhow!("unknown media type for {:?}", path.as_ref())),
     }
 }
 
-#[instrument(skip_all, fields(? path))]
-fn extract_thumbnail_cr3(path: &Path) -> Result<MediaFileRef> {
-    let fp = BufReader::new(File::open(path)?);
-
-    // find preview in file
-    let mut preview = crx::read_preview(fp)?.ok_or_else(|| anyhow!("no preview in {:?}", path))?;
-
-    // copy it to a temporary file
-    let mut jpeg = tempfile::Builder::new().suffix(".jpg").tempfile()?;
-
-    std::io::copy(&mut preview, &mut jpeg)?;
-    jpeg.flush()?;
+/// Like [get], but might return a smaller image if it is at least `size` pixels wide or high,
+/// e.g. a thumbnail embedded in the media file.
+pub fn get_for_size(path: impl AsRef<Path> + Into<PathBuf>, size: u32) -> Result<MediaFileRef> {
+    if let Some(MediaType::Heif) = MediaType::from_path(path.as_ref()) {
+        let info = heif::parse_heif_info(path.as_ref())?;
+
+        let thumbnail = info
+            .thumbnail
+            .filter(|thumb| thumb.width.max(thumb.height) >= size);
+
+        if let Some(thumbnail) = thumbnail {
+            match heif::extract_thumbnail(path.as_ref(), &thumbnail) {
+                Ok(jpeg) => return Ok(jpeg),
+                Err(err) => warn!("Failed to extract heif thumbnail, decoding full image: {:?}", err),
+            }
+        }
+    }
 
-    Ok(MediaFileRef::Temporary(jpeg.into_temp_path()))
+    get(path)
 }
 
 #[instrument(skip_all, fields(? path))]
-fn extract_thumbnail_arw(path: &Path) -> Result<MediaFileRef> {
-    use ::exif::*;
-
-    let mut fp = BufReader::new(File::open(path)?);
-
-    // read a little bit if the file into memory
-    let mut buf = Vec::new();
-    (&mut fp).take(1024 * 1024).read_to_end(&mut buf)?;
-
-    // parse the exif data
-    let parsed = Reader::new().read_from_container(&mut Cursor::new(buf))?;
-
-    // find the preview image in the exif data
-    let tag_preview_start = Tag(Context::Tiff, 513);
-    let tag_preview_len = Tag(Context::Tiff, 514);
-
-    let preview_start = parsed
-        .get_field(tag_preview_start, In(0))
-        .ok_or_else(|| anyhow!("PreviewImageStart not found"))?
-        .value
-        .get_uint(0)
-        .ok_or_else(|| anyhow!("no value for PreviewImageStart"))?;
-
-    let preview_len = parsed
-        .get_field(tag_preview_len, In(0))
-        .ok_or_else(|| anyhow!("PreviewImageLength not found"))?
-        .value
-        .get_uint(0)
-        .ok_or_else(|| anyhow!("no value for PreviewImageLength"))?;
-
-    info!("Preview starts at {} with {} bytes", preview_start, preview_len);
-
-    fp.seek(SeekFrom::Start(preview_start as u64))?;
-
-    let mut jpeg = tempfile::Builder::new().suffix(".jpg").tempfile()?;
+fn extract_thumbnail_cr3(path: &Path) -> Result<MediaFileRef> {
+    let fp = BufReader::new(File::open(path)?);
 
-    std::io::copy(&mut fp.take(preview_len as u64), &mut jpeg)?;
-    jpeg.flush()?;
+    // find preview in file
+    let (offset, len) = crx::find_preview(fp)?.ok_or_else(|| anyhow!("no preview in {:?}", path))?;
 
-    Ok(MediaFileRef::Temporary(jpeg.into_temp_path()))
+    Ok(MediaFileRef::Embedded { path: path.to_owned(), offset, len })
 }
 
 // A media file format
@@ -88,11 +70,29 @@ pub enum MediaType {
     // a generic video format
     GenericVideo,
 
+    // heic or heif image, e.g. from an iphone
+    Heif,
+
     // sony arw file
     Arw,
 
     // canon raw file
     Cr3,
+
+    // nikon raw file
+    Nef,
+
+    // adobe digital negative
+    Dng,
+
+    // fujifilm raw file
+    Raf,
+
+    // olympus raw file
+    Orf,
+
+    // panasonic raw file
+    Rw2,
 }
 
 impl MediaType {
@@ -103,8 +103,14 @@ impl MediaType {
         let format = match extension.to_ascii_lowercase().as_str() {
             "jpg" | "jpeg" | "png" | "avif" => MediaType::GenericImage,
             "mp4" | "avi" | "mov" | "mkv" => MediaType::GenericVideo,
+            "heic" | "heif" => MediaType::Heif,
             "arw" => MediaType::Arw,
             "cr3" => MediaType::Cr3,
+            "nef" => MediaType::Nef,
+            "dng" => MediaType::Dng,
+            "raf" => MediaType::Raf,
+            "orf" => MediaType::Orf,
+            "rw2" => MediaType::Rw2,
             _ => return None,
         };
 
@@ -112,7 +118,11 @@ impl MediaType {
     }
 
     pub fn is_raw(&self) -> bool {
-        matches!(self, Self::Arw | Self::Cr3)
+        matches!(self, Self::Arw | Self::Cr3 | Self::Nef | Self::Dng | Self::Raf | Self::Orf | Self::Rw2)
+    }
+
+    pub fn is_video(&self) -> bool {
+        matches!(self, Self::GenericVideo)
     }
 }
 
@@ -120,13 +130,31 @@ impl MediaType {
 pub enum MediaFileRef {
     Temporary(TempPath),
     Persistent(PathBuf),
+
+    // a range of bytes within a file, e.g. the jpeg preview of a raw file
+    Embedded { path: PathBuf, offset: u64, len: u64 },
 }
 
-impl AsRef<Path> for MediaFileRef {
-    fn as_ref(&self) -> &Path {
+impl MediaFileRef {
+    /// The file on disk that holds the media file.
+    pub fn path(&self) -> &Path {
         match self {
             MediaFileRef::Temporary(p) => p.as_ref(),
             MediaFileRef::Persistent(p) => p.as_ref(),
+            MediaFileRef::Embedded { path, .. } => path.as_ref(),
         }
     }
+
+    /// Opens the media file for reading. Embedded files are read directly from their
+    /// containing file, without copying them out first.
+    pub fn open(&self) -> std::io::Result<RangeReader<BufReader<File>>> {
+        let fp = File::open(self.path())?;
+
+        let (offset, len) = match self {
+            MediaFileRef::Embedded { offset, len, .. } => (*offset, *len),
+            _ => (0, fp.metadata()?.len()),
+        };
+
+        RangeReader::new(BufReader::new(fp), offset, len)
+    }
 }
@@ -0,0 +1,104 @@
+use std::io;
+use std::io::{BufRead, Read, Seek, SeekFrom};
+
+/// Restricts reading to a range of bytes of the underlying reader, e.g. to
+/// read a jpeg preview embedded in a raw file as if it was a file of its own.
+pub struct RangeReader<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+
+    // position relative to start
+    pos: u64,
+}
+
+impl<R: Seek> RangeReader<R> {
+    pub fn new(mut inner: R, start: u64, len: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self { inner, start, len, pos: 0 })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len.saturating_sub(self.pos)
+    }
+}
+
+impl<R: Read + Seek> Read for RangeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = self.remaining().min(buf.len() as u64) as usize;
+        let n = self.inner.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead + Seek> BufRead for RangeReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.remaining();
+        let buf = self.inner.fill_buf()?;
+        let max = remaining.min(buf.len() as u64) as usize;
+        Ok(&buf[..max])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.pos += amt as u64;
+    }
+}
+
+impl<R: Seek> Seek for RangeReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        let Some(pos) = pos else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position"));
+        };
+
+        self.inner.seek(SeekFrom::Start(self.start + pos))?;
+        self.pos = pos;
+
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, Cursor, Read, Seek, SeekFrom};
+
+    use crate::range::RangeReader;
+
+    #[test]
+    fn test_read_range() -> anyhow::Result<()> {
+        let mut r = RangeReader::new(Cursor::new(b"0123456789".to_vec()), 2, 5)?;
+
+        let mut buf = String::new();
+        r.read_to_string(&mut buf)?;
+        assert_eq!(buf, "23456");
+
+        r.seek(SeekFrom::End(-2))?;
+        assert_eq!(r.fill_buf()?, b"56");
+
+        r.seek(SeekFrom::Start(1))?;
+        assert_eq!(r.fill_buf()?, b"3456");
+        r.consume(1);
+        assert_eq!(r.stream_position()?, 2);
+
+        let mut buf = [0; 8];
+        assert_eq!(r.read(&mut buf)?, 3);
+        assert_eq!(&buf[..3], b"456");
+
+        Ok(())
+    }
+}
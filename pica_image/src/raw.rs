@@ -0,0 +1,551 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use tracing::{debug, instrument, warn};
+
+use crate::MediaFileRef;
+
+// tiff tags that point to preview images or to other ifds
+const TAG_NEW_SUBFILE_TYPE: u16 = 254;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_SUB_IFDS: u16 = 330;
+const TAG_JPEG_OFFSET: u16 = 513;
+const TAG_JPEG_LENGTH: u16 = 514;
+const TAG_EXIF_IFD: u16 = 34665;
+const TAG_MAKER_NOTE: u16 = 37500;
+
+// panasonic rw2 stores a full size jpeg in its own tag
+const TAG_RW2_JPEG_FROM_RAW: u16 = 0x2e;
+
+// olympus maker notes keep the preview in the camera settings ifd
+const TAG_OLYMPUS_CAMERA_SETTINGS: u16 = 0x2020;
+const TAG_OLYMPUS_PREVIEW_OFFSET: u16 = 0x101;
+const TAG_OLYMPUS_PREVIEW_LENGTH: u16 = 0x102;
+
+/// Location of an embedded jpeg preview within a raw file
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Preview {
+    pub offset: u64,
+    pub len: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Locates the largest embedded jpeg preview of a raw file.
+/// Supports tiff based formats like nef, dng, arw, orf and rw2, and fuji raf files.
+#[instrument(skip_all, fields(? path))]
+pub fn extract_preview(path: &Path) -> Result<MediaFileRef> {
+    let mut fp = BufReader::new(File::open(path)?);
+
+    let preview = find_preview(&mut fp)?.ok_or_else(|| anyhow!("no preview in {:?}", path))?;
+
+    debug!("Preview of size {}x{} starts at {} with {} bytes", preview.width, preview.height, preview.offset, preview.len);
+
+    Ok(MediaFileRef::Embedded {
+        path: path.to_owned(),
+        offset: preview.offset,
+        len: preview.len,
+    })
+}
+
+/// Finds the largest jpeg preview embedded in a raw file.
+pub fn find_preview<R: Read + Seek>(r: &mut R) -> Result<Option<Preview>> {
+    let mut header = [0_u8; 16];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header)?;
+
+    let candidates = match &header {
+        b"FUJIFILMCCD-RAW " => raf_candidates(r)?,
+        _ => Tiff::new(r)?.candidates()?,
+    };
+
+    let mut best: Option<Preview> = None;
+
+    for (offset, len) in candidates {
+        let dimensions = match jpeg_dimensions(r, offset, len) {
+            Ok(dimensions) => dimensions,
+            Err(err) => {
+                warn!("Skipping unreadable preview at {} with {} bytes: {:?}", offset, len, err);
+                continue;
+            }
+        };
+
+        // skip anything that is not a jpeg we can decode, e.g. lossless compressed raw data
+        let Some((width, height)) = dimensions else {
+            continue;
+        };
+
+        let preview = Preview { offset, len, width, height };
+
+        let pixels = |p: &Preview| p.width as u64 * p.height as u64;
+        if best.as_ref().is_none_or(|best| pixels(&preview) > pixels(best)) {
+            best = Some(preview);
+        }
+    }
+
+    Ok(best)
+}
+
+fn raf_candidates<R: Read + Seek>(r: &mut R) -> Result<Vec<(u64, u64)>> {
+    // offset and length of the jpeg preview are at a fixed position in the header
+    r.seek(SeekFrom::Start(84))?;
+    let offset = r.read_u32::<BigEndian>()?;
+    let len = r.read_u32::<BigEndian>()?;
+    Ok(vec![(offset as u64, len as u64)])
+}
+
+struct Entry {
+    tag: u16,
+    typ: u16,
+    count: u32,
+    value: [u8; 4],
+}
+
+struct Tiff<'a, R> {
+    r: &'a mut R,
+    big_endian: bool,
+}
+
+impl<'a, R: Read + Seek> Tiff<'a, R> {
+    fn new(r: &'a mut R) -> Result<Self> {
+        r.seek(SeekFrom::Start(0))?;
+
+        let mut order = [0_u8; 2];
+        r.read_exact(&mut order)?;
+
+        let big_endian = match &order {
+            b"II" => false,
+            b"MM" => true,
+            _ => return Err(anyhow!("not a tiff file")),
+        };
+
+        let mut tiff = Tiff { r, big_endian };
+
+        // orf and rw2 files use their own magic number
+        match tiff.u16()? {
+            42 | 0x4f52 | 0x5352 | 0x55 => Ok(tiff),
+            magic => Err(anyhow!("unknown tiff magic {:#x}", magic)),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(match self.big_endian {
+            true => self.r.read_u16::<BigEndian>()?,
+            false => self.r.read_u16::<LittleEndian>()?,
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(match self.big_endian {
+            true => self.r.read_u32::<BigEndian>()?,
+            false => self.r.read_u32::<LittleEndian>()?,
+        })
+    }
+
+    /// Value of an entry holding a single short or long
+    fn scalar(&self, entry: &Entry) -> u32 {
+        match (entry.typ, self.big_endian) {
+            // short
+            (3, true) => BigEndian::read_u16(&entry.value) as u32,
+            (3, false) => LittleEndian::read_u16(&entry.value) as u32,
+            (_, true) => BigEndian::read_u32(&entry.value),
+            (_, false) => LittleEndian::read_u32(&entry.value),
+        }
+    }
+
+    /// Values of an entry holding a list of longs, e.g. ifd offsets
+    fn longs(&mut self, entry: &Entry) -> Result<Vec<u32>> {
+        if entry.count <= 1 {
+            return Ok(vec![self.scalar(entry)]);
+        }
+
+        self.r.seek(SeekFrom::Start(self.scalar(entry) as u64))?;
+        (0..entry.count.min(64)).map(|_| self.u32()).collect()
+    }
+
+    fn read_ifd(&mut self, offset: u64) -> Result<(Vec<Entry>, u32)> {
+        self.r.seek(SeekFrom::Start(offset))?;
+
+        let count = self.u16()?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+
+        for _ in 0..count {
+            let tag = self.u16()?;
+            let typ = self.u16()?;
+            let count = self.u32()?;
+
+            let mut value = [0_u8; 4];
+            self.r.read_exact(&mut value)?;
+
+            entries.push(Entry { tag, typ, count, value });
+        }
+
+        let next = self.u32()?;
+
+        Ok((entries, next))
+    }
+
+    /// Walks all ifds and collects offset and length of everything that might be a jpeg preview
+    fn candidates(&mut self) -> Result<Vec<(u64, u64)>> {
+        self.r.seek(SeekFrom::Start(4))?;
+        let first = self.u32()?;
+
+        let mut candidates = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = vec![first as u64];
+
+        while let Some(offset) = queue.pop() {
+            // protect against loops in broken files
+            if offset == 0 || !visited.insert(offset) || visited.len() > 64 {
+                continue;
+            }
+
+            // a broken ifd should not hide the previews found in the others
+            let (entries, next) = match self.read_ifd(offset) {
+                Ok(ifd) => ifd,
+                Err(err) => {
+                    warn!("Skipping unreadable ifd at {}: {:?}", offset, err);
+                    continue;
+                }
+            };
+
+            queue.push(next as u64);
+
+            let get = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+
+            if let (Some(start), Some(len)) = (get(TAG_JPEG_OFFSET), get(TAG_JPEG_LENGTH)) {
+                candidates.push((self.scalar(start) as u64, self.scalar(len) as u64));
+            }
+
+            // jpeg compressed strips, e.g. dng previews
+            let compression = get(TAG_COMPRESSION).map(|entry| self.scalar(entry));
+            let is_preview = get(TAG_NEW_SUBFILE_TYPE).is_none_or(|entry| self.scalar(entry) & 1 == 1);
+
+            if let (Some(6 | 7), true, Some(start), Some(len)) =
+                (compression, is_preview, get(TAG_STRIP_OFFSETS), get(TAG_STRIP_BYTE_COUNTS))
+            {
+                if start.count == 1 {
+                    candidates.push((self.scalar(start) as u64, self.scalar(len) as u64));
+                }
+            }
+
+            if let Some(entry) = get(TAG_RW2_JPEG_FROM_RAW) {
+                candidates.push((self.scalar(entry) as u64, entry.count as u64));
+            }
+
+            for entry in [get(TAG_SUB_IFDS), get(TAG_EXIF_IFD)].into_iter().flatten() {
+                match self.longs(entry) {
+                    Ok(offsets) => queue.extend(offsets.into_iter().map(u64::from)),
+                    Err(err) => warn!("Skipping unreadable ifd offsets of tag {}: {:?}", entry.tag, err),
+                }
+            }
+
+            if let Some(entry) = get(TAG_MAKER_NOTE) {
+                match self.olympus_candidates(self.scalar(entry) as u64) {
+                    Ok(found) => candidates.extend(found),
+                    Err(err) => warn!("Skipping unreadable maker note: {:?}", err),
+                }
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Olympus maker notes store the preview in their own ifd,
+    /// with offsets relative to the start of the maker note.
+    fn olympus_candidates(&mut self, base: u64) -> Result<Vec<(u64, u64)>> {
+        let mut header = [0_u8; 12];
+        self.r.seek(SeekFrom::Start(base))?;
+        self.r.read_exact(&mut header)?;
+
+        if !header.starts_with(b"OLYMPUS\0") {
+            return Ok(Vec::new());
+        }
+
+        // the maker note might use a different byte order than the file itself
+        let big_endian = std::mem::replace(&mut self.big_endian, &header[8..10] == b"MM");
+        let result = self.olympus_camera_settings(base);
+        self.big_endian = big_endian;
+
+        result
+    }
+
+    fn olympus_camera_settings(&mut self, base: u64) -> Result<Vec<(u64, u64)>> {
+        let (entries, _) = self.read_ifd(base + 12)?;
+
+        let Some(settings) = entries.iter().find(|entry| entry.tag == TAG_OLYMPUS_CAMERA_SETTINGS) else {
+            return Ok(Vec::new());
+        };
+
+        let (entries, _) = self.read_ifd(base + self.scalar(settings) as u64)?;
+
+        let get = |tag: u16| entries.iter().find(|entry| entry.tag == tag);
+
+        match (get(TAG_OLYMPUS_PREVIEW_OFFSET), get(TAG_OLYMPUS_PREVIEW_LENGTH)) {
+            (Some(start), Some(len)) => Ok(vec![(base + self.scalar(start) as u64, self.scalar(len) as u64)]),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Reads the dimensions of a baseline or progressive jpeg. Returns `None` if the data
+/// is not a jpeg or uses a compression that common decoders do not support.
+fn jpeg_dimensions<R: Read + Seek>(r: &mut R, offset: u64, len: u64) -> Result<Option<(u32, u32)>> {
+    if len < 4 {
+        return Ok(None);
+    }
+
+    r.seek(SeekFrom::Start(offset))?;
+
+    if r.read_u16::<BigEndian>()? != 0xffd8 {
+        return Ok(None);
+    }
+
+    let mut pos = 2;
+
+    while pos + 4 <= len {
+        let marker = r.read_u16::<BigEndian>()?;
+        let length = r.read_u16::<BigEndian>()? as u64;
+
+        match marker {
+            // baseline, extended sequential and progressive
+            0xffc0..=0xffc2 => {
+                let _precision = r.read_u8()?;
+                let height = r.read_u16::<BigEndian>()?;
+                let width = r.read_u16::<BigEndian>()?;
+                return Ok(Some((width as u32, height as u32)));
+            }
+
+            // lossless, hierarchical or arithmetic coding. Also stop at the start of scan.
+            0xffc3 | 0xffc5..=0xffc7 | 0xffc9..=0xffcb | 0xffcd..=0xffcf | 0xffda => return Ok(None),
+
+            marker if marker >> 8 != 0xff => return Ok(None),
+
+            _ => {
+                r.seek(SeekFrom::Current(length as i64 - 2))?;
+                pos += 2 + length;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+
+    use crate::raw::{find_preview, Preview};
+
+    /// A minimal jpeg header with the given dimensions
+    fn jpeg(sof: u8, width: u16, height: u16) -> Vec<u8> {
+        let mut buf = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, sof, 0, 8, 8];
+        buf.write_u16::<BigEndian>(height).unwrap();
+        buf.write_u16::<BigEndian>(width).unwrap();
+        buf.extend_from_slice(&[0xff, 0xd9]);
+        buf
+    }
+
+    /// Builds a little endian tiff file. Each ifd is given as a list of (tag, type, count, value),
+    /// they are placed at the start of the file and linked as a chain.
+    struct TiffBuilder {
+        buf: Vec<u8>,
+    }
+
+    impl TiffBuilder {
+        fn new(magic: u16) -> Self {
+            let mut buf = b"II".to_vec();
+            buf.write_u16::<LittleEndian>(magic).unwrap();
+            buf.write_u32::<LittleEndian>(0).unwrap();
+            Self { buf }
+        }
+
+        fn offset(&self) -> u32 {
+            self.buf.len() as u32
+        }
+
+        /// Appends some data and returns its offset
+        fn data(&mut self, data: &[u8]) -> u32 {
+            let offset = self.offset();
+            self.buf.extend_from_slice(data);
+            offset
+        }
+
+        /// Appends an ifd and returns its offset
+        fn ifd(&mut self, entries: &[(u16, u16, u32, u32)], next: u32) -> u32 {
+            let offset = self.offset();
+            self.buf.write_u16::<LittleEndian>(entries.len() as u16).unwrap();
+
+            for &(tag, typ, count, value) in entries {
+                self.buf.write_u16::<LittleEndian>(tag).unwrap();
+                self.buf.write_u16::<LittleEndian>(typ).unwrap();
+                self.buf.write_u32::<LittleEndian>(count).unwrap();
+                self.buf.write_u32::<LittleEndian>(value).unwrap();
+            }
+
+            self.buf.write_u32::<LittleEndian>(next).unwrap();
+            offset
+        }
+
+        fn build(mut self, first_ifd: u32) -> Vec<u8> {
+            LittleEndian::write_u32(&mut self.buf[4..8], first_ifd);
+            self.buf
+        }
+    }
+
+    #[test]
+    fn test_nef_preview_in_sub_ifd() -> anyhow::Result<()> {
+        let mut tiff = TiffBuilder::new(42);
+
+        let thumb = jpeg(0xc0, 160, 120);
+        let thumb_offset = tiff.data(&thumb);
+
+        let preview = jpeg(0xc0, 6000, 4000);
+        let preview_offset = tiff.data(&preview);
+
+        // the raw data itself, lossless compressed
+        let raw = jpeg(0xc3, 6048, 4024);
+        let raw_offset = tiff.data(&raw);
+
+        let sub_preview = tiff.ifd(&[(513, 4, 1, preview_offset), (514, 4, 1, preview.len() as u32)], 0);
+        let sub_raw = tiff.ifd(&[(254, 4, 1, 0), (259, 3, 1, 7), (273, 4, 1, raw_offset), (279, 4, 1, raw.len() as u32)], 0);
+        let sub_ifds = tiff.data(&[sub_preview.to_le_bytes(), sub_raw.to_le_bytes()].concat());
+
+        let ifd0 = tiff.ifd(&[(330, 4, 2, sub_ifds), (513, 4, 1, thumb_offset), (514, 4, 1, thumb.len() as u32)], 0);
+
+        let file = tiff.build(ifd0);
+        let found = find_preview(&mut Cursor::new(file))?;
+
+        let expected = Preview { offset: preview_offset as u64, len: preview.len() as u64, width: 6000, height: 4000 };
+        assert_eq!(found, Some(expected));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dng_preview_in_strip() -> anyhow::Result<()> {
+        let mut tiff = TiffBuilder::new(42);
+
+        let preview = jpeg(0xc0, 1024, 768);
+        let preview_offset = tiff.data(&preview);
+
+        let ifd0 = tiff.ifd(
+            &[(254, 4, 1, 1), (259, 3, 1, 7), (273, 4, 1, preview_offset), (279, 4, 1, preview.len() as u32)],
+            0,
+        );
+
+        let found = find_preview(&mut Cursor::new(tiff.build(ifd0)))?;
+        assert_eq!(found.map(|p| (p.offset, p.width)), Some((preview_offset as u64, 1024)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rw2_jpeg_from_raw() -> anyhow::Result<()> {
+        let mut tiff = TiffBuilder::new(0x55);
+
+        let preview = jpeg(0xc0, 5184, 3888);
+        let preview_offset = tiff.data(&preview);
+
+        let ifd0 = tiff.ifd(&[(0x2e, 7, preview.len() as u32, preview_offset)], 0);
+
+        let found = find_preview(&mut Cursor::new(tiff.build(ifd0)))?;
+        assert_eq!(found.map(|p| (p.offset, p.len)), Some((preview_offset as u64, preview.len() as u64)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orf_maker_note_preview() -> anyhow::Result<()> {
+        let preview = jpeg(0xc0, 3200, 2400);
+
+        // header, maker note ifd at 12, camera settings ifd at 30 and the preview at 60.
+        // Offsets in the maker note are relative to its start.
+        let mut maker_note = TiffBuilder { buf: b"OLYMPUS\0II\x03\0".to_vec() };
+        maker_note.ifd(&[(0x2020, 13, 1, 30)], 0);
+        maker_note.ifd(&[(0x101, 4, 1, 60), (0x102, 4, 1, preview.len() as u32)], 0);
+        maker_note.data(&preview);
+
+        let mut tiff = TiffBuilder::new(0x4f52);
+        let maker_note_offset = tiff.data(&maker_note.buf);
+
+        let exif = tiff.ifd(&[(37500, 7, maker_note.offset(), maker_note_offset)], 0);
+        let ifd0 = tiff.ifd(&[(34665, 4, 1, exif)], 0);
+
+        let found = find_preview(&mut Cursor::new(tiff.build(ifd0)))?;
+        assert_eq!(found.map(|p| (p.offset, p.width)), Some((maker_note_offset as u64 + 60, 3200)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skips_unreadable_candidates() -> anyhow::Result<()> {
+        let mut tiff = TiffBuilder::new(42);
+
+        let preview = jpeg(0xc0, 1024, 768);
+        let preview_offset = tiff.data(&preview);
+
+        // a preview, a maker note and a sub ifd that all point past the end of the file
+        let sub_ifd = tiff.ifd(&[(513, 4, 1, 100_000), (514, 4, 1, 1000)], 0);
+        let ifd0 = tiff.ifd(
+            &[
+                (330, 4, 1, sub_ifd),
+                (513, 4, 1, preview_offset),
+                (514, 4, 1, preview.len() as u32),
+                (37500, 7, 100, 200_000),
+            ],
+            300_000,
+        );
+
+        let found = find_preview(&mut Cursor::new(tiff.build(ifd0)))?;
+        assert_eq!(found.map(|p| (p.offset, p.width)), Some((preview_offset as u64, 1024)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skips_broken_sub_ifds() -> anyhow::Result<()> {
+        let mut tiff = TiffBuilder::new(42);
+
+        let preview = jpeg(0xc0, 1024, 768);
+        let preview_offset = tiff.data(&preview);
+
+        // the list of sub ifd offsets is cut off by the end of the file
+        let sub_ifds = tiff.data(&[0; 6]);
+        let ifd0 = tiff.ifd(
+            &[(330, 4, 64, sub_ifds), (513, 4, 1, preview_offset), (514, 4, 1, preview.len() as u32)],
+            0,
+        );
+
+        let found = find_preview(&mut Cursor::new(tiff.build(ifd0)))?;
+        assert_eq!(found.map(|p| (p.offset, p.width)), Some((preview_offset as u64, 1024)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raf_preview() -> anyhow::Result<()> {
+        let preview = jpeg(0xc0, 1920, 1280);
+
+        let mut file = b"FUJIFILMCCD-RAW 0201FF383501".to_vec();
+        file.resize(84, 0);
+        file.write_u32::<BigEndian>(100).unwrap();
+        file.write_u32::<BigEndian>(preview.len() as u32).unwrap();
+        file.resize(100, 0);
+        file.extend_from_slice(&preview);
+
+        let found = find_preview(&mut Cursor::new(file))?;
+        assert_eq!(found.map(|p| (p.offset, p.width, p.height)), Some((100, 1920, 1280)));
+
+        Ok(())
+    }
+}
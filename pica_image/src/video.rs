@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use byteorder::{BigEndian, ReadBytesExt};
+use chrono::{DateTime, Utc};
+use tracing::{debug, instrument};
+
+use crate::bmff::{Atom, AtomIter};
+use crate::MediaFileRef;
+
+// seconds between 1904-01-01, the epoch used in mp4 files, and the unix epoch
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+#[derive(Debug)]
+pub struct VideoInfo {
+    pub duration: Option<Duration>,
+    pub timestamp: Option<DateTime<Utc>>,
+
+    // dimensions of the video track, already rotated for display
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parses duration, creation time and dimensions from the header of a mp4 or mov file.
+/// Returns `None` if the file is not an iso media file or does not contain a video track.
+#[instrument(skip_all, fields(? path))]
+pub fn parse_video_info(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Option<VideoInfo>> {
+    let mut fp = BufReader::new(File::open(path)?);
+    read_video_info(&mut fp)
+}
+
+pub fn read_video_info(mut fp: impl Read + Seek) -> Result<Option<VideoInfo>> {
+    let mut iter = AtomIter::new(&mut fp);
+
+    // check that this looks like an iso media file
+    match iter.next() {
+        Ok(Some(atom)) if matches!(&atom.name, b"ftyp" | b"wide" | b"free" | b"mdat" | b"moov") => (),
+        _ => return Ok(None),
+    }
+
+    let mut iter = AtomIter::new(&mut fp);
+
+    while let Some(atom) = iter.next()? {
+        if &atom.name == b"moov" {
+            return parse_moov(atom);
+        }
+    }
+
+    Ok(None)
+}
+
+fn parse_moov<R: Read + Seek>(moov: Atom<R>) -> Result<Option<VideoInfo>> {
+    let mut header = None;
+    let mut dimensions = None;
+
+    let mut iter = moov.into_iter(0);
+
+    while let Some(atom) = iter.next()? {
+        match &atom.name {
+            b"mvhd" => header = Some(parse_mvhd(atom)?),
+
+            b"trak" if dimensions.is_none() => {
+                let mut iter = atom.into_iter(0);
+
+                while let Some(atom) = iter.next()? {
+                    if &atom.name == b"tkhd" {
+                        // audio tracks have a size of zero
+                        dimensions = parse_tkhd(atom)?.filter(|&(width, height)| width > 0 && height > 0);
+                        break;
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    let Some((width, height)) = dimensions else {
+        return Ok(None);
+    };
+
+    let (timestamp, duration) = header.unwrap_or_default();
+
+    Ok(Some(VideoInfo {
+        duration,
+        timestamp,
+        width,
+        height,
+    }))
+}
+
+fn parse_mvhd<R: Read + Seek>(atom: Atom<R>) -> Result<(Option<DateTime<Utc>>, Option<Duration>)> {
+    let r = atom.payload;
+    r.seek(SeekFrom::Start(atom.payload_start))?;
+
+    let version = r.read_u8()?;
+    r.seek(SeekFrom::Current(3))?;
+
+    let (created, timescale, duration) = match version {
+        1 => {
+            let created = r.read_u64::<BigEndian>()?;
+            let _modified = r.read_u64::<BigEndian>()?;
+            (created, r.read_u32::<BigEndian>()?, r.read_u64::<BigEndian>()?)
+        }
+
+        _ => {
+            let created = r.read_u32::<BigEndian>()? as u64;
+            let _modified = r.read_u32::<BigEndian>()?;
+            (created, r.read_u32::<BigEndian>()?, r.read_u32::<BigEndian>()? as u64)
+        }
+    };
+
+    // a creation time of zero means 'unknown'
+    let timestamp = (created > 0)
+        .then(|| DateTime::from_timestamp(created as i64 - MP4_EPOCH_OFFSET, 0))
+        .flatten();
+
+    let duration = (timescale > 0).then(|| Duration::from_secs_f64(duration as f64 / timescale as f64));
+
+    debug!("Video created at {:?} with duration {:?}", timestamp, duration);
+
+    Ok((timestamp, duration))
+}
+
+fn parse_tkhd<R: Read + Seek>(atom: Atom<R>) -> Result<Option<(u32, u32)>> {
+    let r = atom.payload;
+    r.seek(SeekFrom::Start(atom.payload_start))?;
+
+    // skip times, track id and duration
+    let version = r.read_u8()?;
+    let skip = match version {
+        1 => 3 + 8 + 8 + 4 + 4 + 8,
+        _ => 3 + 4 + 4 + 4 + 4 + 4,
+    };
+
+    // skip reserved, layer, alternate group, volume
+    r.seek(SeekFrom::Current(skip + 8 + 2 + 2 + 2 + 2))?;
+
+    // the transformation matrix, we only look at the rotation part
+    let a = r.read_i32::<BigEndian>()?;
+    let b = r.read_i32::<BigEndian>()?;
+    r.seek(SeekFrom::Current(7 * 4))?;
+
+    // fixed point 16.16 values
+    let width = r.read_u32::<BigEndian>()? >> 16;
+    let height = r.read_u32::<BigEndian>()? >> 16;
+
+    // rotated by 90 or 270 degrees
+    let transposed = a == 0 && b != 0;
+
+    match transposed {
+        true => Ok(Some((height, width))),
+        false => Ok(Some((width, height))),
+    }
+}
+
+/// Extracts a frame from the video to use as a poster image.
+/// This requires `ffmpeg` on the PATH.
+#[instrument(skip_all, fields(? path))]
+pub fn extract_poster_frame(path: &Path) -> Result<MediaFileRef> {
+    let jpeg = tempfile::Builder::new().suffix(".jpg").tempfile()?.into_temp_path();
+
+    // try to skip a black first frame, fall back to the first frame for very short videos
+    for seek in ["1", "0"] {
+        let res = Command::new("ffmpeg")
+            .arg("-nostdin")
+            .arg("-loglevel").arg("error")
+            .arg("-ss").arg(seek)
+            .arg("-i").arg(path)
+            .arg("-frames:v").arg("1")
+            .arg("-q:v").arg("2")
+            .arg("-f").arg("image2")
+            .arg("-y")
+            .arg(&jpeg)
+            .output()?;
+
+        if !res.status.success() {
+            return Err(anyhow!("ffmpeg failed with status {:?}", res.status.code()));
+        }
+
+        if jpeg.metadata()?.len() > 0 {
+            return Ok(MediaFileRef::Temporary(jpeg));
+        }
+    }
+
+    Err(anyhow!("no frame found in video {:?}", path))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use crate::video::read_video_info;
+
+    fn atom(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.write_u32::<BigEndian>(payload.len() as u32 + 8).unwrap();
+        buf.write_all(name).unwrap();
+        buf.write_all(payload).unwrap();
+        buf
+    }
+
+    fn mvhd(created: u32, timescale: u32, duration: u32) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        buf.write_u32::<BigEndian>(created).unwrap();
+        buf.write_u32::<BigEndian>(created).unwrap();
+        buf.write_u32::<BigEndian>(timescale).unwrap();
+        buf.write_u32::<BigEndian>(duration).unwrap();
+        buf.resize(100, 0);
+        atom(b"mvhd", &buf)
+    }
+
+    fn tkhd(matrix: [i32; 2], width: u32, height: u32) -> Vec<u8> {
+        let mut buf = vec![0; 4 + 20 + 8 + 8];
+        buf.write_i32::<BigEndian>(matrix[0] << 16).unwrap();
+        buf.write_i32::<BigEndian>(matrix[1] << 16).unwrap();
+        buf.resize(buf.len() + 7 * 4, 0);
+        buf.write_u32::<BigEndian>(width << 16).unwrap();
+        buf.write_u32::<BigEndian>(height << 16).unwrap();
+        atom(b"tkhd", &buf)
+    }
+
+    fn video(matrix: [i32; 2]) -> Vec<u8> {
+        let audio = atom(b"trak", &tkhd([1, 0], 0, 0));
+        let video = atom(b"trak", &tkhd(matrix, 1920, 1080));
+        let moov = atom(b"moov", &[mvhd(3_800_000_000, 600, 6000), audio, video].concat());
+        [atom(b"ftyp", b"isom"), moov].concat()
+    }
+
+    #[test]
+    fn test_read_video_info() -> anyhow::Result<()> {
+        let info = read_video_info(Cursor::new(video([1, 0])))?.expect("video info");
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert_eq!(info.duration.map(|d| d.as_secs()), Some(10));
+        assert_eq!(info.timestamp.map(|ts| ts.to_rfc3339()).as_deref(), Some("2024-05-31T11:33:20+00:00"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_video_info_rotated() -> anyhow::Result<()> {
+        let info = read_video_info(Cursor::new(video([0, 1])))?.expect("video info");
+        assert_eq!((info.width, info.height), (1080, 1920));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_video_info_no_iso_media() -> anyhow::Result<()> {
+        assert!(read_video_info(Cursor::new(b"RIFF\0\0\0\0AVI LIST".to_vec()))?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+-- modification time of the file at the time it was indexed. This is used to check
+-- if a cached media item is still up to date with the file on disk.
+ALTER TABLE pica_media_cache ADD COLUMN mtime timestamp;
@@ -0,0 +1,2 @@
+-- duration of video files in seconds, null for images
+ALTER TABLE pica_media_cache ADD COLUMN duration float8;
@@ -0,0 +1,6 @@
+-- location of a motion photo video embedded into the media file itself
+ALTER TABLE pica_media_cache ADD COLUMN motion_offset INT8;
+ALTER TABLE pica_media_cache ADD COLUMN motion_length INT8;
+
+-- relative path of a video file that belongs to the media item, e.g. of a live photo
+ALTER TABLE pica_media_cache ADD COLUMN motion_relpath blob;
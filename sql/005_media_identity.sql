@@ -0,0 +1,3 @@
+-- how the id of the media item was derived, either 'path' or 'content'.
+-- Items indexed before this column existed use path based ids.
+ALTER TABLE pica_media_cache ADD COLUMN identity text;
@@ -0,0 +1,5 @@
+-- sha1 of the complete file, identical files have the same content hash
+ALTER TABLE pica_media_cache ADD COLUMN content_hash blob;
+
+-- perceptual hash (dhash) of the image, similar images differ in only a few bits
+ALTER TABLE pica_media_cache ADD COLUMN perceptual_hash INT8;
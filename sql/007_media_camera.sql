@@ -0,0 +1,3 @@
+-- make and model of the camera. This is an empty string if the file does not name a camera
+-- and null if the item was indexed before this column existed.
+ALTER TABLE pica_media_cache ADD COLUMN camera text;
@@ -0,0 +1,30 @@
+CREATE TABLE pica_share
+(
+    -- the random token identifying this share in its link
+    token         text PRIMARY KEY,
+
+    -- name of the user that created the share. The share never grants
+    -- access to more than this user can access.
+    owner         text      NOT NULL,
+
+    -- title to show to the recipient
+    name          text      NOT NULL,
+
+    -- directory of the shared album, null if the share is a selection of media items
+    album_relpath blob,
+
+    created       timestamp NOT NULL,
+    expires       timestamp,
+
+    -- bcrypt hash of the password required to open the share, if any
+    passwd        text
+);
+
+-- the media items of a share that is not an album
+CREATE TABLE pica_share_media
+(
+    share text    NOT NULL REFERENCES pica_share (token) ON DELETE CASCADE,
+    media integer NOT NULL,
+
+    PRIMARY KEY (share, media)
+);
@@ -0,0 +1,27 @@
+CREATE TABLE pica_album
+(
+    -- id of the album, does not collide with the ids of directory albums
+    id          integer PRIMARY KEY,
+
+    -- name of the user that created the album. Only they can see and change it.
+    owner       text      NOT NULL,
+
+    name        text      NOT NULL,
+    description text,
+
+    -- the media item to show as preview of the album, if chosen by the user
+    cover       integer,
+
+    created     timestamp NOT NULL
+);
+
+-- the media items of a user created album in the chosen order. Items are not
+-- removed when their file disappears, they come back once it is indexed again.
+CREATE TABLE pica_album_media
+(
+    album    integer NOT NULL REFERENCES pica_album (id) ON DELETE CASCADE,
+    media    integer NOT NULL,
+    position integer NOT NULL,
+
+    PRIMARY KEY (album, media)
+);
@@ -0,0 +1,17 @@
+-- marks a user put on a media item, only visible to that user.
+-- A missing row means that no flag is set.
+CREATE TABLE pica_media_flags
+(
+    user     text    NOT NULL,
+    media    integer NOT NULL,
+
+    favorite boolean NOT NULL DEFAULT false,
+    hidden   boolean NOT NULL DEFAULT false,
+
+    -- star rating from 1 to 5, null if not rated
+    rating   INT2,
+
+    CHECK (rating BETWEEN 1 AND 5),
+
+    PRIMARY KEY (user, media)
+);
@@ -0,0 +1,10 @@
+-- metadata from the xmp data embedded into the file or from its sidecar file.
+-- Keywords are separated by newlines. They are an empty string if there are no
+-- keywords and null if the item was indexed before these columns existed.
+ALTER TABLE pica_media_cache ADD COLUMN xmp_rating INT2;
+ALTER TABLE pica_media_cache ADD COLUMN xmp_label text;
+ALTER TABLE pica_media_cache ADD COLUMN xmp_keywords text;
+ALTER TABLE pica_media_cache ADD COLUMN xmp_description text;
+
+-- modification time of the xmp sidecar file at the time it was read
+ALTER TABLE pica_media_cache ADD COLUMN xmp_mtime timestamp;
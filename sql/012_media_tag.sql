@@ -0,0 +1,12 @@
+-- tags of media items, shared between all users that can access an item.
+-- Tags form a hierarchy separated by slashes, e.g. 'people/anna'. An item
+-- tagged with 'people/anna' is also found when looking for 'people'.
+CREATE TABLE pica_media_tag
+(
+    media integer NOT NULL,
+    tag   text    NOT NULL,
+
+    PRIMARY KEY (media, tag)
+);
+
+CREATE INDEX pica_media_tag__tag ON pica_media_tag (tag);
@@ -0,0 +1,19 @@
+-- manual corrections of the parsed metadata of a media item, e.g. because the
+-- clock of the camera was wrong. They are applied on top of pica_media_cache.
+CREATE TABLE pica_media_correction
+(
+    media     integer PRIMARY KEY,
+
+    -- replaces the parsed timestamp
+    timestamp timestamp,
+
+    -- moves the parsed timestamp by this many seconds
+    shift     integer,
+
+    -- replaces the parsed location
+    latitude  real,
+    longitude real,
+
+    CHECK (timestamp IS NULL OR shift IS NULL),
+    CHECK ((latitude IS NULL) = (longitude IS NULL))
+);
@@ -0,0 +1,36 @@
+-- gpx tracks used to infer the location of media items without gps data.
+CREATE TABLE pica_track
+(
+    id     integer PRIMARY KEY AUTOINCREMENT,
+
+    -- file name of the track
+    name   text      NOT NULL,
+
+    -- source and path of a gpx file found within a source.
+    -- Both are NULL for uploaded tracks.
+    source text,
+    path   blob,
+
+    -- modification time of the file, or time of the upload
+    mtime  timestamp NOT NULL,
+
+    -- the user that uploaded the track
+    owner  text,
+
+    CHECK ((source IS NULL) == (path IS NULL)),
+    CHECK ((source IS NULL) != (owner IS NULL)),
+
+    UNIQUE (source, path)
+);
+
+CREATE TABLE pica_track_point
+(
+    track     integer   NOT NULL REFERENCES pica_track (id) ON DELETE CASCADE,
+    timestamp timestamp NOT NULL,
+    latitude  real      NOT NULL,
+    longitude real      NOT NULL,
+
+    CHECK (latitude BETWEEN -90 AND 90 AND longitude BETWEEN -180 AND 180)
+);
+
+CREATE INDEX pica_track_point__track ON pica_track_point (track);
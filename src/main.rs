@@ -18,11 +18,12 @@ use tracing_subscriber::{EnvFilter, Layer};
 
 use crate::pica::accessor::{MediaAccessor, Storage};
 use crate::pica::config::ImageCodecConfig;
+use crate::pica::geotag::Geotagger;
 use crate::pica::index::{Indexer, Scanner};
 use crate::pica::queue::ScanQueue;
 use crate::pica::scale::{ImageType, MediaScaler};
 use crate::pica::store::MediaStore;
-use crate::pica::{accessor, album, scale};
+use crate::pica::{accessor, album, db, index, scale};
 
 pub mod pica;
 pub mod pica_web;
@@ -92,14 +93,56 @@ async fn main() -> Result<()> {
 
     let queue = Arc::new(Mutex::new(ScanQueue::default()));
 
+    let store = MediaStore::empty();
+
+    // infers locations from gpx tracks for sources that have it enabled
+    let geotagger = Geotagger::new(&config.sources);
+    geotagger.reload(&db).await?;
+
+    // manual corrections are applied on top of the cached items
+    let corrections = {
+        let mut tx = db.begin().await?;
+        db::correction::list_corrections(&mut tx).await?
+    };
+
     for source in &config.sources {
-        info!("Starting scanner for source {:?}", source.name);
-        let scanner = Scanner::new(&source.path, queue.clone(), source.name.as_str());
+        // ids of cached items might have been derived differently before
+        index::migrate_identity(&db, &source.name.as_str().into(), &source.path, source.identity).await?;
+
+        // warm up the store with what we have indexed previously
+        let (cached, missing) =
+            index::load_cached(&db, &source.name.as_str().into(), &source.path, source.identity).await?;
+        info!("Loaded {} cached media items for source {:?}", cached.len(), source.name);
 
-        tokio::task::spawn(scanner_loop(
-            scanner,
-            Duration::from_secs(config.scan_interval_in_seconds.get() as _),
-        ));
+        info!("Starting scanner for source {:?}", source.name);
+        let mut scanner = Scanner::new(&source.path, queue.clone(), source.name.as_str(), source.identity);
+        // incomplete items are indexed again to extract the missing information
+        scanner.assume_known(cached.iter().filter(|cached| cached.complete));
+        scanner.assume_missing(missing);
+
+        for cached in cached {
+            let item = match corrections.get(&cached.item.id) {
+                Some(correction) => cached.item.corrected(correction)?,
+                None => cached.item,
+            };
+
+            let item = geotagger.apply(item)?;
+
+            store.add(item).await;
+        }
+
+        let interval = source.rescan_interval(config.scan_interval_in_seconds);
+
+        if source.watch {
+            tokio::task::spawn(scanner.watch(interval));
+        } else {
+            tokio::task::spawn(scanner_loop(scanner, interval));
+        }
+
+        if source.geotag.is_some() {
+            let watch = geotagger.clone().watch(db.clone(), store.clone(), source.clone(), interval);
+            tokio::task::spawn(watch);
+        }
     }
 
     let sizes = accessor::Sizes {
@@ -126,7 +169,6 @@ async fn main() -> Result<()> {
         .map(|s| (s.name.clone(), s.path.clone()))
         .collect();
 
-    let store = MediaStore::empty();
     let scaler = MediaScaler::new(scaler_options);
     let media = MediaAccessor::new(storage, scaler, sizes, sources);
 
@@ -138,6 +180,7 @@ async fn main() -> Result<()> {
             queue.clone(),
             store.clone(),
             (!config.lazy_thumbs).then(|| media.clone()),
+            geotagger.clone(),
         );
         tokio::task::spawn(indexer.run());
     }
@@ -152,8 +195,10 @@ async fn main() -> Result<()> {
             strip_title: config.album_config.strip_title,
         },
         store,
+        geotagger,
         users,
         db,
+        scan_queue: queue,
     };
 
     pica_web::serve(opts).await?;
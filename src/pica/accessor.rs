@@ -7,7 +7,7 @@ use tokio::task::spawn_blocking;
 use tracing::{debug_span, instrument, Instrument};
 
 use crate::pica::scale::{Image, MediaScaler};
-use crate::pica::{db, MediaId, MediaItem};
+use crate::pica::{db, MediaId, MediaItem, Motion};
 
 #[derive(Clone)]
 pub struct MediaAccessor {
@@ -41,6 +41,19 @@ impl MediaAccessor {
         Ok(root.join(item.relpath.as_ref()))
     }
 
+    /// Path to the video file attached to a media item, if it is not embedded in the file itself.
+    pub fn motion(&self, item: &MediaItem) -> Result<Option<PathBuf>> {
+        let Some(Motion::Sidecar(relpath)) = &item.motion else {
+            return Ok(None);
+        };
+
+        let root = self.sources
+            .get(item.source.as_str())
+            .ok_or_else(|| anyhow!("source {:?} not found", item.source))?;
+
+        Ok(Some(root.join(relpath.as_ref())))
+    }
+
     pub async fn thumb(&self, item: &MediaItem) -> Result<Image> {
         self.scaled(item, self.sizes.thumb)
             .instrument(debug_span!("scale", size = self.sizes.thumb))
@@ -84,7 +97,7 @@ impl MediaAccessor {
         let path = self.full(media)?;
 
         // extract an image we can process from the media file.
-        let path = spawn_blocking(|| pica_image::get(path)).await??;
+        let path = spawn_blocking(move || pica_image::get_for_size(path, size)).await??;
 
         let image = self.scaler.scaled(path, size).await?;
 
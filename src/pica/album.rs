@@ -1,6 +1,8 @@
 use crate::info;
 use arcstr::ArcStr;
+use chrono::NaiveDate;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Reverse;
 use std::collections::HashMap;
@@ -9,7 +11,52 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::instrument;
 
-use crate::pica::{Album, AlbumId, AlbumInfo, MediaItem};
+use crate::pica::db::album::CustomAlbum;
+use crate::pica::{tag, Album, AlbumId, AlbumInfo, MediaId, MediaItem};
+
+// the first byte of the ids of the different kinds of albums
+const DIRECTORY_PREFIX: u8 = 0x7f;
+const CUSTOM_PREFIX: u8 = 0x7e;
+const TAG_PREFIX: u8 = 0x7d;
+const PLACE_PREFIX: u8 = 0x7c;
+const TRIP_PREFIX: u8 = 0x7b;
+const DAY_PREFIX: u8 = 0x7a;
+
+/// The different kinds of albums, told apart by the first byte of their id.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    // a directory on the file system
+    Directory,
+
+    // created by a user
+    Custom,
+
+    // all items with a tag
+    Tag,
+
+    // all items taken in a country or city
+    Place,
+
+    // a series of days away from home
+    Trip,
+
+    // items taken close together on a single day
+    Day,
+}
+
+impl Kind {
+    pub fn of(id: AlbumId) -> Kind {
+        match id.as_bytes()[0] {
+            CUSTOM_PREFIX => Kind::Custom,
+            TAG_PREFIX => Kind::Tag,
+            PLACE_PREFIX => Kind::Place,
+            TRIP_PREFIX => Kind::Trip,
+            DAY_PREFIX => Kind::Day,
+            _ => Kind::Directory,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Config {
@@ -29,16 +76,7 @@ pub fn by_directory(conf: &Config, items: impl IntoIterator<Item = MediaItem>) -
     info!("Path: {:?}", conf.classify_as_album);
 
     for item in items {
-        let Some(parent) = item.relpath.parent() else {
-            continue;
-        };
-
-        let Some(relpath) = parent.ancestors().find(|path| {
-            path.file_name()
-                .map(|path| path.as_bytes())
-                .map(|name| conf.classify_as_album.is_match_at(name, 0))
-                .unwrap_or_default()
-        }) else {
+        let Some(relpath) = directory_of(conf, &item) else {
             continue;
         };
 
@@ -52,6 +90,7 @@ pub fn by_directory(conf: &Config, items: impl IntoIterator<Item = MediaItem>) -
             let info = AlbumInfo {
                 id: album_id_for_relpath(relpath),
                 name: ArcStr::from(name),
+                description: None,
                 timestamp: item.info.timestamp,
             };
 
@@ -82,6 +121,236 @@ pub fn by_directory(conf: &Config, items: impl IntoIterator<Item = MediaItem>) -
     albums
 }
 
+/// The directory of the album the item belongs to, if any. This is the closest
+/// parent directory that is classified as an album.
+pub fn directory_of<'a>(conf: &Config, item: &'a MediaItem) -> Option<&'a Path> {
+    item.relpath.parent()?.ancestors().find(|path| {
+        path.file_name()
+            .map(|path| path.as_bytes())
+            .map(|name| conf.classify_as_album.is_match_at(name, 0))
+            .unwrap_or_default()
+    })
+}
+
+/// Builds a user created album from the given media items, keeping the order
+/// chosen by the user. Returns None if none of the albums items are available.
+pub fn custom(album: &CustomAlbum, items: &HashMap<MediaId, MediaItem>) -> Option<Album> {
+    let items = album.items.iter().filter_map(|id| items.get(id)).cloned().collect_vec();
+
+    // fall back to the first item if the chosen cover is not available
+    let cover = album
+        .cover
+        .and_then(|cover| items.iter().find(|item| item.id == cover))
+        .or_else(|| items.first())?
+        .clone();
+
+    let info = AlbumInfo {
+        id: album.id,
+        name: ArcStr::from(album.name.as_str()),
+        description: album.description.as_deref().map(ArcStr::from),
+        timestamp: items.iter().map(|item| item.info.timestamp).max()?,
+    };
+
+    Some(Album {
+        info,
+        relpath: None,
+        items,
+        cover,
+    })
+}
+
+/// Builds a virtual album of all items tagged with `tag` or one of its children.
+/// Returns None if no item has this tag.
+pub fn by_tag(tag: &str, items: Vec<MediaItem>, tags: &HashMap<MediaId, Vec<String>>) -> Option<Album> {
+    let items = items
+        .into_iter()
+        .filter(|item| {
+            let item_tags = tags.get(&item.id).map(Vec::as_slice).unwrap_or_default();
+            item_tags.iter().any(|item_tag| tag::is_within(item_tag, tag))
+        })
+        .collect_vec();
+
+    tag_album(tag, items)
+}
+
+/// Builds a virtual album for every tag of the items, and for all of their parents.
+#[instrument(skip_all)]
+pub fn by_tags(items: impl IntoIterator<Item = MediaItem>, tags: &HashMap<MediaId, Vec<String>>) -> Vec<Album> {
+    let mut albums = HashMap::<&str, Vec<MediaItem>>::new();
+
+    for item in items {
+        let item_tags = tags.get(&item.id).map(Vec::as_slice).unwrap_or_default();
+
+        for tag in item_tags.iter().flat_map(|tag| tag::with_parents(tag)).unique() {
+            albums.entry(tag).or_default().push(item.clone());
+        }
+    }
+
+    albums.into_iter().filter_map(|(tag, items)| tag_album(tag, items)).collect()
+}
+
+fn tag_album(tag: &str, mut items: Vec<MediaItem>) -> Option<Album> {
+    items.sort_by_key(|item| Reverse(item.info.timestamp));
+
+    let cover = items.first()?.clone();
+
+    let info = AlbumInfo {
+        id: tag_album_id(tag),
+        name: ArcStr::from(tag),
+        description: None,
+        timestamp: cover.info.timestamp,
+    };
+
+    Some(Album {
+        info,
+        relpath: None,
+        items,
+        cover,
+    })
+}
+
+/// A country, or a city within a country, that media items were taken in.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Place {
+    pub country: ArcStr,
+
+    // region and name of a city, both none for a whole country
+    pub region: Option<ArcStr>,
+    pub city: Option<ArcStr>,
+}
+
+impl Place {
+    pub fn album_id(&self) -> AlbumId {
+        let mut hash = sha1_smol::Sha1::new();
+
+        for value in [Some(&self.country), self.region.as_ref(), self.city.as_ref()] {
+            hash.update(value.map(ArcStr::as_bytes).unwrap_or_default());
+            hash.update(&[0]);
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash.digest().bytes()[..8]);
+        bytes[0] = PLACE_PREFIX;
+
+        AlbumId::from(bytes)
+    }
+}
+
+/// Builds a virtual album for every country and city the items were taken in.
+/// Items without a known city are left out.
+#[instrument(skip_all)]
+pub fn by_place(items: impl IntoIterator<Item = MediaItem>) -> Vec<(Place, Album)> {
+    let mut places = HashMap::<Place, Vec<MediaItem>>::new();
+
+    for item in items {
+        let Some(city) = item.location.as_ref().and_then(|location| location.city.as_ref()) else {
+            continue;
+        };
+
+        let country = Place {
+            country: city.country.clone(),
+            region: None,
+            city: None,
+        };
+
+        let city = Place {
+            country: city.country.clone(),
+            region: city.region.clone(),
+            city: Some(city.name.clone()),
+        };
+
+        places.entry(country).or_default().push(item.clone());
+        places.entry(city).or_default().push(item);
+    }
+
+    places
+        .into_iter()
+        .map(|(place, mut items)| {
+            items.sort_by_key(|item| Reverse(item.info.timestamp));
+
+            let cover = items[0].clone();
+
+            // a city is described by where it is
+            let description = place.city.as_ref().map(|_| {
+                let country = Some(&place.country);
+                ArcStr::from(place.region.as_ref().into_iter().chain(country).join(", "))
+            });
+
+            let info = AlbumInfo {
+                id: place.album_id(),
+                name: place.city.clone().unwrap_or_else(|| place.country.clone()),
+                description,
+                timestamp: cover.info.timestamp,
+            };
+
+            let album = Album {
+                info,
+                relpath: None,
+                items,
+                cover,
+            };
+
+            (place, album)
+        })
+        .collect()
+}
+
+/// Checks if the id belongs to a user created album
+pub fn is_custom(id: AlbumId) -> bool {
+    id.as_bytes()[0] == CUSTOM_PREFIX
+}
+
+/// Creates a random id for a new user created album.
+pub fn new_custom_album_id() -> AlbumId {
+    let mut bytes = rand::random::<[u8; 8]>();
+    bytes[0] = CUSTOM_PREFIX;
+
+    AlbumId::from(bytes)
+}
+
+/// Checks if the id belongs to the virtual album of a tag
+pub fn is_tag(id: AlbumId) -> bool {
+    id.as_bytes()[0] == TAG_PREFIX
+}
+
+pub fn tag_album_id(tag: &str) -> AlbumId {
+    let hash = sha1_smol::Sha1::from(tag.as_bytes()).digest().bytes();
+
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    bytes[0] = TAG_PREFIX;
+
+    AlbumId::from(bytes)
+}
+
+/// Checks if the id belongs to the virtual album of a country or city
+pub fn is_place(id: AlbumId) -> bool {
+    id.as_bytes()[0] == PLACE_PREFIX
+}
+
+/// Checks if the id belongs to the virtual album of a trip or a day
+pub fn is_event(id: AlbumId) -> bool {
+    matches!(Kind::of(id), Kind::Trip | Kind::Day)
+}
+
+/// The id of an event album, derived from the date it starts on, so that it does not change
+/// if items are added to the event. Events starting on the same date are numbered.
+pub fn event_album_id(kind: Kind, start: NaiveDate, ordinal: usize) -> AlbumId {
+    let prefix = match kind {
+        Kind::Trip => TRIP_PREFIX,
+        _ => DAY_PREFIX,
+    };
+
+    let key = format!("{}/{}", start, ordinal);
+    let hash = sha1_smol::Sha1::from(key.as_bytes()).digest().bytes();
+
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    bytes[0] = prefix;
+
+    AlbumId::from(bytes)
+}
+
 fn cleanup_album_title<'a>(config: &Config, title: &'a str) -> Cow<'a, str> {
     match &config.strip_title {
         None => title.into(),
@@ -89,12 +358,12 @@ fn cleanup_album_title<'a>(config: &Config, title: &'a str) -> Cow<'a, str> {
     }
 }
 
-fn album_id_for_relpath(path: &Path) -> AlbumId {
+pub fn album_id_for_relpath(path: &Path) -> AlbumId {
     let hash = sha1_smol::Sha1::from(path.as_os_str().as_bytes()).digest().bytes();
 
     let mut bytes = [0u8; 8];
     bytes.copy_from_slice(&hash[..8]);
-    bytes[0] = 0x7f;
+    bytes[0] = DIRECTORY_PREFIX;
 
     AlbumId::from(bytes)
 }
@@ -1,11 +1,14 @@
 use std::fs::File;
 use std::num::{NonZeroU32, NonZeroU8};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Result;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer};
 
+use crate::pica::Identity;
+
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PicaConfig {
@@ -47,6 +50,44 @@ pub struct SourceConfig {
 
     /// List of users that can access this source
     pub access: Vec<String>,
+
+    /// Watch the source for changes (using inotify on linux). New files are picked up
+    /// immediately and full scans are only done every `rescanIntervalInSeconds`.
+    #[serde(default)]
+    pub watch: bool,
+
+    /// Time between two full scans of this source. Defaults to `scanIntervalInSeconds`
+    /// or to one hour if the source is watched.
+    #[serde(default)]
+    pub rescan_interval_in_seconds: Option<NonZeroU32>,
+
+    /// How media ids are derived. With `content`, ids are derived from the file content
+    /// and stay the same if a file is renamed or moved within the source.
+    #[serde(default)]
+    pub identity: Identity,
+
+    /// Write ratings, tags and corrected dates and locations to xmp sidecar files next to
+    /// the media files, e.g. `IMG_1234.jpg.xmp`, so other tools can see them. Media files
+    /// are never modified. Only the ratings of the first user in `access` are written.
+    #[serde(default)]
+    pub write_xmp_sidecars: bool,
+
+    /// Infer the location of media items without gps data from gpx tracks. Tracks are
+    /// read from `.gpx` files within the source and from tracks uploaded by its users.
+    #[serde(default)]
+    pub geotag: Option<GeotagConfig>,
+}
+
+impl SourceConfig {
+    pub fn rescan_interval(&self, scan_interval_in_seconds: NonZeroU32) -> Duration {
+        let default = match self.watch {
+            true => 3600,
+            false => scan_interval_in_seconds.get(),
+        };
+
+        let seconds = self.rescan_interval_in_seconds.map(NonZeroU32::get).unwrap_or(default);
+        Duration::from_secs(seconds as _)
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -67,6 +108,19 @@ pub enum ImageCodecConfig {
     Jpeg,
 }
 
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeotagConfig {
+    /// Seconds to add to the timestamp of a media item to get the time of the gpx track,
+    /// which is in UTC. For a camera set to central european summer time this is `-7200`.
+    #[serde(default)]
+    pub camera_offset_in_seconds: i64,
+
+    /// Maximum time between a media item and the track points used to locate it.
+    #[serde(default = "max_gap_in_seconds_default")]
+    pub max_gap_in_seconds: u32,
+}
+
 #[derive(Clone, Deserialize)]
 #[serde(rename_all="camelCase")]
 pub struct AlbumConfig {
@@ -87,6 +141,10 @@ fn allow_access_over_http_default() -> bool {
     true
 }
 
+fn max_gap_in_seconds_default() -> u32 {
+    300
+}
+
 fn deserialize_bytes_regex<'de, D: Deserializer<'de>>(deserialize: D) -> Result<regex::bytes::Regex, D::Error> {
     let pattern = String::deserialize(deserialize)?;
     let regex = regex::bytes::Regex::new(&pattern).map_err(|err| {
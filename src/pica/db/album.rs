@@ -0,0 +1,120 @@
+use std::ops::DerefMut;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+
+use crate::pica::{AlbumId, MediaId};
+
+/// An album a user put together by hand.
+#[derive(Clone, Debug)]
+pub struct CustomAlbum {
+    pub id: AlbumId,
+    pub owner: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub cover: Option<MediaId>,
+
+    // in the order chosen by the user
+    pub items: Vec<MediaId>,
+
+    pub created: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AlbumRow {
+    id: AlbumId,
+    owner: String,
+    name: String,
+    description: Option<String>,
+    cover: Option<MediaId>,
+    created: DateTime<Utc>,
+}
+
+/// Stores a new album or updates an existing one, including its items.
+pub async fn store_album(tx: &mut Transaction<'_, Sqlite>, album: &CustomAlbum) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO pica_album (id, owner, name, description, cover, created) VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET name=excluded.name, description=excluded.description, cover=excluded.cover",
+    )
+    .bind(album.id)
+    .bind(&album.owner)
+    .bind(&album.name)
+    .bind(&album.description)
+    .bind(album.cover)
+    .bind(album.created)
+    .execute(tx.deref_mut())
+    .await?;
+
+    sqlx::query("DELETE FROM pica_album_media WHERE album=?")
+        .bind(album.id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    for (position, id) in album.items.iter().enumerate() {
+        sqlx::query("INSERT INTO pica_album_media (album, media, position) VALUES (?, ?, ?)")
+            .bind(album.id)
+            .bind(id)
+            .bind(position as i64)
+            .execute(tx.deref_mut())
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn read_album(tx: &mut Transaction<'_, Sqlite>, id: AlbumId, owner: &str) -> Result<Option<CustomAlbum>> {
+    let row: Option<AlbumRow> = sqlx::query_as("SELECT * FROM pica_album WHERE id=? AND owner=?")
+        .bind(id)
+        .bind(owner)
+        .fetch_optional(tx.deref_mut())
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some(album_from_row(tx, row).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Lists all albums created by the given user.
+pub async fn list_albums(tx: &mut Transaction<'_, Sqlite>, owner: &str) -> Result<Vec<CustomAlbum>> {
+    let rows: Vec<AlbumRow> = sqlx::query_as("SELECT * FROM pica_album WHERE owner=? ORDER BY created")
+        .bind(owner)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    let mut albums = Vec::with_capacity(rows.len());
+    for row in rows {
+        albums.push(album_from_row(tx, row).await?);
+    }
+
+    Ok(albums)
+}
+
+/// Deletes an album. Returns false if the user has no album with this id.
+pub async fn delete_album(tx: &mut Transaction<'_, Sqlite>, id: AlbumId, owner: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM pica_album WHERE id=? AND owner=?")
+        .bind(id)
+        .bind(owner)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn album_from_row(tx: &mut Transaction<'_, Sqlite>, row: AlbumRow) -> Result<CustomAlbum> {
+    let items: Vec<MediaId> = sqlx::query_scalar("SELECT media FROM pica_album_media WHERE album=? ORDER BY position")
+        .bind(row.id)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    Ok(CustomAlbum {
+        id: row.id,
+        owner: row.owner,
+        name: row.name,
+        description: row.description,
+        cover: row.cover,
+        items,
+        created: row.created,
+    })
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+
+use crate::pica::MediaId;
+
+/// Manual corrections of the metadata parsed from a media file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, sqlx::FromRow)]
+pub struct Correction {
+    // replaces the parsed timestamp
+    pub timestamp: Option<DateTime<Utc>>,
+
+    // moves the parsed timestamp by this many seconds, e.g. to fix the clock of a camera
+    pub shift: Option<i64>,
+
+    // replaces the parsed location
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+}
+
+#[derive(sqlx::FromRow)]
+struct CorrectionRow {
+    media: MediaId,
+
+    #[sqlx(flatten)]
+    correction: Correction,
+}
+
+/// Reads the corrections of all media items.
+pub async fn list_corrections(tx: &mut Transaction<'_, Sqlite>) -> Result<HashMap<MediaId, Correction>> {
+    let rows: Vec<CorrectionRow> =
+        sqlx::query_as("SELECT media, timestamp, shift, latitude, longitude FROM pica_media_correction")
+            .fetch_all(tx.deref_mut())
+            .await?;
+
+    Ok(rows.into_iter().map(|row| (row.media, row.correction)).collect())
+}
+
+pub async fn read_correction(tx: &mut Transaction<'_, Sqlite>, media: MediaId) -> Result<Option<Correction>> {
+    let correction =
+        sqlx::query_as("SELECT timestamp, shift, latitude, longitude FROM pica_media_correction WHERE media=?")
+            .bind(media)
+            .fetch_optional(tx.deref_mut())
+            .await?;
+
+    Ok(correction)
+}
+
+/// Replaces the complete correction of a media item, callers merge changes into the
+/// current correction first. An empty correction removes it.
+pub async fn store_correction(tx: &mut Transaction<'_, Sqlite>, media: MediaId, correction: Correction) -> Result<()> {
+    sqlx::query("DELETE FROM pica_media_correction WHERE media=?")
+        .bind(media)
+        .execute(tx.deref_mut())
+        .await?;
+
+    if correction == Correction::default() {
+        return Ok(());
+    }
+
+    sqlx::query("INSERT INTO pica_media_correction (media, timestamp, shift, latitude, longitude) VALUES (?, ?, ?, ?, ?)")
+        .bind(media)
+        .bind(correction.timestamp)
+        .bind(correction.shift)
+        .bind(correction.latitude)
+        .bind(correction.longitude)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+
+use crate::pica::MediaId;
+
+/// The marks a user put on a media item.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct MediaFlags {
+    pub favorite: bool,
+    pub hidden: bool,
+
+    // from 1 to 5 stars
+    pub rating: Option<u8>,
+}
+
+#[derive(sqlx::FromRow)]
+struct FlagsRow {
+    media: MediaId,
+
+    #[sqlx(flatten)]
+    flags: MediaFlags,
+}
+
+/// Reads the flags of all media items the user has marked.
+pub async fn list_flags(tx: &mut Transaction<'_, Sqlite>, user: &str) -> Result<HashMap<MediaId, MediaFlags>> {
+    let rows: Vec<FlagsRow> = sqlx::query_as("SELECT media, favorite, hidden, rating FROM pica_media_flags WHERE user=?")
+        .bind(user)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    Ok(rows.into_iter().map(|row| (row.media, row.flags)).collect())
+}
+
+pub async fn read_flags(tx: &mut Transaction<'_, Sqlite>, user: &str, media: MediaId) -> Result<MediaFlags> {
+    let flags: Option<MediaFlags> =
+        sqlx::query_as("SELECT favorite, hidden, rating FROM pica_media_flags WHERE user=? AND media=?")
+            .bind(user)
+            .bind(media)
+            .fetch_optional(tx.deref_mut())
+            .await?;
+
+    Ok(flags.unwrap_or_default())
+}
+
+pub async fn store_flags(tx: &mut Transaction<'_, Sqlite>, user: &str, media: MediaId, flags: MediaFlags) -> Result<()> {
+    // do not keep rows without any flag
+    if flags == MediaFlags::default() {
+        sqlx::query("DELETE FROM pica_media_flags WHERE user=? AND media=?")
+            .bind(user)
+            .bind(media)
+            .execute(tx.deref_mut())
+            .await?;
+
+        return Ok(());
+    }
+
+    sqlx::query(
+        "INSERT INTO pica_media_flags (user, media, favorite, hidden, rating) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (user, media) DO UPDATE SET favorite=excluded.favorite, hidden=excluded.hidden, rating=excluded.rating",
+    )
+    .bind(user)
+    .bind(media)
+    .bind(flags.favorite)
+    .bind(flags.hidden)
+    .bind(flags.rating)
+    .execute(tx.deref_mut())
+    .await?;
+
+    Ok(())
+}
@@ -2,12 +2,14 @@ use std::ffi::OsStr;
 use std::ops::DerefMut;
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
-use chrono::Utc;
+use arcstr::ArcStr;
+use chrono::{DateTime, Utc};
 use sqlx::{Sqlite, Transaction};
 
-use crate::pica::{MediaId, MediaInfo, MediaItem, SourceId};
+use crate::pica::{Identity, MediaHashes, MediaId, MediaInfo, MediaItem, Motion, SourceId, XmpInfo};
 
 #[derive(sqlx::FromRow)]
 struct MediaRow {
@@ -17,14 +19,84 @@ struct MediaRow {
     pub bytesize: i64,
     pub width: u32,
     pub height: u32,
-    pub timestamp: chrono::DateTime<Utc>,
+    pub timestamp: DateTime<Utc>,
     pub latitude: Option<f32>,
     pub longitude: Option<f32>,
+    pub mtime: Option<DateTime<Utc>>,
+    pub duration: Option<f64>,
+    pub motion_offset: Option<i64>,
+    pub motion_length: Option<i64>,
+    pub motion_relpath: Option<Vec<u8>>,
+    pub content_hash: Option<Vec<u8>>,
+    pub perceptual_hash: Option<i64>,
+    pub camera: Option<String>,
+    pub xmp_rating: Option<u8>,
+    pub xmp_label: Option<String>,
+    pub xmp_keywords: Option<String>,
+    pub xmp_description: Option<String>,
+    pub xmp_mtime: Option<DateTime<Utc>>,
 }
 
-/// Stores a scanned MediaItem into the database.
-pub async fn store_media_item(tx: &mut Transaction<'_, Sqlite>, item: &MediaItem) -> Result<()> {
-    sqlx::query("INSERT OR IGNORE INTO pica_media_cache (id, source, relpath, bytesize, width, height, timestamp, latitude, longitude) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+/// A [MediaItem] read from the cache, together with the modification time
+/// its file had at the time it was indexed.
+pub struct CachedMediaItem {
+    pub item: MediaItem,
+    pub mtime: Option<DateTime<Utc>>,
+
+    // false if the item was indexed before we extracted all the information we
+    // extract today. The file needs to be parsed again, its thumbnails are still valid.
+    pub complete: bool,
+
+    // modification time of the xmp sidecar file that was read, if any
+    pub xmp_mtime: Option<DateTime<Utc>>,
+}
+
+/// Stores a scanned MediaItem into the database, replacing any previous version.
+pub async fn store_media_item(
+    tx: &mut Transaction<'_, Sqlite>,
+    item: &MediaItem,
+    mtime: DateTime<Utc>,
+    xmp_mtime: Option<DateTime<Utc>>,
+    identity: Identity,
+) -> Result<()> {
+    // with content based ids, a different version of the file might still be cached for this path
+    let stale: Vec<MediaId> =
+        sqlx::query_scalar("SELECT id FROM pica_media_cache WHERE source=? AND relpath=? AND bytesize=? AND id!=?")
+            .bind(item.source.as_str())
+            .bind(item.relpath.as_os_str().as_bytes())
+            .bind(item.filesize as i64)
+            .bind(item.id)
+            .fetch_all(tx.deref_mut())
+            .await?;
+
+    for id in stale {
+        invalidate_media_item(tx, id).await?;
+    }
+
+    let sql = r#"
+        INSERT INTO pica_media_cache (id, source, relpath, bytesize, width, height, timestamp, latitude, longitude, mtime, duration,
+                                      motion_offset, motion_length, motion_relpath, identity, content_hash, perceptual_hash, camera,
+                                      xmp_rating, xmp_label, xmp_keywords, xmp_description, xmp_mtime)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE
+          SET relpath=excluded.relpath, bytesize=excluded.bytesize,
+              width=excluded.width, height=excluded.height, timestamp=excluded.timestamp,
+              latitude=excluded.latitude, longitude=excluded.longitude, mtime=excluded.mtime,
+              duration=excluded.duration, motion_offset=excluded.motion_offset,
+              motion_length=excluded.motion_length, motion_relpath=excluded.motion_relpath,
+              identity=excluded.identity, content_hash=excluded.content_hash,
+              perceptual_hash=excluded.perceptual_hash, camera=excluded.camera,
+              xmp_rating=excluded.xmp_rating, xmp_label=excluded.xmp_label, xmp_keywords=excluded.xmp_keywords,
+              xmp_description=excluded.xmp_description, xmp_mtime=excluded.xmp_mtime
+    "#;
+
+    let (motion_offset, motion_length, motion_relpath) = match &item.motion {
+        Some(Motion::Embedded { offset, len }) => (Some(*offset as i64), Some(*len as i64), None),
+        Some(Motion::Sidecar(relpath)) => (None, None, Some(relpath.as_os_str().as_bytes())),
+        None => (None, None, None),
+    };
+
+    sqlx::query(sql)
         .bind(item.id)
         .bind(item.source.as_str())
         .bind(item.relpath.as_os_str().as_bytes())
@@ -34,13 +106,63 @@ pub async fn store_media_item(tx: &mut Transaction<'_, Sqlite>, item: &MediaItem
         .bind(item.info.timestamp)
         .bind(item.info.latitude)
         .bind(item.info.longitude)
+        .bind(mtime)
+        .bind(item.info.duration.map(|duration| duration.as_secs_f64()))
+        .bind(motion_offset)
+        .bind(motion_length)
+        .bind(motion_relpath)
+        .bind(identity.as_str())
+        .bind(item.hashes.as_ref().map(|hashes| hashes.content.as_slice()))
+        .bind(item.hashes.and_then(|hashes| hashes.perceptual).map(|hash| hash as i64))
+        .bind(item.info.camera.as_deref().unwrap_or_default())
+        .bind(item.xmp.rating)
+        .bind(item.xmp.label.as_deref())
+        .bind(item.xmp.keywords.join("\n"))
+        .bind(item.xmp.description.as_deref())
+        .bind(xmp_mtime)
         .execute(tx.deref_mut())
         .await?;
 
     Ok(())
 }
 
-impl TryFrom<MediaRow> for MediaItem {
+/// Updates the modification time of a cached media item.
+pub async fn update_mtime(tx: &mut Transaction<'_, Sqlite>, id: MediaId, mtime: DateTime<Utc>) -> Result<()> {
+    sqlx::query("UPDATE pica_media_cache SET mtime=? WHERE id=?")
+        .bind(mtime)
+        .bind(id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
+
+/// Updates the modification time of the xmp sidecar of a cached media item, e.g. after
+/// writing it ourselves. The sidecar is then not read again.
+pub async fn update_xmp_mtime(tx: &mut Transaction<'_, Sqlite>, id: MediaId, xmp_mtime: DateTime<Utc>) -> Result<()> {
+    sqlx::query("UPDATE pica_media_cache SET xmp_mtime=? WHERE id=?")
+        .bind(xmp_mtime)
+        .bind(id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
+
+/// Removes everything we know about a media item, e.g. because its file has changed.
+pub async fn invalidate_media_item(tx: &mut Transaction<'_, Sqlite>, id: MediaId) -> Result<()> {
+    for sql in [
+        "DELETE FROM pica_image WHERE media=?",
+        "DELETE FROM pica_media_error WHERE id=?",
+        "DELETE FROM pica_media_cache WHERE id=?",
+    ] {
+        sqlx::query(sql).bind(id).execute(tx.deref_mut()).await?;
+    }
+
+    Ok(())
+}
+
+impl TryFrom<MediaRow> for CachedMediaItem {
     type Error = anyhow::Error;
 
     fn try_from(row: MediaRow) -> Result<Self> {
@@ -52,14 +174,52 @@ impl TryFrom<MediaRow> for MediaItem {
             height: row.height,
             latitude: row.latitude,
             longitude: row.longitude,
+            duration: row.duration.map(Duration::from_secs_f64),
+            camera: row.camera.as_deref().filter(|camera| !camera.is_empty()).map(ArcStr::from),
         };
 
+        let complete = row.content_hash.is_some() && row.camera.is_some() && row.xmp_keywords.is_some();
+
         let source = SourceId(row.source.into());
-        MediaItem::from_media_info(row.id, source, relpath, row.bytesize as u64, info)
+        let mut item = MediaItem::from_media_info(row.id, source, relpath, row.bytesize as u64, info)?;
+
+        item.motion = match (row.motion_offset, row.motion_length, row.motion_relpath) {
+            (Some(offset), Some(len), _) => Some(Motion::Embedded {
+                offset: offset as u64,
+                len: len as u64,
+            }),
+
+            (_, _, Some(relpath)) => Some(Motion::Sidecar(PathBuf::from(OsStr::from_bytes(&relpath)).into())),
+
+            _ => None,
+        };
+
+        item.hashes = match row.content_hash.as_deref().map(<[u8; 20]>::try_from) {
+            Some(Ok(content)) => Some(MediaHashes {
+                content,
+                perceptual: row.perceptual_hash.map(|hash| hash as u64),
+            }),
+
+            _ => None,
+        };
+
+        item.xmp = XmpInfo {
+            rating: row.xmp_rating,
+            label: row.xmp_label.map(ArcStr::from),
+            keywords: row.xmp_keywords.iter().flat_map(|keywords| keywords.lines()).map(ArcStr::from).collect(),
+            description: row.xmp_description.map(ArcStr::from),
+        };
+
+        Ok(Self {
+            item,
+            mtime: row.mtime,
+            complete,
+            xmp_mtime: row.xmp_mtime,
+        })
     }
 }
 
-pub async fn read_media_item(tx: &mut Transaction<'_, Sqlite>, id: MediaId) -> Result<Option<MediaItem>> {
+pub async fn read_media_item(tx: &mut Transaction<'_, Sqlite>, id: MediaId) -> Result<Option<CachedMediaItem>> {
     let row: Option<MediaRow> = sqlx::query_as("SELECT * FROM pica_media_cache WHERE id=?")
         .bind(id)
         .fetch_optional(tx.deref_mut())
@@ -70,7 +230,101 @@ pub async fn read_media_item(tx: &mut Transaction<'_, Sqlite>, id: MediaId) -> R
         return Ok(None);
     };
 
-    Ok(Some(MediaItem::try_from(row)?))
+    Ok(Some(CachedMediaItem::try_from(row)?))
+}
+
+/// Reads all cached media items of the given source.
+pub async fn list_media_items(tx: &mut Transaction<'_, Sqlite>, source: &SourceId) -> Result<Vec<CachedMediaItem>> {
+    let rows: Vec<MediaRow> = sqlx::query_as("SELECT * FROM pica_media_cache WHERE source=?")
+        .bind(source.as_str())
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    rows.into_iter().map(CachedMediaItem::try_from).collect()
+}
+
+/// Reads the cached media items of the given source whose ids were not derived using `identity`.
+pub async fn list_media_items_with_other_identity(
+    tx: &mut Transaction<'_, Sqlite>,
+    source: &SourceId,
+    identity: Identity,
+) -> Result<Vec<CachedMediaItem>> {
+    let rows: Vec<MediaRow> =
+        sqlx::query_as("SELECT * FROM pica_media_cache WHERE source=? AND COALESCE(identity, 'path') != ?")
+            .bind(source.as_str())
+            .bind(identity.as_str())
+            .fetch_all(tx.deref_mut())
+            .await?;
+
+    rows.into_iter().map(CachedMediaItem::try_from).collect()
+}
+
+/// Changes the id of a cached media item, keeping its thumbnails.
+pub async fn rekey_media_item(tx: &mut Transaction<'_, Sqlite>, id: MediaId, new_id: MediaId, identity: Identity) -> Result<()> {
+    let exists: Option<MediaId> = sqlx::query_scalar("SELECT id FROM pica_media_cache WHERE id=?")
+        .bind(new_id)
+        .fetch_optional(tx.deref_mut())
+        .await?;
+
+    if id != new_id {
+        move_media_references(tx, id, new_id).await?;
+    }
+
+    // a copy of the same file that is already cached
+    if exists.is_some() && id != new_id {
+        return invalidate_media_item(tx, id).await;
+    }
+
+    // thumbnails reference the media item, only check them once we are done
+    sqlx::query("PRAGMA defer_foreign_keys = ON").execute(tx.deref_mut()).await?;
+
+    sqlx::query("UPDATE pica_media_cache SET id=?, identity=? WHERE id=?")
+        .bind(new_id)
+        .bind(identity.as_str())
+        .bind(id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    for sql in [
+        "UPDATE pica_image SET media=? WHERE media=?",
+        "UPDATE pica_media_error SET id=? WHERE id=?",
+    ] {
+        sqlx::query(sql).bind(new_id).bind(id).execute(tx.deref_mut()).await?;
+    }
+
+    Ok(())
+}
+
+/// Moves shares, album entries, flags, tags and corrections of a media item to its new id.
+/// If the new id already has an entry of its own, e.g. a copy of the file that was tagged
+/// too, that entry is kept and the one of the old id is dropped.
+async fn move_media_references(tx: &mut Transaction<'_, Sqlite>, id: MediaId, new_id: MediaId) -> Result<()> {
+    sqlx::query("UPDATE pica_album SET cover=? WHERE cover=?")
+        .bind(new_id)
+        .bind(id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    for table in [
+        "pica_share_media",
+        "pica_album_media",
+        "pica_media_flags",
+        "pica_media_tag",
+        "pica_media_correction",
+    ] {
+        sqlx::query(&format!("UPDATE OR IGNORE {} SET media=? WHERE media=?", table))
+            .bind(new_id)
+            .bind(id)
+            .execute(tx.deref_mut())
+            .await?;
+
+        sqlx::query(&format!("DELETE FROM {} WHERE media=?", table))
+            .bind(id)
+            .execute(tx.deref_mut())
+            .await?;
+    }
+
+    Ok(())
 }
 
 pub async fn media_mark_as_error(tx: &mut Transaction<'_, Sqlite>, id: MediaId, error: &str) -> Result<()> {
@@ -1,3 +1,9 @@
+pub mod album;
+pub mod correction;
+pub mod flags;
 pub mod image;
 pub mod media;
+pub mod share;
+pub mod tag;
+pub mod track;
 mod types;
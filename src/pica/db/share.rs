@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::ops::DerefMut;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+
+use crate::pica::MediaId;
+
+/// A link that grants read-only access to an album or a selection of media items.
+#[derive(Clone, Debug)]
+pub struct Share {
+    pub token: String,
+    pub owner: String,
+    pub name: String,
+    pub target: ShareTarget,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+
+    // bcrypt hash of the password, if any
+    pub passwd: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ShareTarget {
+    // all items in the album with this directory
+    Album(Arc<PathBuf>),
+    Media(HashSet<MediaId>),
+}
+
+impl Share {
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ShareRow {
+    token: String,
+    owner: String,
+    name: String,
+    album_relpath: Option<Vec<u8>>,
+    created: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    passwd: Option<String>,
+}
+
+pub async fn store_share(tx: &mut Transaction<'_, Sqlite>, share: &Share) -> Result<()> {
+    let album_relpath = match &share.target {
+        ShareTarget::Album(relpath) => Some(relpath.as_os_str().as_bytes()),
+        ShareTarget::Media(_) => None,
+    };
+
+    sqlx::query("INSERT INTO pica_share (token, owner, name, album_relpath, created, expires, passwd) VALUES (?, ?, ?, ?, ?, ?, ?)")
+        .bind(&share.token)
+        .bind(&share.owner)
+        .bind(&share.name)
+        .bind(album_relpath)
+        .bind(share.created)
+        .bind(share.expires)
+        .bind(&share.passwd)
+        .execute(tx.deref_mut())
+        .await?;
+
+    if let ShareTarget::Media(ids) = &share.target {
+        for id in ids {
+            sqlx::query("INSERT INTO pica_share_media (share, media) VALUES (?, ?)")
+                .bind(&share.token)
+                .bind(id)
+                .execute(tx.deref_mut())
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn read_share(tx: &mut Transaction<'_, Sqlite>, token: &str) -> Result<Option<Share>> {
+    let row: Option<ShareRow> = sqlx::query_as("SELECT * FROM pica_share WHERE token=?")
+        .bind(token)
+        .fetch_optional(tx.deref_mut())
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some(share_from_row(tx, row).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Lists all shares created by the given user.
+pub async fn list_shares(tx: &mut Transaction<'_, Sqlite>, owner: &str) -> Result<Vec<Share>> {
+    let rows: Vec<ShareRow> = sqlx::query_as("SELECT * FROM pica_share WHERE owner=? ORDER BY created DESC")
+        .bind(owner)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    let mut shares = Vec::with_capacity(rows.len());
+    for row in rows {
+        shares.push(share_from_row(tx, row).await?);
+    }
+
+    Ok(shares)
+}
+
+/// Revokes a share. Returns false if the user has no share with this token.
+pub async fn delete_share(tx: &mut Transaction<'_, Sqlite>, token: &str, owner: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM pica_share WHERE token=? AND owner=?")
+        .bind(token)
+        .bind(owner)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn share_from_row(tx: &mut Transaction<'_, Sqlite>, row: ShareRow) -> Result<Share> {
+    let target = match row.album_relpath {
+        Some(relpath) => ShareTarget::Album(Arc::new(PathBuf::from(OsStr::from_bytes(&relpath)))),
+
+        None => {
+            let ids: Vec<MediaId> = sqlx::query_scalar("SELECT media FROM pica_share_media WHERE share=?")
+                .bind(&row.token)
+                .fetch_all(tx.deref_mut())
+                .await?;
+
+            ShareTarget::Media(ids.into_iter().collect())
+        }
+    };
+
+    Ok(Share {
+        token: row.token,
+        owner: row.owner,
+        name: row.name,
+        target,
+        created: row.created,
+        expires: row.expires,
+        passwd: row.passwd,
+    })
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+
+use crate::pica::MediaId;
+
+/// Reads the tags of all media items, sorted by name.
+pub async fn list_tags(tx: &mut Transaction<'_, Sqlite>) -> Result<HashMap<MediaId, Vec<String>>> {
+    let rows: Vec<(MediaId, String)> = sqlx::query_as("SELECT media, tag FROM pica_media_tag ORDER BY tag")
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    let mut tags = HashMap::<MediaId, Vec<String>>::new();
+    for (media, tag) in rows {
+        tags.entry(media).or_default().push(tag);
+    }
+
+    Ok(tags)
+}
+
+pub async fn read_tags(tx: &mut Transaction<'_, Sqlite>, media: MediaId) -> Result<Vec<String>> {
+    let tags = sqlx::query_scalar("SELECT tag FROM pica_media_tag WHERE media=? ORDER BY tag")
+        .bind(media)
+        .fetch_all(tx.deref_mut())
+        .await?;
+
+    Ok(tags)
+}
+
+pub async fn add_tag(tx: &mut Transaction<'_, Sqlite>, media: MediaId, tag: &str) -> Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO pica_media_tag (media, tag) VALUES (?, ?)")
+        .bind(media)
+        .bind(tag)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
+
+/// Removes the tag from the media item. Tags below it in the hierarchy are kept.
+pub async fn remove_tag(tx: &mut Transaction<'_, Sqlite>, media: MediaId, tag: &str) -> Result<()> {
+    sqlx::query("DELETE FROM pica_media_tag WHERE media=? AND tag=?")
+        .bind(media)
+        .bind(tag)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
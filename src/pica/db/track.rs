@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ops::DerefMut;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+
+/// A point of a gpx track.
+#[derive(Clone, Copy, Debug, PartialEq, sqlx::FromRow)]
+pub struct TrackPoint {
+    pub timestamp: DateTime<Utc>,
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Track {
+    pub id: i64,
+    pub name: String,
+
+    // the source of a track found within a source, none for uploaded tracks
+    pub source: Option<String>,
+
+    // the user that uploaded the track
+    pub owner: Option<String>,
+
+    pub mtime: DateTime<Utc>,
+
+    // number of points and the time span of the track
+    pub points: i64,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+/// A track point together with where its track came from.
+#[derive(sqlx::FromRow)]
+pub struct TrackPointRow {
+    pub source: Option<String>,
+    pub owner: Option<String>,
+
+    #[sqlx(flatten)]
+    pub point: TrackPoint,
+}
+
+pub async fn list_tracks(tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<Track>> {
+    let tracks = sqlx::query_as(
+        "
+        SELECT t.id, t.name, t.source, t.owner, t.mtime,
+               COUNT(p.track) AS points, MIN(p.timestamp) AS start, MAX(p.timestamp) AS end
+        FROM pica_track t
+            LEFT JOIN pica_track_point p ON p.track = t.id
+        GROUP BY t.id
+        ORDER BY start, t.id
+        ",
+    )
+    .fetch_all(tx.deref_mut())
+    .await?;
+
+    Ok(tracks)
+}
+
+pub async fn read_track(tx: &mut Transaction<'_, Sqlite>, id: i64) -> Result<Option<Track>> {
+    let tracks = list_tracks(tx).await?;
+    Ok(tracks.into_iter().find(|track| track.id == id))
+}
+
+/// Reads the points of all tracks, ordered by time.
+pub async fn list_track_points(tx: &mut Transaction<'_, Sqlite>) -> Result<Vec<TrackPointRow>> {
+    let points = sqlx::query_as(
+        "
+        SELECT t.source, t.owner, p.timestamp, p.latitude, p.longitude
+        FROM pica_track_point p
+            JOIN pica_track t ON p.track = t.id
+        ORDER BY p.timestamp
+        ",
+    )
+    .fetch_all(tx.deref_mut())
+    .await?;
+
+    Ok(points)
+}
+
+/// Returns id and modification time of the tracks found within the source, by path.
+pub async fn list_source_tracks(
+    tx: &mut Transaction<'_, Sqlite>,
+    source: &str,
+) -> Result<HashMap<PathBuf, (i64, DateTime<Utc>)>> {
+    let rows: Vec<(i64, Vec<u8>, DateTime<Utc>)> =
+        sqlx::query_as("SELECT id, path, mtime FROM pica_track WHERE source=?")
+            .bind(source)
+            .fetch_all(tx.deref_mut())
+            .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, path, mtime)| (PathBuf::from(OsStr::from_bytes(&path)), (id, mtime)))
+        .collect())
+}
+
+/// Stores a track found within a source, replacing a previous version of the same file.
+pub async fn store_source_track(
+    tx: &mut Transaction<'_, Sqlite>,
+    source: &str,
+    path: &Path,
+    mtime: DateTime<Utc>,
+    points: &[TrackPoint],
+) -> Result<i64> {
+    sqlx::query("DELETE FROM pica_track WHERE source=? AND path=?")
+        .bind(source)
+        .bind(path.as_os_str().as_bytes())
+        .execute(tx.deref_mut())
+        .await?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    let id = sqlx::query_scalar("INSERT INTO pica_track (name, source, path, mtime) VALUES (?, ?, ?, ?) RETURNING id")
+        .bind(name.as_ref())
+        .bind(source)
+        .bind(path.as_os_str().as_bytes())
+        .bind(mtime)
+        .fetch_one(tx.deref_mut())
+        .await?;
+
+    store_points(tx, id, points).await?;
+
+    Ok(id)
+}
+
+/// Stores a track uploaded by a user.
+pub async fn store_uploaded_track(
+    tx: &mut Transaction<'_, Sqlite>,
+    name: &str,
+    owner: &str,
+    points: &[TrackPoint],
+) -> Result<i64> {
+    let id = sqlx::query_scalar("INSERT INTO pica_track (name, owner, mtime) VALUES (?, ?, ?) RETURNING id")
+        .bind(name)
+        .bind(owner)
+        .bind(Utc::now())
+        .fetch_one(tx.deref_mut())
+        .await?;
+
+    store_points(tx, id, points).await?;
+
+    Ok(id)
+}
+
+pub async fn delete_track(tx: &mut Transaction<'_, Sqlite>, id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM pica_track WHERE id=?")
+        .bind(id)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes a track uploaded by the user. Returns false if the user has no track with this id.
+pub async fn delete_uploaded_track(tx: &mut Transaction<'_, Sqlite>, id: i64, owner: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM pica_track WHERE id=? AND owner=?")
+        .bind(id)
+        .bind(owner)
+        .execute(tx.deref_mut())
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn store_points(tx: &mut Transaction<'_, Sqlite>, track: i64, points: &[TrackPoint]) -> Result<()> {
+    for point in points {
+        sqlx::query("INSERT INTO pica_track_point (track, timestamp, latitude, longitude) VALUES (?, ?, ?, ?)")
+            .bind(track)
+            .bind(point.timestamp)
+            .bind(point.latitude)
+            .bind(point.longitude)
+            .execute(tx.deref_mut())
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,424 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use itertools::Itertools;
+use tracing::{debug, instrument};
+
+use crate::pica::{MediaHashes, MediaId, MediaItem};
+
+// maximum number of differing bits for two perceptual hashes to be considered a near duplicate.
+// Must be less than 7 for the bucketing in `near_duplicates` to find all pairs.
+const NEAR_DUPLICATE_DISTANCE: u32 = 6;
+
+// videos are only hashed at their start and end
+const VIDEO_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// A set of media items showing the same picture.
+pub struct DuplicateGroup {
+    // all items are identical files. Videos are only compared at their start and end.
+    pub exact: bool,
+    pub items: Vec<MediaItem>,
+}
+
+/// Calculates the hashes of a media item. This reads the complete image file
+/// and decodes it, so it must not be called on the async runtime.
+#[instrument(skip_all, fields(? item.relpath))]
+pub fn hash_media(item: &MediaItem) -> Result<MediaHashes> {
+    let content = match item.is_video() {
+        true => sampled_hash(&item.relpath, item.filesize)?,
+        false => content_hash(&item.relpath)?,
+    };
+
+    let perceptual = match item.is_video() {
+        true => None,
+        false => match decode_small(&item.relpath) {
+            Ok(image) => Some(perceptual_hash(&image)),
+            Err(err) => {
+                debug!("No perceptual hash for {:?}: {:?}", item.relpath, err);
+                None
+            }
+        },
+    };
+
+    Ok(MediaHashes { content, perceptual })
+}
+
+fn content_hash(path: &Path) -> Result<[u8; 20]> {
+    let mut fp = File::open(path)?;
+
+    let mut hasher = sha1_smol::Sha1::new();
+    let mut buf = vec![0_u8; 256 * 1024];
+
+    loop {
+        let n = fp.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.digest().bytes())
+}
+
+/// Videos are too large to be read completely. Their size together with the
+/// start and the end of the file tells copies apart well enough.
+fn sampled_hash(path: &Path, filesize: u64) -> Result<[u8; 20]> {
+    let mut fp = File::open(path)?;
+
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(&filesize.to_be_bytes());
+
+    let mut buf = Vec::new();
+    (&mut fp).take(VIDEO_SAMPLE_SIZE).read_to_end(&mut buf)?;
+    hasher.update(&buf);
+
+    let tail = filesize.saturating_sub(VIDEO_SAMPLE_SIZE).max(buf.len() as u64);
+
+    buf.clear();
+    fp.seek(SeekFrom::Start(tail))?;
+    fp.take(VIDEO_SAMPLE_SIZE).read_to_end(&mut buf)?;
+    hasher.update(&buf);
+
+    Ok(hasher.digest().bytes())
+}
+
+fn decode_small(path: &Path) -> Result<DynamicImage> {
+    // an embedded thumbnail is good enough for a 9x8 pixel hash
+    let file = pica_image::get_for_size(path, 256)?;
+    Ok(image::ImageReader::new(file.open()?).with_guessed_format()?.decode()?)
+}
+
+/// Difference hash: scale the image down to 9x8 gray pixels and
+/// set a bit for each pixel that is brighter than its right neighbour.
+fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+    let mut hash = 0_u64;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+
+    hash
+}
+
+/// Groups items with the same content hash. Items without hashes are ignored.
+pub fn exact_duplicates(items: impl IntoIterator<Item = MediaItem>) -> Vec<Vec<MediaItem>> {
+    items
+        .into_iter()
+        .filter_map(|item| Some((item.hashes?.content, item)))
+        .into_group_map()
+        .into_values()
+        .collect()
+}
+
+/// Returns the ids of all exact duplicates, except one item of each set.
+pub fn redundant_copies(items: &[MediaItem]) -> HashSet<MediaId> {
+    let mut keep: HashMap<[u8; 20], &MediaItem> = HashMap::new();
+
+    for item in items {
+        let Some(hashes) = item.hashes else {
+            continue;
+        };
+
+        // prefer the same item each time, independent of the order of the input
+        match keep.entry(hashes.content) {
+            Entry::Occupied(mut entry) => {
+                if duplicate_order(item, entry.get()).is_lt() {
+                    entry.insert(item);
+                }
+            }
+
+            Entry::Vacant(entry) => {
+                entry.insert(item);
+            }
+        }
+    }
+
+    let keep: HashSet<MediaId> = keep.values().map(|item| item.id).collect();
+
+    items
+        .iter()
+        .filter(|item| item.hashes.is_some() && !keep.contains(&item.id))
+        .map(|item| item.id)
+        .collect()
+}
+
+/// Finds groups of duplicates. Exact duplicates whose pictures are also similar to other
+/// pictures end up in a single group of near duplicates.
+#[instrument(skip_all)]
+pub fn find_duplicates(items: impl IntoIterator<Item = MediaItem>) -> Vec<DuplicateGroup> {
+    let exact = exact_duplicates(items);
+
+    // one representative per distinct file
+    let representatives = exact
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, items)| {
+            let item = &items[0];
+            Some((idx, item.hashes?.perceptual?, aspect_ratio(item)))
+        })
+        .collect_vec();
+
+    let mut sets = DisjointSets::new(exact.len());
+
+    for (a, b) in near_duplicates(&representatives) {
+        sets.union(a, b);
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for idx in 0..exact.len() {
+        groups.entry(sets.find(idx)).or_default().push(idx);
+    }
+
+    let mut exact = exact.into_iter().map(Some).collect_vec();
+
+    let mut result = groups
+        .into_values()
+        .filter_map(|members| {
+            let near = members.len() > 1;
+
+            let mut items = members.into_iter().flat_map(|idx| exact[idx].take().unwrap_or_default()).collect_vec();
+
+            if items.len() < 2 {
+                return None;
+            }
+
+            items.sort_by(duplicate_order);
+
+            Some(DuplicateGroup { exact: !near, items })
+        })
+        .collect_vec();
+
+    // newest pictures first, like in the stream
+    result.sort_by_key(|group| std::cmp::Reverse(group.items.iter().map(|item| item.info.timestamp).max()));
+
+    result
+}
+
+/// Finds pairs of similar pictures, enough to connect all similar pictures into groups.
+/// Hashes that differ in at most 6 bits have at least two of their 8 bytes in common, so we
+/// only need to compare hashes sharing two bytes at the same positions. This keeps the buckets
+/// small, even for libraries with lots of similar pictures.
+fn near_duplicates(hashes: &[(usize, u64, f32)]) -> Vec<(usize, usize)> {
+    let mut pairs = HashSet::new();
+
+    // pictures with the same hash and shape, e.g. a series of screenshots, are compared once
+    let mut same: HashMap<(u64, u32), usize> = HashMap::new();
+    let mut distinct = Vec::new();
+
+    for (pos, &(idx, hash, aspect)) in hashes.iter().enumerate() {
+        match same.entry((hash, aspect.to_bits())) {
+            Entry::Occupied(entry) => _ = pairs.insert((hashes[*entry.get()].0, idx)),
+            Entry::Vacant(entry) => {
+                entry.insert(pos);
+                distinct.push(pos);
+            }
+        }
+    }
+
+    let mut buckets: HashMap<(usize, usize, u8, u8), Vec<usize>> = HashMap::new();
+
+    for &pos in &distinct {
+        let bytes = hashes[pos].1.to_be_bytes();
+
+        for (a, b) in (0..8).tuple_combinations() {
+            buckets.entry((a, b, bytes[a], bytes[b])).or_default().push(pos);
+        }
+    }
+
+    for bucket in buckets.values() {
+        for (idx, &a) in bucket.iter().enumerate() {
+            for &b in &bucket[idx + 1..] {
+                let (a_idx, a_hash, a_aspect) = hashes[a];
+                let (b_idx, b_hash, b_aspect) = hashes[b];
+
+                // a crop might have a similar hash, but it is not the same picture
+                let similar_aspect = (a_aspect - b_aspect).abs() < 0.05 * a_aspect.max(b_aspect);
+
+                if similar_aspect && (a_hash ^ b_hash).count_ones() <= NEAR_DUPLICATE_DISTANCE {
+                    pairs.insert((a_idx, b_idx));
+                }
+            }
+        }
+    }
+
+    pairs.into_iter().collect()
+}
+
+fn aspect_ratio(item: &MediaItem) -> f32 {
+    item.info.width as f32 / item.info.height as f32
+}
+
+/// Larger files first, as they are most likely the originals.
+fn duplicate_order(a: &MediaItem, b: &MediaItem) -> std::cmp::Ordering {
+    b.filesize
+        .cmp(&a.filesize)
+        .then_with(|| a.source.as_str().cmp(b.source.as_str()))
+        .then_with(|| a.relpath.cmp(&b.relpath))
+}
+
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, mut idx: usize) -> usize {
+        while self.parents[idx] != idx {
+            self.parents[idx] = self.parents[self.parents[idx]];
+            idx = self.parents[idx];
+        }
+
+        idx
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parents[a] = b;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeDelta, TimeZone, Utc};
+
+    use super::*;
+    use crate::pica::testing::media_item;
+
+    fn hashed(id: u8, content: u8, perceptual: Option<u64>) -> MediaItem {
+        let timestamp = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap() + TimeDelta::seconds(id.into());
+        let mut item = media_item(id, timestamp, None);
+        item.filesize = 1000 + u64::from(id);
+        item.hashes = Some(MediaHashes {
+            content: [content; 20],
+            perceptual,
+        });
+
+        item
+    }
+
+    fn ids(group: &DuplicateGroup) -> Vec<u8> {
+        group.items.iter().map(|item| item.id.as_bytes()[0]).collect()
+    }
+
+    #[test]
+    fn test_exact_duplicates() {
+        let items = vec![hashed(1, 1, Some(0)), hashed(2, 1, Some(0)), hashed(3, 2, Some(u64::MAX))];
+
+        let groups = find_duplicates(items.clone());
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].exact);
+
+        // the larger file first
+        assert_eq!(ids(&groups[0]), [2, 1]);
+
+        assert_eq!(redundant_copies(&items), HashSet::from([MediaId::from([1; 8])]));
+    }
+
+    #[test]
+    fn test_near_duplicates_at_threshold() {
+        // differs in as many bits as allowed, spread over most of the bytes
+        let near: u64 = 0x0101_0101_0101_0000;
+        assert_eq!(near.count_ones(), NEAR_DUPLICATE_DISTANCE);
+
+        let groups = find_duplicates(vec![hashed(1, 1, Some(0)), hashed(2, 2, Some(near))]);
+        assert_eq!(groups.len(), 1);
+        assert!(!groups[0].exact);
+        assert_eq!(ids(&groups[0]), [2, 1]);
+
+        // one more bit is too much
+        let far = near | 0x0100;
+        assert!(find_duplicates(vec![hashed(1, 1, Some(0)), hashed(2, 2, Some(far))]).is_empty());
+
+        // exact copies join the group of a similar picture
+        let groups = find_duplicates(vec![hashed(1, 1, Some(0)), hashed(2, 2, Some(near)), hashed(3, 1, Some(0))]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(ids(&groups[0]), [3, 2, 1]);
+
+        // not the same picture if it was cropped
+        let mut crop = hashed(2, 2, Some(near));
+        crop.info.height = 4000;
+        assert!(find_duplicates(vec![hashed(1, 1, Some(0)), crop]).is_empty());
+    }
+
+    #[test]
+    fn test_many_similar_pictures() {
+        // a series of screenshots, with the same hash or one bit apart
+        let items = (0..=200_u8)
+            .map(|id| hashed(id, id, Some(u64::from(id % 2) << 40)))
+            .chain([hashed(201, 201, Some(u64::MAX))])
+            .collect_vec();
+
+        let groups = find_duplicates(items);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].items.len(), 201);
+    }
+
+    #[test]
+    fn test_video_hash_samples() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let video = |name: &str, patch: Option<(usize, u8)>| -> Result<MediaItem> {
+            let mut content = (0..3 * VIDEO_SAMPLE_SIZE).map(|idx| (idx % 251) as u8).collect_vec();
+            if let Some((pos, value)) = patch {
+                content[pos] = value;
+            }
+
+            let path = dir.path().join(name);
+            std::fs::write(&path, &content)?;
+
+            let mut item = hashed(1, 1, None);
+            item.relpath = path.into();
+            item.filesize = content.len() as u64;
+            item.typ = pica_image::MediaType::GenericVideo;
+            Ok(item)
+        };
+
+        let content = |item: &MediaItem| hash_media(item).map(|hashes| hashes.content);
+
+        let original = content(&video("a.mp4", None)?)?;
+
+        // the middle of a video is not read
+        let middle = VIDEO_SAMPLE_SIZE as usize + 10;
+        assert_eq!(content(&video("b.mp4", Some((middle, 0)))?)?, original);
+
+        let end = 3 * VIDEO_SAMPLE_SIZE as usize - 10;
+        assert_ne!(content(&video("c.mp4", Some((end, 0)))?)?, original);
+        assert_ne!(content(&video("d.mp4", Some((10, 0)))?)?, original);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_items_without_hashes() {
+        let mut unhashed = hashed(2, 1, Some(0));
+        unhashed.hashes = None;
+
+        let items = vec![hashed(1, 1, Some(0)), unhashed, hashed(3, 3, None)];
+
+        assert!(find_duplicates(items.clone()).is_empty());
+        assert!(redundant_copies(&items).is_empty());
+
+        // videos have no perceptual hash, but exact copies are still found
+        let groups = find_duplicates(vec![hashed(1, 3, None), hashed(2, 3, None)]);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].exact);
+    }
+}
@@ -0,0 +1,355 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use arcstr::ArcStr;
+use chrono::{Datelike, Duration, NaiveDate};
+use itertools::Itertools;
+use tracing::instrument;
+
+use crate::pica::album::{self, Kind};
+use crate::pica::{Album, AlbumInfo, City, MediaItem};
+
+// items further apart in time belong to different days
+const MAX_GAP: Duration = Duration::hours(3);
+
+// items taken further apart belong to different days, e.g. when travelling to another city
+const MAX_JUMP_KM: f64 = 50.0;
+
+// fewer items on a day are not worth an album of their own
+const MIN_DAY_ITEMS: usize = 3;
+
+// a trip continues over at most this many days without any items
+const MAX_TRIP_GAP_DAYS: i64 = 1;
+
+// a trip covers at least this many days
+const MIN_TRIP_DAYS: usize = 2;
+
+/// Groups the items into virtual albums of days and trips. A day is a series
+/// of items taken on the same date without larger breaks or jumps in location.
+/// A trip is a series of days spent away from home, where home is the city most
+/// items were taken in.
+#[instrument(skip_all)]
+pub fn by_event(items: Vec<MediaItem>) -> Vec<Album> {
+    let items = items
+        .into_iter()
+        .sorted_by_key(|item| (item.info.timestamp, item.id))
+        .collect_vec();
+
+    let days = split_days(&items);
+
+    // trips never start on the same date
+    let mut albums = trips(&items, &days)
+        .into_iter()
+        .filter_map(|trip| event_album(Kind::Trip, trip, 0))
+        .collect_vec();
+
+    // but days might, e.g. after a longer break
+    let mut ordinals = HashMap::<NaiveDate, usize>::new();
+
+    albums.extend(days.into_iter().filter(|day| day.len() >= MIN_DAY_ITEMS).filter_map(|day| {
+        let ordinal = ordinals.entry(date(&day[0])).or_default();
+        *ordinal += 1;
+        event_album(Kind::Day, day.to_vec(), *ordinal - 1)
+    }));
+
+    albums.sort_by_key(|album| album.info.timestamp);
+    albums
+}
+
+fn split_days(items: &[MediaItem]) -> Vec<&[MediaItem]> {
+    let mut days = Vec::new();
+
+    let mut start = 0;
+    let mut located: Option<&MediaItem> = None;
+
+    for (idx, item) in items.iter().enumerate() {
+        if let Some(previous) = idx.checked_sub(1).map(|idx| &items[idx]) {
+            let jump = match (located.and_then(|item| item.location.as_ref()), &item.location) {
+                (Some(a), Some(b)) => pica_geo::haversine_km(a.latitude, a.longitude, b.latitude, b.longitude),
+                _ => 0.0,
+            };
+
+            let split = date(previous) != date(item)
+                || item.info.timestamp - previous.info.timestamp > MAX_GAP
+                || jump > MAX_JUMP_KM;
+
+            if split {
+                days.push(&items[start..idx]);
+                start = idx;
+                located = None;
+            }
+        }
+
+        if item.location.is_some() {
+            located = Some(item);
+        }
+    }
+
+    if start < items.len() {
+        days.push(&items[start..]);
+    }
+
+    days
+}
+
+fn trips(items: &[MediaItem], days: &[&[MediaItem]]) -> Vec<Vec<MediaItem>> {
+    let Some(home) = dominant_city(items) else {
+        return Vec::new();
+    };
+
+    let mut trips = Vec::new();
+
+    // the current trip, and days without a known location that might belong to it
+    let mut trip: Vec<&[MediaItem]> = Vec::new();
+    let mut pending: Vec<&[MediaItem]> = Vec::new();
+
+    for &day in days {
+        let continues = pending.last().or(trip.last()).is_some_and(|previous| {
+            let gap = date(&day[0]) - date(&previous[previous.len() - 1]);
+            gap.num_days() <= MAX_TRIP_GAP_DAYS + 1
+        });
+
+        if !continues {
+            trips.push(std::mem::take(&mut trip));
+            pending.clear();
+        }
+
+        match dominant_city(day) {
+            Some(city) if same_city(city, home) => {
+                trips.push(std::mem::take(&mut trip));
+                pending.clear();
+            }
+
+            Some(_) => {
+                trip.append(&mut pending);
+                trip.push(day);
+            }
+
+            None if !trip.is_empty() => pending.push(day),
+            None => (),
+        }
+    }
+
+    trips.push(trip);
+
+    trips
+        .into_iter()
+        .filter(|trip| trip.iter().map(|day| date(&day[0])).unique().count() >= MIN_TRIP_DAYS)
+        .map(|trip| trip.concat())
+        .collect()
+}
+
+fn event_album(kind: Kind, mut items: Vec<MediaItem>, ordinal: usize) -> Option<Album> {
+    let first = items.first()?.clone();
+
+    items.sort_by_key(|item| Reverse(item.info.timestamp));
+    let cover = items.first()?.clone();
+
+    let dates = format_dates(date(&first), date(&cover));
+
+    let name = match place_name(&items) {
+        Some(place) => format!("{}, {}", place, dates),
+        None => dates,
+    };
+
+    let info = AlbumInfo {
+        id: album::event_album_id(kind, date(&first), ordinal),
+        name: name.into(),
+        description: None,
+        timestamp: cover.info.timestamp,
+    };
+
+    Some(Album {
+        info,
+        relpath: None,
+        items,
+        cover,
+    })
+}
+
+/// Names the city most items were taken in, or the country if no city dominates.
+fn place_name(items: &[MediaItem]) -> Option<ArcStr> {
+    let cities = items
+        .iter()
+        .filter_map(|item| item.location.as_ref()?.city.as_ref())
+        .collect_vec();
+
+    let city = dominant_city(items)?;
+
+    let count = cities.iter().filter(|other| same_city(other, city)).count();
+    if count * 2 >= cities.len() {
+        return Some(city.name.clone());
+    }
+
+    // the first name wins a tie, like for cities
+    let countries = cities.iter().map(|city| &city.country).counts();
+    countries
+        .into_iter()
+        .max_by_key(|(country, count)| (*count, Reverse(*country)))
+        .map(|(country, _)| country.clone())
+}
+
+/// The city most of the items were taken in.
+fn dominant_city(items: &[MediaItem]) -> Option<&City> {
+    let mut counts = HashMap::<(&str, &str), (&City, usize)>::new();
+
+    for city in items.iter().filter_map(|item| item.location.as_ref()?.city.as_ref()) {
+        counts.entry((&city.country, &city.name)).or_insert((city, 0)).1 += 1;
+    }
+
+    counts
+        .into_values()
+        .max_by_key(|(city, count)| (*count, Reverse((city.name.clone(), city.country.clone()))))
+        .map(|(city, _)| city)
+}
+
+fn same_city(a: &City, b: &City) -> bool {
+    a.name == b.name && a.country == b.country
+}
+
+fn date(item: &MediaItem) -> NaiveDate {
+    item.info.timestamp.date_naive()
+}
+
+/// Formats a range of dates, e.g. `3 Feb 2001` or `28 Feb – 2 Mar 2001`.
+fn format_dates(from: NaiveDate, to: NaiveDate) -> String {
+    if from == to {
+        from.format("%-d %b %Y").to_string()
+    } else if from.year() != to.year() {
+        format!("{} – {}", from.format("%-d %b %Y"), to.format("%-d %b %Y"))
+    } else if from.month() != to.month() {
+        format!("{} – {}", from.format("%-d %b"), to.format("%-d %b %Y"))
+    } else {
+        format!("{} – {}", from.format("%-d"), to.format("%-d %b %Y"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+    use crate::pica::testing::media_item;
+
+    const BERLIN: Option<(f32, f32)> = Some((52.52, 13.405));
+    const PARIS: Option<(f32, f32)> = Some((48.8566, 2.3522));
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn ids(album: &Album) -> Vec<u8> {
+        album.items.iter().map(|item| item.id.as_bytes()[0]).sorted().collect()
+    }
+
+    #[test]
+    fn test_trip_spanning_midnight() {
+        let mut items = (1..=5).map(|id| media_item(id, at(5, 1, 10, id as u32), BERLIN)).collect_vec();
+
+        items.extend([
+            media_item(6, at(6, 1, 22, 0), PARIS),
+            media_item(7, at(6, 1, 23, 30), PARIS),
+            media_item(8, at(6, 2, 0, 30), PARIS),
+            media_item(9, at(6, 2, 1, 0), PARIS),
+        ]);
+
+        let albums = by_event(items);
+        assert_eq!(albums.len(), 2);
+
+        assert_eq!(Kind::of(albums[0].info.id), Kind::Day);
+        assert_eq!(albums[0].info.name, "Berlin, 1 May 2024");
+        assert_eq!(ids(&albums[0]), [1, 2, 3, 4, 5]);
+
+        // the days on their own have too few items to be an album
+        assert_eq!(Kind::of(albums[1].info.id), Kind::Trip);
+        assert_eq!(albums[1].info.name, "Paris, 1 – 2 Jun 2024");
+        assert_eq!(ids(&albums[1]), [6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_gap_at_threshold() {
+        let items = [
+            media_item(1, at(5, 1, 9, 0), None),
+            media_item(2, at(5, 1, 12, 0), None),
+            media_item(3, at(5, 1, 15, 0), None),
+            media_item(4, at(5, 1, 18, 1), None),
+        ];
+
+        let days = split_days(&items);
+        assert_eq!(days.iter().map(|day| day.len()).collect_vec(), [3, 1]);
+
+        let albums = by_event(items.to_vec());
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].info.name, "1 May 2024");
+        assert_eq!(ids(&albums[0]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_single_item_day() {
+        let items = vec![media_item(1, at(5, 1, 12, 0), BERLIN)];
+
+        assert_eq!(split_days(&items).len(), 1);
+        assert!(by_event(items).is_empty());
+    }
+
+    #[test]
+    fn test_id_stays_when_adding_earlier_items() {
+        let day = (1..=3).map(|id| media_item(id, at(5, 1, 10, id as u32), None)).collect_vec();
+        let trip = (4..=7).map(|id| media_item(id, at(6, 1 + id as u32 % 2, 12, id as u32), PARIS)).collect_vec();
+        let home = (8..=13).map(|id| media_item(id, at(7, 1, 12, id as u32), BERLIN)).collect_vec();
+
+        let items = [day, trip, home].concat();
+        let albums = by_event(items.clone());
+
+        let kinds = albums.iter().map(|album| Kind::of(album.info.id)).collect_vec();
+        assert_eq!(kinds, [Kind::Day, Kind::Trip, Kind::Day]);
+
+        let mut more = items;
+        more.push(media_item(14, at(5, 1, 9, 0), None));
+        more.push(media_item(15, at(6, 1, 8, 0), PARIS));
+
+        let ids = |albums: &[Album]| albums.iter().map(|album| album.info.id).collect_vec();
+        let more = by_event(more);
+        assert_eq!(ids(&more), ids(&albums));
+        assert_eq!(more[0].items.len(), 4);
+        assert_eq!(more[1].items.len(), 5);
+
+        // other days on the same date get another id
+        let second = (16..=18).map(|id| media_item(id, at(5, 1, 20, id as u32), None));
+        let albums = by_event(albums[0].items.iter().cloned().chain(second).collect());
+        assert_eq!(albums.len(), 2);
+        assert_ne!(albums[0].info.id, albums[1].info.id);
+    }
+
+    #[test]
+    fn test_place_name_tie() {
+        let item = |id: u8, name: &str, country: &str| {
+            let mut item = media_item(id, at(5, 1, 12, 0), PARIS);
+            item.location.as_mut().unwrap().city = Some(City {
+                latitude: 0.0,
+                longitude: 0.0,
+                name: name.into(),
+                country: country.into(),
+                region: None,
+            });
+
+            item
+        };
+
+        let items = [item(1, "Paris", "France"), item(2, "Lyon", "France"), item(3, "Berlin", "Germany"), item(4, "Munich", "Germany")];
+
+        for _ in 0..20 {
+            assert_eq!(place_name(&items).as_deref(), Some("France"));
+        }
+    }
+
+    #[test]
+    fn test_format_dates() {
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+        assert_eq!(format_dates(date(2001, 2, 3), date(2001, 2, 3)), "3 Feb 2001");
+        assert_eq!(format_dates(date(2001, 2, 3), date(2001, 2, 5)), "3 – 5 Feb 2001");
+        assert_eq!(format_dates(date(2001, 2, 28), date(2001, 3, 2)), "28 Feb – 2 Mar 2001");
+        assert_eq!(format_dates(date(2023, 12, 30), date(2024, 1, 2)), "30 Dec 2023 – 2 Jan 2024");
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use itertools::Itertools;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tokio::task::block_in_place;
+use tokio::time::sleep;
+use tracing::{debug, info, instrument, warn};
+use walkdir::WalkDir;
+
+use crate::pica::config::{GeotagConfig, SourceConfig};
+use crate::pica::db::track::TrackPoint;
+use crate::pica::index::{file_is_hidden, timestamp_from_metadata};
+use crate::pica::store::MediaStore;
+use crate::pica::{db, City, Location, MediaItem, SourceId};
+
+/// Infers the location of media items without gps data from gpx tracks.
+#[derive(Clone)]
+pub struct Geotagger {
+    sources: Arc<HashMap<SourceId, GeotagSource>>,
+}
+
+struct GeotagSource {
+    config: GeotagConfig,
+
+    // users that can access the source, their uploaded tracks apply to it
+    access: Vec<String>,
+
+    // points of all tracks that apply to the source, ordered by time
+    points: RwLock<Arc<[TrackPoint]>>,
+}
+
+impl Geotagger {
+    pub fn new(sources: &[SourceConfig]) -> Self {
+        let sources = sources
+            .iter()
+            .filter_map(|source| {
+                let geotag = GeotagSource {
+                    config: source.geotag?,
+                    access: source.access.clone(),
+                    points: RwLock::new(Arc::new([])),
+                };
+
+                Some((SourceId::from(source.name.as_str()), geotag))
+            })
+            .collect();
+
+        Self {
+            sources: Arc::new(sources),
+        }
+    }
+
+    /// Reads the track points from the database.
+    #[instrument(skip_all)]
+    pub async fn reload(&self, db: &SqlitePool) -> Result<()> {
+        if self.sources.is_empty() {
+            return Ok(());
+        }
+
+        let points = {
+            let mut tx = db.begin().await?;
+            db::track::list_track_points(&mut tx).await?
+        };
+
+        for (id, source) in self.sources.iter() {
+            // tracks within a source apply to that source, uploaded tracks to all sources of the user
+            let applies = |row: &&db::track::TrackPointRow| match (&row.source, &row.owner) {
+                (Some(name), _) => name.as_str() == id.as_str(),
+                (None, Some(owner)) => source.access.contains(owner),
+                (None, None) => false,
+            };
+
+            let points = points.iter().filter(applies).map(|row| row.point).collect_vec();
+            debug!("Use {} track points for source {:?}", points.len(), id);
+
+            *source.points.write().unwrap() = points.into();
+        }
+
+        Ok(())
+    }
+
+    /// Infers the location of an item without gps data. A previously inferred
+    /// location is replaced, or removed if the tracks do not cover the item anymore.
+    pub fn apply(&self, mut item: MediaItem) -> Result<MediaItem> {
+        if item.location.as_ref().is_some_and(|location| !location.inferred) {
+            return Ok(item);
+        }
+
+        let Some(source) = self.sources.get(&item.source) else {
+            return Ok(item);
+        };
+
+        let points = source.points.read().unwrap().clone();
+
+        // the offset might not fit into a timestamp, leave the item as it is then
+        let offset = source.config.camera_offset_in_seconds;
+        let Some(timestamp) = TimeDelta::try_seconds(offset).and_then(|offset| item.info.timestamp.checked_add_signed(offset)) else {
+            debug!("Camera offset of {}s is out of range for {:?}", offset, item.relpath);
+            return Ok(item);
+        };
+
+        let max_gap = chrono::Duration::seconds(source.config.max_gap_in_seconds.into());
+
+        item.location = match locate(&points, timestamp, max_gap) {
+            Some((latitude, longitude)) => {
+                let city = pica_geo::nearest_city(latitude, longitude)?.map(City::from);
+
+                Some(Location {
+                    latitude,
+                    longitude,
+                    city,
+                    inferred: true,
+                })
+            }
+
+            None => None,
+        };
+
+        Ok(item)
+    }
+
+    /// Applies the current tracks to all items in the store.
+    #[instrument(skip_all)]
+    pub async fn apply_to_store(&self, store: &MediaStore) -> Result<()> {
+        let position = |item: &MediaItem| item.location.as_ref().map(|loc| (loc.latitude, loc.longitude));
+
+        let items = store.items().await;
+
+        // looking up the cities takes a while for a large library
+        let updated = block_in_place(|| {
+            let mut updated = Vec::new();
+
+            for item in items {
+                if !self.sources.contains_key(&item.source) {
+                    continue;
+                }
+
+                let previous = position(&item);
+                let item = self.apply(item)?;
+
+                if position(&item) != previous {
+                    updated.push(item);
+                }
+            }
+
+            anyhow::Ok(updated)
+        })?;
+
+        info!("Updated the inferred location of {} media items", updated.len());
+
+        for item in updated {
+            store.add(item).await;
+        }
+
+        Ok(())
+    }
+
+    /// Imports the gpx files within the source from time to time.
+    pub async fn watch(self, db: SqlitePool, store: MediaStore, source: SourceConfig, interval: Duration) {
+        loop {
+            match import_tracks(&db, &source).await {
+                Ok(false) => (),
+
+                Ok(true) => {
+                    let result = match self.reload(&db).await {
+                        Ok(()) => self.apply_to_store(&store).await,
+                        Err(err) => Err(err),
+                    };
+
+                    if let Err(err) = result {
+                        warn!("Failed to apply gpx tracks of source {:?}: {:?}", source.name, err);
+                    }
+                }
+
+                Err(err) => warn!("Failed to import gpx tracks of source {:?}: {:?}", source.name, err),
+            }
+
+            sleep(interval).await;
+        }
+    }
+}
+
+/// Interpolates the position at the given time between the surrounding track points.
+/// If only one of them is close enough, its position is taken as is.
+fn locate(points: &[TrackPoint], timestamp: DateTime<Utc>, max_gap: chrono::Duration) -> Option<(f32, f32)> {
+    let idx = points.partition_point(|point| point.timestamp <= timestamp);
+
+    let before = idx.checked_sub(1).and_then(|idx| points.get(idx));
+    let after = points.get(idx);
+
+    match (before, after) {
+        (Some(before), Some(after)) if after.timestamp - before.timestamp <= max_gap => {
+            let total = (after.timestamp - before.timestamp).num_milliseconds();
+            let elapsed = (timestamp - before.timestamp).num_milliseconds();
+
+            let f = match total {
+                0 => 0.0,
+                _ => elapsed as f32 / total as f32,
+            };
+
+            // take the short way across the antimeridian, e.g. from 179° to -179°
+            let longitude = before.longitude + normalize_longitude(after.longitude - before.longitude) * f;
+
+            Some((
+                before.latitude + (after.latitude - before.latitude) * f,
+                normalize_longitude(longitude),
+            ))
+        }
+
+        (before, after) => {
+            let gap = |point: &&TrackPoint| (point.timestamp - timestamp).abs();
+
+            before
+                .into_iter()
+                .chain(after)
+                .filter(|point| gap(point) <= max_gap)
+                .min_by_key(gap)
+                .map(|point| (point.latitude, point.longitude))
+        }
+    }
+}
+
+/// Wraps a longitude, or a difference of two, into [-180, 180)
+fn normalize_longitude(longitude: f32) -> f32 {
+    (longitude + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Imports new or modified gpx files within the source and removes tracks whose
+/// file is gone. Returns true if any track has changed.
+#[instrument(skip_all, fields(source = source.name))]
+async fn import_tracks(db: &SqlitePool, source: &SourceConfig) -> Result<bool> {
+    let files = block_in_place(|| scan_path_for_tracks(source));
+
+    let mut tx = db.begin().await?;
+    let mut known = db::track::list_source_tracks(&mut tx, &source.name).await?;
+
+    let mut changed = false;
+
+    for (path, mtime) in files {
+        if known.remove(&path).is_some_and(|(_, known)| known == mtime) {
+            continue;
+        }
+
+        info!("Import gpx track {:?}", path);
+
+        // a broken file is stored without points, so it is not read again until it changes
+        let points = match block_in_place(|| std::fs::read(&path)).map_err(anyhow::Error::from).and_then(|xml| parse_gpx(&xml)) {
+            Ok(points) => points,
+            Err(err) => {
+                warn!("Failed to parse gpx track {:?}: {:?}", path, err);
+                Vec::new()
+            }
+        };
+
+        db::track::store_source_track(&mut tx, &source.name, &path, mtime, &points).await?;
+        changed = true;
+    }
+
+    // tracks of deleted files
+    for (path, (id, _)) in known {
+        info!("Remove gpx track {:?}", path);
+        db::track::delete_track(&mut tx, id).await?;
+        changed = true;
+    }
+
+    tx.commit().await?;
+
+    Ok(changed)
+}
+
+fn scan_path_for_tracks(source: &SourceConfig) -> Vec<(PathBuf, DateTime<Utc>)> {
+    let files_iter = WalkDir::new(&source.path)
+        .same_file_system(true)
+        .follow_links(false)
+        .follow_root_links(false)
+        .into_iter();
+
+    files_iter
+        .filter_entry(|entry| !file_is_hidden(entry.file_name()))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            let ext = entry.path().extension().unwrap_or_default();
+            ext.eq_ignore_ascii_case("gpx")
+        })
+        .filter_map(|entry| {
+            let timestamp = timestamp_from_metadata(&entry.metadata().ok()?).ok()?;
+            Some((entry.into_path(), timestamp))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct Gpx {
+    #[serde(rename = "trk", default)]
+    tracks: Vec<GpxTrack>,
+}
+
+#[derive(Deserialize)]
+struct GpxTrack {
+    #[serde(rename = "trkseg", default)]
+    segments: Vec<GpxSegment>,
+}
+
+#[derive(Deserialize)]
+struct GpxSegment {
+    #[serde(rename = "trkpt", default)]
+    points: Vec<GpxPoint>,
+}
+
+#[derive(Deserialize)]
+struct GpxPoint {
+    #[serde(rename = "@lat")]
+    latitude: f32,
+
+    #[serde(rename = "@lon")]
+    longitude: f32,
+
+    time: Option<DateTime<Utc>>,
+}
+
+/// Parses the points of all tracks in a gpx file, ordered by time. Points
+/// without a timestamp can not be matched to media items and are skipped.
+pub fn parse_gpx(xml: &[u8]) -> Result<Vec<TrackPoint>> {
+    let gpx: Gpx = quick_xml::de::from_reader(xml)?;
+
+    let points = gpx
+        .tracks
+        .into_iter()
+        .flat_map(|track| track.segments)
+        .flat_map(|segment| segment.points)
+        .filter(|point| (-90.0..=90.0).contains(&point.latitude) && (-180.0..=180.0).contains(&point.longitude))
+        .filter_map(|point| {
+            Some(TrackPoint {
+                timestamp: point.time?,
+                latitude: point.latitude,
+                longitude: point.longitude,
+            })
+        })
+        .sorted_by_key(|point| point.timestamp)
+        .collect();
+
+    Ok(points)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn point(minute: u32, latitude: f32, longitude: f32) -> TrackPoint {
+        TrackPoint {
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 12, minute, 0).unwrap(),
+            latitude,
+            longitude,
+        }
+    }
+
+    #[test]
+    fn test_locate_interpolates() {
+        let points = [point(0, 10.0, 20.0), point(2, 12.0, 22.0)];
+        let timestamp = Utc.with_ymd_and_hms(2024, 5, 1, 12, 1, 0).unwrap();
+
+        assert_eq!(locate(&points, timestamp, TimeDelta::minutes(5)), Some((11.0, 21.0)));
+
+        // too far apart, the closer point is too far away as well
+        assert_eq!(locate(&points, timestamp, TimeDelta::seconds(30)), None);
+    }
+
+    #[test]
+    fn test_locate_across_antimeridian() {
+        let timestamp = Utc.with_ymd_and_hms(2024, 5, 1, 12, 1, 0).unwrap();
+
+        for (from, to, expected) in [(179.0, -179.0, -180.0), (-179.0, 179.0, -180.0), (178.0, -176.0, -179.0)] {
+            let points = [point(0, -17.0, from), point(2, -17.0, to)];
+            let (_, longitude) = locate(&points, timestamp, TimeDelta::minutes(5)).expect("location");
+            assert!((longitude - expected).abs() < 1e-4, "{} -> {}: {}", from, to, longitude);
+        }
+    }
+}
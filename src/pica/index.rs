@@ -1,6 +1,8 @@
-use std::collections::HashSet;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fs::Metadata;
+use std::fs::{File, Metadata};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::prelude::OsStrExt;
 use std::path::{Path, PathBuf};
@@ -8,20 +10,25 @@ use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, bail, Context, Result};
+use arcstr::ArcStr;
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use itertools::Itertools;
+use notify::event::{AccessKind, AccessMode, ModifyKind};
+use notify::{EventKind, RecursiveMode, Watcher};
 use pica_image::exif::ExifSummary;
 use regex::Regex;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio::task::block_in_place;
-use tokio::time::sleep;
+use tokio::time::{sleep, sleep_until, timeout};
 use tracing::{debug, info, instrument, warn};
 use walkdir::WalkDir;
 
 use crate::pica::accessor::MediaAccessor;
+use crate::pica::db::media::CachedMediaItem;
+use crate::pica::geotag::Geotagger;
 use crate::pica::queue::{QueueItem, ScanQueue};
 use crate::pica::store::MediaStore;
-use crate::pica::{db, MediaId, MediaInfo, MediaItem, SourceId};
+use crate::pica::{db, duplicates, Identity, MediaId, MediaInfo, MediaItem, Motion, SourceId, XmpInfo};
 use pica_image::MediaType;
 
 thread_local! {
@@ -43,22 +50,74 @@ pub struct ScanItem {
 
     // the media type, derived from the file name
     pub typ: MediaType,
+
+    // a video file with the same name, e.g. the video of a live photo
+    pub motion: Option<PathBuf>,
+
+    // an xmp file next to the media file, e.g. written by lightroom or darktable
+    pub xmp: Option<XmpSidecar>,
+
+    // how the id was derived
+    pub identity: Identity,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmpSidecar {
+    pub path: PathBuf,
+
+    // modified timestamp from metadata
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Clone, Eq, PartialEq)]
+struct KnownItem {
+    id: MediaId,
+    motion: Option<PathBuf>,
+    filesize: u64,
+    timestamp: DateTime<Utc>,
+
+    // modified timestamp of the xmp sidecar
+    xmp: Option<DateTime<Utc>>,
+}
+
+impl From<&ScanItem> for KnownItem {
+    fn from(item: &ScanItem) -> Self {
+        Self {
+            id: item.id,
+            motion: item.motion.clone(),
+            filesize: item.filesize,
+            timestamp: item.timestamp,
+            xmp: item.xmp.as_ref().map(|xmp| xmp.timestamp),
+        }
+    }
 }
 
 pub struct Scanner {
     root: PathBuf,
     queue: Arc<Mutex<ScanQueue>>,
-    known: HashSet<MediaId>,
+    // the media items we know about, by their path relative to the root
+    known: HashMap<PathBuf, KnownItem>,
+    // cached items whose files were missing at startup. They might have been moved
+    // and are purged from the cache if the first full scan does not find them.
+    missing: HashSet<MediaId>,
     source: SourceId,
+    identity: Identity,
 }
 
 impl Scanner {
-    pub fn new(root: impl Into<PathBuf>, queue: Arc<Mutex<ScanQueue>>, source: impl Into<SourceId>) -> Self {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        queue: Arc<Mutex<ScanQueue>>,
+        source: impl Into<SourceId>,
+        identity: Identity,
+    ) -> Self {
         Self {
             root: root.into(),
             queue,
-            known: HashSet::new(),
+            known: HashMap::new(),
+            missing: HashSet::new(),
             source: source.into(),
+            identity,
         }
     }
 
@@ -69,9 +128,7 @@ impl Scanner {
 
         debug!("Starting scan in {:?}", self.root);
 
-        let mut seen = HashSet::new();
-
-        let items = block_in_place(|| scan_path_for_items(&self.source, &self.root));
+        let items = block_in_place(|| scan_path_for_items(&self.source, &self.root, &self.root));
 
         info!(
             "Scan of {:?} finished in {:?}, found {} media files",
@@ -80,23 +137,300 @@ impl Scanner {
             items.len(),
         );
 
-        let items = collapse_raw_with_jpeg(items);
+        let items = collapse_live_photos(collapse_raw_with_jpeg(self.identify(items)));
+        self.apply_sidecars_written().await;
+
+        let mut seen = HashMap::new();
 
         for item in items {
-            seen.insert(item.id);
+            let known = KnownItem::from(&item);
+            seen.insert(item.relpath.clone(), known.clone());
 
-            if !self.known.contains(&item.id) {
+            if self.known.get(&item.relpath) != Some(&known) {
                 self.queue.lock().await.add(item);
             }
         }
 
         // remove the ones that we did not see this time
-        let deleted = self.known.difference(&seen);
+        let seen_ids: HashSet<_> = seen.values().map(|known| known.id).collect();
         let mut queue = self.queue.lock().await;
-        deleted.for_each(|id| queue.remove(*id));
+
+        self.known
+            .values()
+            .filter(|known| !seen_ids.contains(&known.id))
+            .for_each(|known| queue.remove(known.id));
+
+        std::mem::take(&mut self.missing)
+            .into_iter()
+            .filter(|id| !seen_ids.contains(id))
+            .for_each(|id| queue.purge(id));
 
         self.known = seen;
     }
+
+    /// Watches the root directory for changes and feeds them into the scan queue as they
+    /// happen. A full scan is done at the start and then every `rescan_interval` to pick up
+    /// any events we might have missed. Falls back to periodic scanning if the filesystem
+    /// can not be watched.
+    #[instrument(skip_all, fields(? self.root))]
+    pub async fn watch(mut self, rescan_interval: Duration) {
+        let (sender, mut events) = mpsc::unbounded_channel();
+
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event_changes_content(&event.kind) => _ = sender.send(event.paths),
+            Ok(_) => (),
+            Err(err) => warn!("Error while watching filesystem: {:?}", err),
+        });
+
+        // keep the watcher alive for as long as we are running
+        let _watcher = match watcher.and_then(|mut watcher| {
+            watcher.watch(&self.root, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        }) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!("Failed to watch {:?}, falling back to periodic scans: {:?}", self.root, err);
+                None
+            }
+        };
+
+        loop {
+            self.scan().await;
+
+            let next_scan = tokio::time::Instant::now() + rescan_interval;
+
+            loop {
+                let paths = tokio::select! {
+                    _ = sleep_until(next_scan) => break,
+                    paths = events.recv() => paths,
+                };
+
+                // the watcher is gone, only periodic scanning from now on
+                let Some(paths) = paths else {
+                    sleep_until(next_scan).await;
+                    break;
+                };
+
+                // a file copy produces a burst of events. Collect them until things calm down.
+                let mut changed: HashSet<_> = paths.into_iter().collect();
+                while let Ok(Some(paths)) = timeout(Duration::from_secs(1), events.recv()).await {
+                    changed.extend(paths);
+                }
+
+                self.apply_changes(changed).await;
+            }
+        }
+    }
+
+    /// Re-checks the given paths and updates the scan queue accordingly.
+    #[instrument(skip_all)]
+    async fn apply_changes(&mut self, mut paths: HashSet<PathBuf>) {
+        let mut items = Vec::new();
+        let mut removed = Vec::new();
+
+        block_in_place(|| {
+            // files with the same name might be collapsed into one item, e.g. a live photo
+            // and its video. Re-check them together.
+            let siblings = paths
+                .iter()
+                .filter_map(|path| path.strip_prefix(&self.root).ok())
+                .flat_map(|relpath| self.siblings(relpath))
+                .map(|relpath| self.root.join(relpath))
+                .collect_vec();
+
+            paths.extend(siblings);
+
+            for path in paths {
+                let Ok(relpath) = path.strip_prefix(&self.root) else {
+                    continue;
+                };
+
+                if relpath.iter().any(file_is_hidden) {
+                    continue;
+                }
+
+                if path.exists() {
+                    // new files or a directory that was moved into the source
+                    items.extend(scan_path_for_items(&self.source, &self.root, &path));
+                } else {
+                    // a deleted file, or a deleted directory
+                    removed.extend(self.known.keys().filter(|known| known.starts_with(relpath)).cloned());
+                }
+            }
+        });
+
+        let scanned: HashSet<_> = items.iter().map(|item| item.relpath.clone()).collect();
+        let items = collapse_live_photos(collapse_raw_with_jpeg(self.identify(items)));
+
+        // files that still exist but were collapsed into another item
+        removed.extend(
+            scanned
+                .into_iter()
+                .filter(|relpath| !items.iter().any(|item| &item.relpath == relpath)),
+        );
+
+        debug!("Filesystem changed, {} media files updated, {} removed", items.len(), removed.len());
+
+        self.apply_sidecars_written().await;
+        let mut queue = self.queue.lock().await;
+
+        let removed = removed.into_iter().filter_map(|relpath| self.known.remove(&relpath)).collect_vec();
+
+        // another copy of a file might still exist if ids are derived from the content
+        let remaining: HashSet<_> = self.known.values().map(|known| known.id).collect();
+
+        for known in removed {
+            if !remaining.contains(&known.id) {
+                queue.remove(known.id);
+            }
+        }
+
+        for item in items {
+            // skip raw files if we already know about a non-raw version of it
+            if item.typ.is_raw() && self.has_non_raw_version(&item.relpath) {
+                continue;
+            }
+
+            let known = KnownItem::from(&item);
+
+            let previous = self.known.insert(item.relpath.clone(), known.clone());
+            if previous.as_ref() == Some(&known) {
+                continue;
+            }
+
+            // the file changed and has a new id, remove the old version
+            if let Some(previous) = previous.filter(|previous| previous.id != known.id) {
+                queue.remove(previous.id);
+            }
+
+            queue.add(item);
+        }
+    }
+
+    /// Marks the given media items as known, e.g. after loading them from the cache.
+    /// They will not be queued again unless their files change.
+    pub fn assume_known<'a>(&mut self, items: impl IntoIterator<Item = &'a CachedMediaItem>) {
+        for CachedMediaItem { item, mtime, xmp_mtime, .. } in items {
+            // relpath might have been stored including the root directory
+            let relpath = item.relpath.strip_prefix(&self.root).unwrap_or(&item.relpath);
+
+            let motion = match &item.motion {
+                Some(Motion::Sidecar(path)) => Some(path.as_ref().clone()),
+                _ => None,
+            };
+
+            let known = KnownItem {
+                id: item.id,
+                motion,
+                filesize: item.filesize,
+                timestamp: mtime.unwrap_or_default(),
+                xmp: *xmp_mtime,
+            };
+
+            self.known.insert(relpath.to_owned(), known);
+        }
+    }
+
+    /// Our own changes to xmp sidecars are no reason to index a file again.
+    async fn apply_sidecars_written(&mut self) {
+        let written = self.queue.lock().await.take_sidecars_written(&self.root);
+
+        for (path, mtime) in written {
+            let Some(known) = path.strip_prefix(&self.root).ok().and_then(|relpath| self.known.get_mut(relpath)) else {
+                continue;
+            };
+
+            known.xmp = Some(mtime);
+        }
+    }
+
+    /// Marks the given cached media items as missing, see [load_cached]. Unless the next
+    /// full scan finds them under another path, they are purged from the cache.
+    pub fn assume_missing(&mut self, ids: impl IntoIterator<Item = MediaId>) {
+        self.missing.extend(ids);
+    }
+
+    /// Derives the ids of the scanned items from their content, if configured. Files that
+    /// did not change since we have last seen them keep their id without being read again.
+    fn identify(&self, items: Vec<ScanItem>) -> Vec<ScanItem> {
+        if self.identity == Identity::Path {
+            return items;
+        }
+
+        block_in_place(|| {
+            items
+                .into_iter()
+                .filter_map(|mut item| {
+                    let known = self
+                        .known
+                        .get(&item.relpath)
+                        .filter(|known| known.filesize == item.filesize && known.timestamp == item.timestamp);
+
+                    item.id = match known {
+                        Some(known) => known.id,
+                        None => match content_id(&item.source, &item.path, item.filesize) {
+                            Ok(id) => id,
+                            Err(err) => {
+                                warn!("Failed to hash file {:?}: {:?}", item.path, err);
+                                return None;
+                            }
+                        },
+                    };
+
+                    item.identity = Identity::Content;
+
+                    Some(item)
+                })
+                .collect()
+        })
+    }
+
+    /// Returns the files that have the same name as `relpath`, ignoring the extension,
+    /// including the videos attached to them. For an xmp sidecar, this includes the file it
+    /// belongs to.
+    fn siblings(&self, relpath: &Path) -> Vec<PathBuf> {
+        let base = relpath.with_extension("");
+
+        // darktable names its sidecars `name.jpg.xmp`
+        let is_sidecar = relpath.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xmp"));
+
+        let is_sibling = |path: &Path| path.with_extension("") == base || (is_sidecar && path == base);
+
+        let known = self.known.iter().filter(|(known, _)| is_sibling(known)).flat_map(|(known, item)| {
+            let motion = item.motion.as_ref().and_then(|path| path.strip_prefix(&self.root).ok());
+            [Some(known.as_path()), motion].into_iter().flatten().map(Path::to_path_buf)
+        });
+
+        // files we do not know about, e.g. a raw file that was collapsed into its jpeg
+        let parent = self.root.join(relpath).parent().map(Path::to_path_buf).unwrap_or_default();
+        let unknown = std::fs::read_dir(parent)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.path().strip_prefix(&self.root).ok().map(Path::to_path_buf))
+            .filter(|path| is_sibling(path));
+
+        known.chain(unknown).filter(|path| path != relpath).unique().collect()
+    }
+
+    fn has_non_raw_version(&self, relpath: &Path) -> bool {
+        let base = relpath.with_extension("");
+
+        self.known.keys().any(|known| {
+            let is_raw = MediaType::from_path(known).is_some_and(|typ| typ.is_raw());
+            !is_raw && known.with_extension("") == base
+        })
+    }
+}
+
+/// Filters out events that do not change the content of a source,
+/// e.g. the indexer reading the files.
+fn event_changes_content(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        _ => true,
+    }
 }
 
 #[instrument(skip_all)]
@@ -121,10 +455,41 @@ fn collapse_raw_with_jpeg(items: Vec<ScanItem>) -> impl Iterator<Item = ScanItem
     })
 }
 
-/// Returns an iterator over files we might want to index
+/// Attaches videos to images of the same name, e.g. the video of a live photo,
+/// instead of listing them as separate items.
+#[instrument(skip_all)]
+fn collapse_live_photos(items: impl IntoIterator<Item = ScanItem>) -> Vec<ScanItem> {
+    let (videos, mut items): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| item.typ.is_video());
+
+    let mut videos_by_name = HashMap::new();
+
+    for video in videos {
+        match videos_by_name.entry(video.relpath.with_extension("")) {
+            Entry::Vacant(entry) => _ = entry.insert(video),
+            // more than one video with this name, keep it as a separate item
+            Entry::Occupied(_) => items.push(video),
+        }
+    }
+
+    for item in &mut items {
+        if item.typ.is_video() {
+            continue;
+        }
+
+        if let Some(video) = videos_by_name.remove(&item.relpath.with_extension("")) {
+            item.motion = Some(video.path);
+        }
+    }
+
+    items.extend(videos_by_name.into_values());
+    items
+}
+
+/// Returns the files below `path` we might want to index.
+/// `path` must either be `root` or a file or directory within `root`.
 #[instrument]
-fn scan_path_for_items(source: &SourceId, root: &Path) -> Vec<ScanItem> {
-    let files_iter = WalkDir::new(root)
+fn scan_path_for_items(source: &SourceId, root: &Path, path: &Path) -> Vec<ScanItem> {
+    let files_iter = WalkDir::new(path)
         .same_file_system(true)
         .follow_links(false)
         .follow_root_links(false)
@@ -146,21 +511,11 @@ fn scan_path_for_items(source: &SourceId, root: &Path) -> Vec<ScanItem> {
             let meta = entry.metadata().with_context(ctx)?;
             let timestamp = timestamp_from_metadata(&meta).with_context(ctx)?;
 
-            // hash the relative path and file size into an id
-            let hash = {
-                let mut hasher = sha1_smol::Sha1::new();
-                hasher.update(source.as_bytes());
-                hasher.update(relpath.as_os_str().as_bytes());
-                hasher.update(meta.size().to_be_bytes().as_slice());
-                hasher.digest().bytes()
-            };
-
             let typ = MediaType::from_path(&relpath).ok_or_else(|| anyhow!("no media type in {:?}", relpath))?;
 
-            // build id from hash
-            let mut bytes = [0_u8; 8];
-            bytes.copy_from_slice(&hash[..8]);
-            let id = MediaId::from(bytes);
+            let id = path_id(source, &relpath, meta.size());
+
+            let xmp = find_xmp_sidecar(entry.path());
 
             Ok(ScanItem {
                 id,
@@ -170,6 +525,9 @@ fn scan_path_for_items(source: &SourceId, root: &Path) -> Vec<ScanItem> {
                 source: source.clone(),
                 filesize: meta.size(),
                 path: entry.into_path(),
+                motion: None,
+                xmp,
+                identity: Identity::Path,
             })
         })
         .flatten_ok()
@@ -188,16 +546,201 @@ fn scan_path_for_items(source: &SourceId, root: &Path) -> Vec<ScanItem> {
     items
 }
 
-fn file_is_hidden(name: &OsStr) -> bool {
+/// Hashes the relative path and file size into an id
+fn path_id(source: &SourceId, relpath: &Path, filesize: u64) -> MediaId {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(source.as_bytes());
+    hasher.update(relpath.as_os_str().as_bytes());
+    hasher.update(filesize.to_be_bytes().as_slice());
+    id_from_hash(hasher.digest().bytes())
+}
+
+/// Hashes the file size and the start and end of the file into an id
+fn content_id(source: &SourceId, path: &Path, filesize: u64) -> Result<MediaId> {
+    const SAMPLE_SIZE: u64 = 64 * 1024;
+
+    let mut fp = File::open(path)?;
+
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(source.as_bytes());
+    hasher.update(filesize.to_be_bytes().as_slice());
+
+    let mut buf = Vec::new();
+    (&mut fp).take(SAMPLE_SIZE).read_to_end(&mut buf)?;
+    hasher.update(&buf);
+
+    if filesize > 2 * SAMPLE_SIZE {
+        buf.clear();
+        fp.seek(SeekFrom::Start(filesize - SAMPLE_SIZE))?;
+        fp.take(SAMPLE_SIZE).read_to_end(&mut buf)?;
+        hasher.update(&buf);
+    }
+
+    Ok(id_from_hash(hasher.digest().bytes()))
+}
+
+fn id_from_hash(hash: [u8; 20]) -> MediaId {
+    let mut bytes = [0_u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    MediaId::from(bytes)
+}
+
+pub fn file_is_hidden(name: &OsStr) -> bool {
     name.to_str().map(|name| name.starts_with('.')).unwrap_or(false)
 }
 
 fn file_is_indexable(name: &Path) -> bool {
-    match MediaType::from_path(name) {
-        Some(MediaType::GenericVideo) => false,
-        Some(_) => true,
-        None => false,
+    MediaType::from_path(name).is_some()
+}
+
+/// The path of a darktable style sidecar, the extension appended to the file name.
+pub fn xmp_sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
+
+/// Looks for an xmp sidecar next to a media file. darktable appends `.xmp` to the file
+/// name, lightroom replaces the extension.
+pub fn find_xmp_sidecar(path: &Path) -> Option<XmpSidecar> {
+    let candidates = ["xmp", "XMP"]
+        .into_iter()
+        .flat_map(|ext| [xmp_sidecar_path(path, ext), path.with_extension(ext)]);
+
+    for path in candidates {
+        let Ok(meta) = std::fs::metadata(&path) else {
+            continue;
+        };
+
+        if !meta.is_file() {
+            continue;
+        }
+
+        let timestamp = timestamp_from_metadata(&meta).ok()?;
+        return Some(XmpSidecar { path, timestamp });
+    }
+
+    None
+}
+
+/// Loads all cached media items of a source whose files still exist
+/// unchanged below `root`. Outdated entries are removed from the cache.
+///
+/// With content based ids, files that are missing might have been moved while we were
+/// not watching. They are kept in the cache, including their thumbnails, so that the
+/// scanner can pick them up under their new path. Their ids are returned separately,
+/// see [Scanner::assume_missing].
+#[instrument(skip_all, fields(? source))]
+pub async fn load_cached(
+    db: &sqlx::sqlite::SqlitePool,
+    source: &SourceId,
+    root: &Path,
+    identity: Identity,
+) -> Result<(Vec<CachedMediaItem>, Vec<MediaId>)> {
+    let cached = {
+        let mut tx = db.begin().await?;
+        db::media::list_media_items(&mut tx, source).await?
+    };
+
+    let mut items = Vec::new();
+    let mut missing = Vec::new();
+    let mut outdated = Vec::new();
+    let mut backfill = Vec::new();
+
+    block_in_place(|| {
+        for mut cached in cached {
+            let item = &cached.item;
+
+            let path = root.join(item.relpath.as_ref());
+            let meta = std::fs::metadata(&path);
+
+            if identity == Identity::Content && meta.is_err() {
+                missing.push(item.id);
+                continue;
+            }
+
+            let mtime = meta
+                .ok()
+                .filter(|meta| meta.size() == item.filesize)
+                .and_then(|meta| timestamp_from_metadata(&meta).ok());
+
+            match (mtime, cached.mtime) {
+                (Some(mtime), Some(cached_mtime)) if mtime == cached_mtime => items.push(cached),
+
+                // indexed before we started tracking the modification time
+                (Some(mtime), None) => {
+                    backfill.push((item.id, mtime));
+                    cached.mtime = Some(mtime);
+                    items.push(cached);
+                }
+
+                _ => outdated.push(item.id),
+            }
+        }
+    });
+
+    let mut tx = db.begin().await?;
+
+    for id in outdated {
+        db::media::invalidate_media_item(&mut tx, id).await?;
+    }
+
+    for (id, mtime) in backfill {
+        db::media::update_mtime(&mut tx, id, mtime).await?;
     }
+
+    tx.commit().await?;
+
+    Ok((items, missing))
+}
+
+/// Changes the ids of cached media items of a source that were derived using a different
+/// [Identity], so that switching the identity does not require indexing everything again.
+#[instrument(skip_all, fields(? source, ? identity))]
+pub async fn migrate_identity(db: &sqlx::sqlite::SqlitePool, source: &SourceId, root: &Path, identity: Identity) -> Result<()> {
+    let cached = {
+        let mut tx = db.begin().await?;
+        db::media::list_media_items_with_other_identity(&mut tx, source, identity).await?
+    };
+
+    if cached.is_empty() {
+        return Ok(());
+    }
+
+    info!("Migrating {} cached media items to {:?} based ids", cached.len(), identity);
+
+    let mut rekey = Vec::new();
+
+    block_in_place(|| {
+        for cached in cached {
+            let item = cached.item;
+
+            let path = root.join(item.relpath.as_ref());
+            let relpath = path.strip_prefix(root).unwrap_or(&path);
+
+            let id = match identity {
+                Identity::Path => Ok(path_id(source, relpath, item.filesize)),
+                Identity::Content => content_id(source, &path, item.filesize),
+            };
+
+            // files that are gone are removed from the cache when loading it
+            match id {
+                Ok(id) => rekey.push((item.id, id)),
+                Err(err) => debug!("Not migrating {:?}: {:?}", path, err),
+            }
+        }
+    });
+
+    let mut tx = db.begin().await?;
+
+    for (id, new_id) in rekey {
+        db::media::rekey_media_item(&mut tx, id, new_id, identity).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
 }
 
 pub struct Indexer {
@@ -205,6 +748,7 @@ pub struct Indexer {
     queue: Arc<Mutex<ScanQueue>>,
     accessor: Option<MediaAccessor>,
     store: MediaStore,
+    geotagger: Geotagger,
 }
 
 impl Indexer {
@@ -213,12 +757,14 @@ impl Indexer {
         queue: Arc<Mutex<ScanQueue>>,
         store: MediaStore,
         accessor: Option<MediaAccessor>,
+        geotagger: Geotagger,
     ) -> Self {
         Self {
             db,
             queue,
             accessor,
             store,
+            geotagger,
         }
     }
 
@@ -241,6 +787,14 @@ impl Indexer {
                     self.store.remove(item).await;
                 }
 
+                Some(QueueItem::Purge(item)) => {
+                    self.store.remove(item).await;
+
+                    if let Err(err) = self.purge(item).await {
+                        warn!("Purging failed for {}: {:?}", item, err);
+                    }
+                }
+
                 None => {
                     // no more data in queue, wait a moment before trying again
                     sleep(Duration::from_millis(100)).await;
@@ -250,6 +804,13 @@ impl Indexer {
         }
     }
 
+    async fn purge(&self, id: MediaId) -> Result<()> {
+        let mut tx = self.db.begin().await?;
+        db::media::invalidate_media_item(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     #[instrument(skip_all, fields(? item.relpath))]
     async fn index_one(&self, item: &ScanItem) -> Result<()> {
         let existing_error = {
@@ -265,6 +826,18 @@ impl Indexer {
 
         match result {
             Ok(media) => {
+                let correction = {
+                    let mut tx = self.db.begin().await?;
+                    db::correction::read_correction(&mut tx, media.id).await?
+                };
+
+                let media = match correction {
+                    Some(correction) => media.corrected(&correction)?,
+                    None => media,
+                };
+
+                let media = self.geotagger.apply(media)?;
+
                 // put the media item into the store
                 let count = self.store.add(media).await;
                 debug!("Added item to library, total items: {}", count);
@@ -288,22 +861,81 @@ impl Indexer {
             db::media::read_media_item(&mut tx, item.id).await?
         };
 
-        if let Some(media) = cached {
-            // ensure that media exists
-            if let Some(accessor) = &self.accessor {
-                debug!("Create thumbnails");
-                accessor.thumb(&media).await?;
-                accessor.preview(&media).await?;
+        match cached {
+            Some(cached) if cached.complete && cached.mtime.is_none_or(|mtime| mtime == item.timestamp) => {
+                let mut media = cached.item;
+                let mut changed = false;
+
+                // with content based ids, the file might have been moved or renamed
+                if *media.relpath != item.path {
+                    debug!("Update path of media item from {:?}", media.relpath);
+                    let (motion, hashes, xmp) = (media.motion.take(), media.hashes, media.xmp);
+                    media = MediaItem::from_media_info(media.id, media.source, item.path.clone(), media.filesize, media.info)?;
+                    media.motion = motion;
+                    media.hashes = hashes;
+                    media.xmp = xmp;
+                    changed = true;
+                }
+
+                // the sidecar might have been added, removed or edited
+                if cached.xmp_mtime != item.xmp.as_ref().map(|xmp| xmp.timestamp) {
+                    debug!("Update xmp metadata of media item from {:?}", item.xmp);
+                    media.xmp = block_in_place(|| read_xmp(item));
+                    changed = true;
+                }
+
+                // a live photo video might have been added or removed next to the file
+                if !matches!(media.motion, Some(Motion::Embedded { .. })) {
+                    let motion = item.motion.clone().map(|path| Motion::Sidecar(path.into()));
+
+                    if media.motion != motion {
+                        debug!("Update motion of media item to {:?}", motion);
+                        media.motion = motion;
+                        changed = true;
+                    }
+                }
+
+                if changed {
+                    let mut tx = self.db.begin().await?;
+                    let xmp_mtime = item.xmp.as_ref().map(|xmp| xmp.timestamp);
+                    db::media::store_media_item(&mut tx, &media, item.timestamp, xmp_mtime, item.identity).await?;
+                    tx.commit().await?;
+                }
+
+                // ensure that media exists
+                if let Some(accessor) = &self.accessor {
+                    debug!("Create thumbnails");
+                    accessor.thumb(&media).await?;
+                    accessor.preview(&media).await?;
+                }
+
+                return Ok(media);
+            }
+
+            Some(cached) if cached.mtime.is_none_or(|mtime| mtime == item.timestamp) => {
+                // parse again, but keep the thumbnails
+                debug!("Media item was indexed by an older version, parse it again");
+            }
+
+            Some(_) => {
+                debug!("File was modified since it was indexed");
+                let mut tx = self.db.begin().await?;
+                db::media::invalidate_media_item(&mut tx, item.id).await?;
+                tx.commit().await?;
             }
 
-            return Ok(media);
+            None => (),
         }
 
-        let item = parse(item).await.with_context(|| "parse to MediaItem")?;
+        let mtime = item.timestamp;
+        let xmp_mtime = item.xmp.as_ref().map(|xmp| xmp.timestamp);
+        let identity = item.identity;
+        let mut item = parse(item).await.with_context(|| "parse to MediaItem")?;
+        item.hashes = Some(block_in_place(|| duplicates::hash_media(&item))?);
 
         // store the parsed item in the database
         let mut tx = self.db.begin().await?;
-        db::media::store_media_item(&mut tx, &item).await?;
+        db::media::store_media_item(&mut tx, &item, mtime, xmp_mtime, identity).await?;
         tx.commit().await?;
 
         if let Some(accessor) = &self.accessor {
@@ -319,25 +951,77 @@ impl Indexer {
 /// Parses a [ScanItem] into a new [MediaItem]
 #[instrument(skip_all, fields(? item.relpath))]
 async fn parse(item: &ScanItem) -> Result<MediaItem> {
-    let path = block_in_place(|| pica_image::get(&item.path))?;
-
-    let reader = image::ImageReader::open(path.as_ref())?;
-
-    let (width, height) = reader.with_guessed_format()?.into_dimensions()?;
+    match item.typ {
+        MediaType::GenericVideo => parse_video(item).await,
+        _ => parse_image(item).await,
+    }
+}
 
-    let exif = match block_in_place(|| pica_image::exif::parse_exif(&path)) {
-        Ok(Some(exif)) => Some(exif),
-        Ok(None) => None,
+#[instrument(skip_all, fields(? item.relpath))]
+async fn parse_video(item: &ScanItem) -> Result<MediaItem> {
+    let video = match block_in_place(|| pica_image::video::parse_video_info(&item.path)) {
+        Ok(video) => video,
         Err(err) => {
-            warn!("Failed to parse exif data of {:?}: {:?}", path, err);
+            warn!("Failed to parse video header of {:?}: {:?}", item.path, err);
             None
         }
     };
 
-    // if we have information about the orientation, rotate width + height
-    let (width, height) = match &exif {
-        Some(ExifSummary { orientation, .. }) if orientation.transposed() => (height, width),
-        _ => (width, height),
+    let (width, height) = match &video {
+        Some(video) => (video.width, video.height),
+        None => {
+            // unknown container format, take the size of the poster frame
+            let poster = block_in_place(|| pica_image::get(&item.path))?;
+            image::ImageReader::new(poster.open()?).with_guessed_format()?.into_dimensions()?
+        }
+    };
+
+    let timestamp = timestamp_from_path(&item.relpath)
+        .or_else(|| video.as_ref()?.timestamp)
+        .unwrap_or(item.timestamp);
+
+    let info = MediaInfo {
+        timestamp,
+        width,
+        height,
+        latitude: None,
+        longitude: None,
+        duration: video.and_then(|video| video.duration),
+        camera: None,
+    };
+
+    let mut media = MediaItem::from_media_info(item.id, item.source.clone(), item.path.clone(), item.filesize, info)?;
+    media.xmp = block_in_place(|| read_xmp(item));
+
+    Ok(media)
+}
+
+#[instrument(skip_all, fields(? item.relpath))]
+async fn parse_image(item: &ScanItem) -> Result<MediaItem> {
+    let (width, height, exif) = match item.typ {
+        // heif files can not be decoded by the image crate, the container
+        // knows the size of the image including its rotation
+        MediaType::Heif => {
+            let info = block_in_place(|| pica_image::heif::parse_heif_info(&item.path))?;
+            (info.width, info.height, parse_exif_or_warn(&item.path))
+        }
+
+        _ => {
+            let file = block_in_place(|| pica_image::get(&item.path))?;
+
+            let reader = image::ImageReader::new(file.open()?);
+
+            let (width, height) = reader.with_guessed_format()?.into_dimensions()?;
+
+            // for raw files, this reads the metadata of the raw file and not of its preview
+            let exif = parse_exif_or_warn(&item.path);
+
+            // if we have information about the orientation, rotate width + height
+            match &exif {
+                Some(ExifSummary { orientation, .. }) if orientation.transposed() => (height, width, exif),
+                _ => (width, height, exif),
+            }
+        }
     };
 
     let timestamp = timestamp_from_path(&item.relpath)
@@ -350,12 +1034,116 @@ async fn parse(item: &ScanItem) -> Result<MediaItem> {
         height,
         latitude: exif.as_ref().and_then(|exif| exif.latitude),
         longitude: exif.as_ref().and_then(|exif| exif.longitude),
+        duration: None,
+        camera: exif.as_ref().and_then(|exif| exif.camera.as_deref()).map(ArcStr::from),
+    };
+
+    let mut media = MediaItem::from_media_info(item.id, item.source.clone(), item.path.clone(), item.filesize, info)?;
+
+    media.motion = match &item.motion {
+        Some(path) => Some(Motion::Sidecar(path.clone().into())),
+        None => block_in_place(|| embedded_motion(item)),
     };
 
-    MediaItem::from_media_info(item.id, item.source.clone(), item.path.clone(), item.filesize, info)
+    media.xmp = block_in_place(|| read_xmp(item));
+
+    Ok(media)
+}
+
+fn parse_exif_or_warn(path: &Path) -> Option<ExifSummary> {
+    match block_in_place(|| pica_image::exif::parse_exif(path)) {
+        Ok(exif) => exif,
+        Err(err) => {
+            warn!("Failed to parse exif data of {:?}: {:?}", path, err);
+            None
+        }
+    }
+}
+
+fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_ascii_lowercase().as_str(), "jpg" | "jpeg"))
+}
+
+/// Looks for a video embedded at the end of a jpeg file, e.g. a google motion photo.
+fn embedded_motion(item: &ScanItem) -> Option<Motion> {
+    if !is_jpeg(&item.path) {
+        return None;
+    }
+
+    let result = (|| -> Result<Option<Motion>> {
+        let mut fp = BufReader::new(File::open(&item.path)?);
+
+        let Some(motion) = ultrahdr_rs::motion_photo(&mut fp)? else {
+            return Ok(None);
+        };
+
+        let Some(offset) = item.filesize.checked_sub(motion.offset_from_end) else {
+            bail!("motion photo offset {} out of bounds", motion.offset_from_end);
+        };
+
+        // the video must be an iso media file starting with a ftyp box
+        let mut header = [0_u8; 8];
+        fp.seek(SeekFrom::Start(offset))?;
+        fp.read_exact(&mut header)?;
+
+        if &header[4..] != b"ftyp" {
+            bail!("no video found at motion photo offset {}", offset);
+        }
+
+        let len = motion.len.min(item.filesize - offset);
+
+        Ok(Some(Motion::Embedded { offset, len }))
+    })();
+
+    result.unwrap_or_else(|err| {
+        warn!("Failed to read motion photo of {:?}: {:?}", item.path, err);
+        None
+    })
+}
+
+/// Reads the xmp metadata embedded into a jpeg file and the one of its sidecar.
+/// Values from the sidecar win, that is where editors write their changes to.
+fn read_xmp(item: &ScanItem) -> XmpInfo {
+    let mut xmp = ultrahdr_rs::XmpMetadata::default();
+
+    if is_jpeg(&item.path) {
+        let embedded = File::open(&item.path)
+            .map_err(anyhow::Error::from)
+            .and_then(|fp| ultrahdr_rs::embedded_xmp_metadata(BufReader::new(fp)));
+
+        match embedded {
+            Ok(embedded) => xmp = embedded.unwrap_or_default(),
+            Err(err) => warn!("Failed to read embedded xmp of {:?}: {:?}", item.path, err),
+        }
+    }
+
+    if let Some(sidecar) = &item.xmp {
+        let parsed = std::fs::read(&sidecar.path)
+            .map_err(anyhow::Error::from)
+            .and_then(ultrahdr_rs::xmp_metadata);
+
+        match parsed {
+            Ok(sidecar) => {
+                xmp.rating = sidecar.rating.or(xmp.rating);
+                xmp.label = sidecar.label.or(xmp.label);
+                xmp.description = sidecar.description.or(xmp.description);
+
+                // tags pica wrote itself are not keywords of the file
+                if !sidecar.keywords.is_empty() {
+                    xmp.keywords = sidecar.keywords.into_iter().filter(|keyword| !sidecar.tags.contains(keyword)).collect();
+                }
+            }
+
+            Err(err) => warn!("Failed to read xmp sidecar {:?}: {:?}", sidecar.path, err),
+        }
+    }
+
+    XmpInfo::from(xmp)
 }
 
-fn timestamp_from_metadata(metadata: &Metadata) -> Result<DateTime<Utc>> {
+pub fn timestamp_from_metadata(metadata: &Metadata) -> Result<DateTime<Utc>> {
     let modified = metadata.modified().or_else(|_| metadata.created())?;
     let epoch_seconds = modified.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
 
@@ -395,3 +1183,107 @@ fn timestamp_from_path(path: &Path) -> Option<DateTime<Utc>> {
 
     android.or_else(whatsapp)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Source {
+        // the root must not be hidden, unlike the temporary directory itself
+        _dir: tempfile::TempDir,
+        root: PathBuf,
+        queue: Arc<Mutex<ScanQueue>>,
+        scanner: Scanner,
+    }
+
+    impl Source {
+        fn new() -> Result<Self> {
+            let dir = tempfile::tempdir()?;
+            let root = dir.path().join("photos");
+            std::fs::create_dir(&root)?;
+
+            let queue = Arc::new(Mutex::new(ScanQueue::default()));
+            let scanner = Scanner::new(&root, queue.clone(), "photos", Identity::Path);
+
+            Ok(Self { _dir: dir, root, queue, scanner })
+        }
+
+        /// Writes a file large enough to not be skipped by the scanner
+        fn write(&self, name: &str) -> Result<PathBuf> {
+            let path = self.root.join(name);
+            std::fs::write(&path, vec![0x42; 16 * 1024])?;
+            Ok(path)
+        }
+
+        /// Returns the paths of the queued items and the ids of the removed ones
+        async fn queued(&self) -> (Vec<PathBuf>, Vec<MediaId>) {
+            let mut queue = self.queue.lock().await;
+
+            let mut added = Vec::new();
+            let mut removed = Vec::new();
+
+            while let Some(queued) = queue.poll() {
+                match queued {
+                    QueueItem::Add(item) => added.push(item.relpath),
+                    QueueItem::Remove(id) | QueueItem::Purge(id) => removed.push(id),
+                }
+            }
+
+            (added, removed)
+        }
+
+        fn id(&self, relpath: &str) -> MediaId {
+            self.scanner.known[Path::new(relpath)].id
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_jpeg_added_after_raw() -> Result<()> {
+        let mut source = Source::new()?;
+
+        source.write("IMG_0001.NEF")?;
+        source.scanner.scan().await;
+        assert_eq!(source.queued().await, (vec![PathBuf::from("IMG_0001.NEF")], vec![]));
+
+        // the raw file is replaced by the jpeg
+        let raw = source.id("IMG_0001.NEF");
+        let jpeg = source.write("IMG_0001.JPG")?;
+        source.scanner.apply_changes(HashSet::from([jpeg.clone()])).await;
+        assert_eq!(source.queued().await, (vec![PathBuf::from("IMG_0001.JPG")], vec![raw]));
+
+        source.scanner.scan().await;
+        assert_eq!(source.queued().await, (vec![], vec![]));
+
+        // and shows up again once the jpeg is gone
+        let id = source.id("IMG_0001.JPG");
+        std::fs::remove_file(&jpeg)?;
+        source.scanner.apply_changes(HashSet::from([jpeg])).await;
+        assert_eq!(source.queued().await, (vec![PathBuf::from("IMG_0001.NEF")], vec![id]));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_raw_added_after_jpeg() -> Result<()> {
+        let mut source = Source::new()?;
+
+        let jpeg = source.write("IMG_0001.JPG")?;
+        source.scanner.scan().await;
+        assert_eq!(source.queued().await, (vec![PathBuf::from("IMG_0001.JPG")], vec![]));
+
+        // the jpeg is kept, the raw file is not added
+        let raw = source.write("IMG_0001.NEF")?;
+        source.scanner.apply_changes(HashSet::from([raw])).await;
+        assert_eq!(source.queued().await, (vec![], vec![]));
+
+        source.scanner.scan().await;
+        assert_eq!(source.queued().await, (vec![], vec![]));
+
+        let id = source.id("IMG_0001.JPG");
+        std::fs::remove_file(&jpeg)?;
+        source.scanner.apply_changes(HashSet::from([jpeg])).await;
+        assert_eq!(source.queued().await, (vec![PathBuf::from("IMG_0001.NEF")], vec![id]));
+
+        Ok(())
+    }
+}
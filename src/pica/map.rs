@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use itertools::Itertools;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::pica::{MediaId, MediaItem};
+
+/// Highest zoom level with its own clusters, items closer than this are always merged.
+pub const MAX_ZOOM: u8 = 20;
+
+// size of a cluster cell at the given zoom level, in tiles of 256 pixels
+const CELLS_PER_TILE: u32 = 4;
+
+// web mercator can not display anything closer to the poles than this
+const MAX_LATITUDE: f64 = 85.051_13;
+
+/// A group of media items close to each other at a zoom level.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cluster {
+    // center of all items in the cluster
+    pub latitude: f32,
+    pub longitude: f32,
+
+    pub count: usize,
+
+    // the newest item of the cluster, shown as its preview
+    pub cover: MediaId,
+}
+
+/// Groups the items with a location into clusters. Items are put into a grid of
+/// cells of a fixed size on a web mercator map, so clusters are stable while
+/// the map is moved and only change when zooming in or out.
+#[instrument(skip_all)]
+pub fn cluster<'a>(items: impl IntoIterator<Item = &'a MediaItem>, zoom: u8) -> Vec<Cluster> {
+    let cells = CELLS_PER_TILE << zoom.min(MAX_ZOOM);
+
+    let mut grid = HashMap::<(u32, u32), Vec<&MediaItem>>::new();
+
+    for item in items {
+        let Some(location) = &item.location else {
+            continue;
+        };
+
+        let cell = cell_of(location.latitude, location.longitude, cells);
+        grid.entry(cell).or_default().push(item);
+    }
+
+    grid.into_iter()
+        .sorted_by_key(|(cell, _)| *cell)
+        .map(|(_, items)| {
+            let count = items.len();
+
+            let (latitude, longitude) = items
+                .iter()
+                .filter_map(|item| item.location.as_ref())
+                .fold((0.0, 0.0), |(lat, lon), location| {
+                    (lat + f64::from(location.latitude), lon + f64::from(location.longitude))
+                });
+
+            let cover = items
+                .iter()
+                .max_by_key(|item| (item.info.timestamp, item.id))
+                .map(|item| item.id)
+                .expect("cluster is not empty");
+
+            Cluster {
+                latitude: (latitude / count as f64) as f32,
+                longitude: (longitude / count as f64) as f32,
+                count,
+                cover,
+            }
+        })
+        .collect()
+}
+
+/// The grid cell of a location, as x from west to east and y from north to south.
+fn cell_of(latitude: f32, longitude: f32, cells: u32) -> (u32, u32) {
+    let latitude = f64::from(latitude).clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let longitude = f64::from(longitude).clamp(-180.0, 180.0);
+
+    let x = (longitude + 180.0) / 360.0;
+    let y = (1.0 - (latitude.tan() + 1.0 / latitude.cos()).ln() / PI) / 2.0;
+
+    let scale = |value: f64| ((value * f64::from(cells)) as u32).min(cells - 1);
+
+    (scale(x), scale(y))
+}
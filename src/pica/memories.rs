@@ -0,0 +1,162 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Datelike, NaiveDate};
+use itertools::Itertools;
+use tracing::instrument;
+
+use crate::pica::{duplicates, MediaId, MediaItem};
+
+/// The items taken on the same calendar day in an earlier year.
+pub struct Memory {
+    pub year: i32,
+    pub items: Vec<MediaItem>,
+}
+
+pub struct Options {
+    // order the items of a year by their score, highest first. Otherwise oldest first.
+    pub ranked: bool,
+
+    // keep only the best item of each set of near duplicates
+    pub skip_duplicates: bool,
+}
+
+/// Finds the items taken on the same day and month as `date` in the years before,
+/// newest year first. Items taken on the 29th of February show up on the 28th in
+/// years without one.
+#[instrument(skip_all, fields(? date))]
+pub fn on_this_day(items: Vec<MediaItem>, date: NaiveDate, opts: Options, score: impl Fn(&MediaItem) -> u32) -> Vec<Memory> {
+    let mut years = BTreeMap::<i32, Vec<MediaItem>>::new();
+
+    for item in items {
+        let taken = item.info.timestamp.date_naive();
+
+        if taken.year() < date.year() && same_day(taken, date) {
+            years.entry(taken.year()).or_default().push(item);
+        }
+    }
+
+    years
+        .into_iter()
+        .rev()
+        .map(|(year, mut items)| {
+            items.sort_by_key(|item| (item.info.timestamp, item.id));
+
+            if opts.ranked {
+                items.sort_by_key(|item| Reverse(score(item)));
+            }
+
+            if opts.skip_duplicates {
+                let skipped = skipped_duplicates(&items, &score);
+                items.retain(|item| !skipped.contains(&item.id));
+            }
+
+            Memory { year, items }
+        })
+        .collect()
+}
+
+fn same_day(taken: NaiveDate, date: NaiveDate) -> bool {
+    let leap_day = |date: NaiveDate| date.month() == 2 && date.day() == 29;
+
+    if leap_day(taken) && date.month() == 2 && date.day() == 28 {
+        return NaiveDate::from_ymd_opt(date.year(), 2, 29).is_none();
+    }
+
+    taken.month() == date.month() && taken.day() == date.day()
+}
+
+/// Returns all near duplicates except the one with the highest score of each set.
+fn skipped_duplicates(items: &[MediaItem], score: impl Fn(&MediaItem) -> u32) -> HashSet<MediaId> {
+    duplicates::find_duplicates(items.iter().cloned())
+        .into_iter()
+        .flat_map(|group| {
+            // groups are sorted by preference, keep the first of the best scored items
+            let keep = group.items.iter().position_min_by_key(|item| Reverse(score(item)));
+            group.items.into_iter().enumerate().filter(move |(idx, _)| Some(*idx) != keep).map(|(_, item)| item.id)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::pica::testing::media_item;
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn item(id: u8, year: i32, month: u32, day: u32) -> MediaItem {
+        media_item(id, Utc.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap(), None)
+    }
+
+    fn years(items: Vec<MediaItem>, date: NaiveDate) -> Vec<(i32, Vec<u8>)> {
+        let opts = Options {
+            ranked: false,
+            skip_duplicates: false,
+        };
+
+        on_this_day(items, date, opts, |_| 0)
+            .into_iter()
+            .map(|memory| {
+                (
+                    memory.year,
+                    memory.items.iter().map(|item| item.id.as_bytes()[0]).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_day_leap_day() {
+        // a leap day shows up on the 28th in years without one
+        assert!(same_day(day(2020, 2, 29), day(2023, 2, 28)));
+        assert!(!same_day(day(2020, 2, 29), day(2023, 3, 1)));
+
+        // and only on the 29th in years with one
+        assert!(same_day(day(2020, 2, 29), day(2024, 2, 29)));
+        assert!(!same_day(day(2020, 2, 29), day(2024, 2, 28)));
+
+        // other days are not moved
+        assert!(same_day(day(2020, 2, 28), day(2024, 2, 28)));
+        assert!(!same_day(day(2020, 2, 28), day(2024, 2, 29)));
+        assert!(!same_day(day(2021, 3, 1), day(2024, 2, 29)));
+    }
+
+    #[test]
+    fn test_on_this_day() {
+        let items = vec![
+            item(1, 2020, 2, 29),
+            item(2, 2021, 2, 28),
+            item(3, 2022, 2, 28),
+            item(4, 2022, 3, 1),
+            item(5, 2023, 2, 28),
+            item(6, 2024, 2, 28),
+        ];
+
+        assert_eq!(
+            years(items.clone(), day(2023, 2, 28)),
+            [(2022, vec![3]), (2021, vec![2]), (2020, vec![1])]
+        );
+        assert_eq!(years(items.clone(), day(2024, 2, 29)), [(2020, vec![1])]);
+        assert_eq!(
+            years(items, day(2025, 2, 28)),
+            [
+                (2024, vec![6]),
+                (2023, vec![5]),
+                (2022, vec![3]),
+                (2021, vec![2]),
+                (2020, vec![1])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skips_current_year() {
+        let items = vec![item(1, 2023, 6, 1), item(2, 2024, 6, 1), item(3, 2025, 6, 1)];
+        assert_eq!(years(items, day(2024, 6, 1)), [(2023, vec![1])]);
+    }
+}
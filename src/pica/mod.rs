@@ -4,6 +4,7 @@ use std::marker::PhantomData;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, ensure};
 use arcstr::ArcStr;
@@ -14,15 +15,25 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 
+use crate::pica::db::correction::Correction;
+
 pub mod album;
 pub mod index;
 
 pub mod accessor;
 pub mod config;
 pub mod db;
+pub mod duplicates;
+pub mod event;
+pub mod geotag;
+pub mod map;
+pub mod memories;
 pub mod queue;
 pub mod scale;
+pub mod search;
+pub mod sidecar;
 pub mod store;
+pub mod tag;
 
 #[derive(SerializeDisplay, DeserializeFromStr)]
 pub struct Id<T> {
@@ -60,6 +71,18 @@ impl<T> PartialEq for Id<T> {
 
 impl<T> Eq for Id<T> {}
 
+impl<T> PartialOrd for Id<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Id<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
 impl<T> From<[u8; 8]> for Id<T> {
     fn from(value: [u8; 8]) -> Self {
         Self {
@@ -113,6 +136,12 @@ pub struct MediaInfo {
     pub height: u32,
     pub latitude: Option<f32>,
     pub longitude: Option<f32>,
+
+    // playback duration of a video
+    pub duration: Option<Duration>,
+
+    // make and model of the camera that took the picture
+    pub camera: Option<ArcStr>,
 }
 
 /// A [MediaItem] references a media file on the filesystem.
@@ -126,9 +155,115 @@ pub struct MediaItem {
     pub typ: MediaType,
     pub info: MediaInfo,
     pub location: Option<Location>,
+
+    // a short video clip that belongs to this image
+    pub motion: Option<Motion>,
+
+    // hashes of the content, used to find duplicates
+    pub hashes: Option<MediaHashes>,
+
+    // rating, keywords, etc. from the xmp data of the file and its sidecar
+    pub xmp: XmpInfo,
+}
+
+/// Metadata written by photo management tools like lightroom or darktable.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct XmpInfo {
+    // from 1 to 5 stars
+    pub rating: Option<u8>,
+
+    // a color label, e.g. "Red"
+    pub label: Option<ArcStr>,
+
+    pub keywords: Arc<[ArcStr]>,
+    pub description: Option<ArcStr>,
+}
+
+impl From<ultrahdr_rs::XmpMetadata> for XmpInfo {
+    fn from(xmp: ultrahdr_rs::XmpMetadata) -> Self {
+        Self {
+            rating: xmp.rating,
+            label: xmp.label.map(ArcStr::from),
+            keywords: xmp.keywords.into_iter().map(ArcStr::from).collect(),
+            description: xmp.description.map(ArcStr::from),
+        }
+    }
+}
+
+/// Hashes of a media file, see [duplicates].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MediaHashes {
+    // sha1 of the complete file
+    pub content: [u8; 20],
+
+    // difference hash of the image, similar images have similar hashes.
+    // This is not available for videos.
+    pub perceptual: Option<u64>,
+}
+
+/// How the [MediaId] of a file is derived.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Identity {
+    // hash of source, relative path and file size. Renaming a file changes its id.
+    #[default]
+    Path,
+
+    // hash of source, file size and the start and end of the file.
+    // The id stays the same if a file is renamed or moved.
+    Content,
+}
+
+impl Identity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Identity::Path => "path",
+            Identity::Content => "content",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Motion {
+    // a video appended to the image file, e.g. a google motion photo
+    Embedded { offset: u64, len: u64 },
+
+    // a video file next to the image, e.g. an iphone live photo
+    Sidecar(Arc<PathBuf>),
 }
 
 impl MediaItem {
+    pub fn is_video(&self) -> bool {
+        self.typ.is_video()
+    }
+
+    /// Applies a manual correction on top of the parsed metadata. The city
+    /// is looked up again for a corrected location.
+    pub fn corrected(self, correction: &Correction) -> anyhow::Result<Self> {
+        let mut info = self.info;
+
+        if let Some(timestamp) = correction.timestamp {
+            info.timestamp = timestamp;
+        } else if let Some(shift) = correction.shift {
+            info.timestamp = chrono::TimeDelta::try_seconds(shift)
+                .and_then(|shift| info.timestamp.checked_add_signed(shift))
+                .ok_or_else(|| anyhow!("shifting {} by {}s is out of range", info.timestamp, shift))?;
+        }
+
+        if let (Some(latitude), Some(longitude)) = (correction.latitude, correction.longitude) {
+            info.latitude = Some(latitude);
+            info.longitude = Some(longitude);
+        }
+
+        let relpath = self.relpath.as_ref().clone();
+        let mut item = Self::from_media_info(self.id, self.source, relpath, self.filesize, info)?;
+        item.motion = self.motion;
+        item.hashes = self.hashes;
+        item.xmp = self.xmp;
+
+        Ok(item)
+    }
+
     pub fn from_media_info(
         id: MediaId,
         source: SourceId,
@@ -153,6 +288,7 @@ impl MediaItem {
                     latitude,
                     longitude,
                     city,
+                    inferred: false,
                 })
             }
 
@@ -168,6 +304,9 @@ impl MediaItem {
             location,
             source,
             relpath: relpath.into(),
+            motion: None,
+            hashes: None,
+            xmp: XmpInfo::default(),
         })
     }
 }
@@ -177,6 +316,9 @@ pub struct Location {
     pub latitude: f32,
     pub longitude: f32,
     pub city: Option<City>,
+
+    // the location was not recorded by the camera but inferred, e.g. from a gpx track
+    pub inferred: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -185,6 +327,9 @@ pub struct City {
     pub longitude: f32,
     pub name: ArcStr,
     pub country: ArcStr,
+
+    // the state or region within the country
+    pub region: Option<ArcStr>,
 }
 
 impl From<&pica_geo::City> for City {
@@ -192,6 +337,7 @@ impl From<&pica_geo::City> for City {
         Self {
             name: value.name.clone(),
             country: value.country.clone(),
+            region: value.region.clone(),
             latitude: value.latitude,
             longitude: value.longitude,
         }
@@ -204,6 +350,7 @@ pub type AlbumId = Id<Album>;
 pub struct AlbumInfo {
     pub id: AlbumId,
     pub name: ArcStr,
+    pub description: Option<ArcStr>,
     pub timestamp: DateTime<Utc>,
 }
 
@@ -220,3 +367,26 @@ pub struct Album {
     // the albums preview image
     pub cover: MediaItem,
 }
+
+#[cfg(test)]
+pub mod testing {
+    use chrono::{DateTime, Utc};
+
+    use crate::pica::{MediaId, MediaInfo, MediaItem};
+
+    /// A media item without a file behind it, taken at the given time and place.
+    pub fn media_item(id: u8, timestamp: DateTime<Utc>, location: Option<(f32, f32)>) -> MediaItem {
+        let info = MediaInfo {
+            timestamp,
+            width: 4000,
+            height: 3000,
+            latitude: location.map(|(latitude, _)| latitude),
+            longitude: location.map(|(_, longitude)| longitude),
+            duration: None,
+            camera: None,
+        };
+
+        let relpath = format!("Album/{}.jpg", id);
+        MediaItem::from_media_info(MediaId::from([id; 8]), "test".into(), relpath.into(), 1024, info).expect("media item")
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use priority_queue::PriorityQueue;
 
 use crate::pica::index::ScanItem;
@@ -9,12 +10,18 @@ use crate::pica::MediaId;
 pub enum QueueItem {
     Add(ScanItem),
     Remove(MediaId),
+
+    // removes the item from the cache too, its file is gone for good
+    Purge(MediaId),
 }
 
 #[derive(Default)]
 pub struct ScanQueue {
     queue: PriorityQueue<MediaId, chrono::DateTime<Utc>>,
     queued: HashMap<MediaId, QueueItem>,
+
+    // modification times of the xmp sidecars we wrote ourselves, by the path of their media file
+    sidecars: HashMap<PathBuf, DateTime<Utc>>,
 }
 
 impl ScanQueue {
@@ -31,6 +38,23 @@ impl ScanQueue {
         self.queued.insert(item, QueueItem::Remove(item));
     }
 
+    pub fn purge(&mut self, item: MediaId) {
+        self.remove(item);
+        self.queued.insert(item, QueueItem::Purge(item));
+    }
+
+    /// Remembers that we wrote the xmp sidecar of the media file at `path`, so that
+    /// the scanner does not take it for a change made by another tool.
+    pub fn sidecar_written(&mut self, path: PathBuf, mtime: DateTime<Utc>) {
+        self.sidecars.insert(path, mtime);
+    }
+
+    /// Takes the sidecars we wrote for media files below `root`.
+    pub fn take_sidecars_written(&mut self, root: &Path) -> Vec<(PathBuf, DateTime<Utc>)> {
+        let paths = self.sidecars.keys().filter(|path| path.starts_with(root)).cloned().collect::<Vec<_>>();
+        paths.into_iter().filter_map(|path| self.sidecars.remove_entry(&path)).collect()
+    }
+
     pub fn poll(&mut self) -> Option<QueueItem> {
         let (id, _) = self.queue.pop()?;
         self.queued.remove(&id)
@@ -1,9 +1,6 @@
 use std::ffi::OsString;
-use std::fmt::Debug;
 use std::fs;
-use std::fs::File;
 use std::io::BufReader;
-use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Result};
 use image::codecs::jpeg::JpegEncoder;
@@ -14,6 +11,7 @@ use tokio::task::spawn_blocking;
 use tracing::{debug_span, instrument, Instrument};
 
 use pica_image::exif::{parse_exif, Orientation};
+use pica_image::MediaFileRef;
 use ultrahdr_rs::{Jpeg, SegmentKind};
 
 pub struct Image {
@@ -56,10 +54,10 @@ impl MediaScaler {
     }
 
     /// Generate a resized version of an image
-    #[instrument(skip_all, fields(? path, size))]
-    pub async fn scaled(&self, path: impl AsRef<Path> + Debug, size: u32) -> Result<Image> {
+    #[instrument(skip_all, fields(? file, size))]
+    pub async fn scaled(&self, file: MediaFileRef, size: u32) -> Result<Image> {
         // run resize in a different task to not block the executor
-        let bytes = self.resize(path.as_ref(), size)
+        let bytes = self.resize(file, size)
             .instrument(debug_span!("resize"))
             .await?;
 
@@ -70,8 +68,7 @@ impl MediaScaler {
         Ok(thumb)
     }
 
-    async fn resize(&self, path: impl AsRef<Path>, size: u32) -> Result<Vec<u8>> {
-        let path = PathBuf::from(path.as_ref());
+    async fn resize(&self, file: MediaFileRef, size: u32) -> Result<Vec<u8>> {
         let options = self.options.clone();
 
         let span = debug_span!("resize-inner");
@@ -80,15 +77,15 @@ impl MediaScaler {
             let _entered = span.entered();
 
             if options.prefer_ultra_hdr {
-                if ultrahdr_rs::is_ultrahdr(BufReader::new(File::open(&path)?))? {
-                    return resize_ultrahdr(&path, size);
+                if ultrahdr_rs::is_ultrahdr(file.open()?)? {
+                    return resize_ultrahdr(&file, size);
                 }
             }
 
             if options.use_image_magick {
-                resize_imagemagick(&path, &options.image_type, size)
+                resize_imagemagick(&file, &options.image_type, size)
             } else {
-                resize_rust(&path, &options.image_type, size)
+                resize_rust(&file, &options.image_type, size)
             }
         };
 
@@ -98,8 +95,8 @@ impl MediaScaler {
 
 /// Resizes the image using image magick.
 #[instrument(skip_all, fields(? source, format, size))]
-fn resize_imagemagick(source: &Path, format: &ImageType, size: u32) -> Result<Vec<u8>> {
-    use std::process::Command;
+fn resize_imagemagick(source: &MediaFileRef, format: &ImageType, size: u32) -> Result<Vec<u8>> {
+    use std::process::{Command, Stdio};
 
     let format = match format {
         ImageType::Jpeg => "jpeg",
@@ -114,17 +111,40 @@ fn resize_imagemagick(source: &Path, format: &ImageType, size: u32) -> Result<Ve
     target_avif.push(":");
     target_avif.push(target.as_ref());
 
-    let res = Command::new("convert")
-        .arg("-auto-orient")
+    // embedded images are piped into image magick. They do not carry the orientation
+    // of the original file, so we need to apply it ourselves.
+    let (input, orient) = match source {
+        MediaFileRef::Embedded { .. } => {
+            let rotate = parse_exif(source.path()).ok().flatten().map(|r| r.orientation);
+            (OsString::from("-"), imagemagick_orientation(rotate))
+        }
+
+        _ => (source.path().into(), ["-auto-orient"].as_slice()),
+    };
+
+    let mut child = Command::new("convert")
+        .arg(input)
+        .args(orient)
         .arg("-resize")
         .arg(format!("{}x{}", size, size))
         .arg("-quality")
         .arg("60")
         .arg("-strip")
         .arg("-interlace").arg("Plane")
-        .arg(source)
         .arg(target_avif)
-        .output()?;
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    // closes stdin after writing, so image magick sees the end of the image
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?;
+    if let MediaFileRef::Embedded { .. } = source {
+        std::io::copy(&mut source.open()?, &mut stdin)?;
+    }
+
+    drop(stdin);
+
+    let res = child.wait_with_output()?;
 
     if !res.status.success() {
         return Err(anyhow!("resize failed with status {:?}", res.status.code()));
@@ -136,13 +156,27 @@ fn resize_imagemagick(source: &Path, format: &ImageType, size: u32) -> Result<Ve
     Ok(bytes)
 }
 
+fn imagemagick_orientation(orientation: Option<Orientation>) -> &'static [&'static str] {
+    match orientation {
+        Some(Orientation::FlipH) => &["-flop"],
+        Some(Orientation::Rotate180) => &["-rotate", "180"],
+        Some(Orientation::FlipHRotate180) => &["-flip"],
+        Some(Orientation::FlipHRotate270) => &["-transpose"],
+        Some(Orientation::Rotate90) => &["-rotate", "90"],
+        Some(Orientation::FlipHRotate90) => &["-transverse"],
+        Some(Orientation::Rotate270) => &["-rotate", "270"],
+        _ => &[],
+    }
+}
+
 #[instrument(skip_all, fields(? source, format, size))]
-fn resize_rust(source: &Path, format: &ImageType, size: u32) -> Result<Vec<u8>> {
-    let rotate = parse_exif(source).ok().flatten().map(|r| r.orientation);
+fn resize_rust(source: &MediaFileRef, format: &ImageType, size: u32) -> Result<Vec<u8>> {
+    // take the orientation from the original file, e.g. the raw file and not its preview
+    let rotate = parse_exif(source.path()).ok().flatten().map(|r| r.orientation);
 
     let mut image = {
         let _span = debug_span!("read image");
-        ImageReader::open(source)?.with_guessed_format()?.decode()?
+        ImageReader::new(source.open()?).with_guessed_format()?.decode()?
     };
 
     image = match rotate {
@@ -189,13 +223,12 @@ fn resize_rust(source: &Path, format: &ImageType, size: u32) -> Result<Vec<u8>>
 }
 
 #[instrument(skip_all, fields(? source, format, size))]
-fn resize_ultrahdr(source: &Path, size: u32) -> Result<Vec<u8>> {
+fn resize_ultrahdr(source: &MediaFileRef, size: u32) -> Result<Vec<u8>> {
     // check if the image needs rotating
-    let rotate = parse_exif(source).ok().flatten().map(|r| r.orientation);
+    let rotate = parse_exif(source.path()).ok().flatten().map(|r| r.orientation);
 
     // load the ultra hdr image to memory
-    let r = BufReader::new(File::open(source)?);
-    let uhdr = ultrahdr_rs::UltraHDR::from_reader(r)?;
+    let uhdr = ultrahdr_rs::UltraHDR::from_reader(source.open()?)?;
 
     let primary = resize_jpeg(&uhdr.primary, &rotate, size)?;
     let mut gainmap = resize_jpeg(&uhdr.gainmap, &rotate, size / 4)?;
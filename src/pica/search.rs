@@ -0,0 +1,323 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure};
+use arcstr::ArcStr;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde_with::DeserializeFromStr;
+use tracing::instrument;
+
+use crate::pica::{album, AlbumId, MediaId, MediaItem, SourceId};
+
+/// A search over media items. All given filters must match.
+#[derive(Default)]
+pub struct Query {
+    // words that must each appear in the name, album, place, camera, keywords,
+    // label or description of an item
+    pub text: Option<String>,
+
+    // first and last day, both inclusive
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+
+    pub name: Option<String>,
+    pub album: Option<AlbumId>,
+    pub source: Option<SourceId>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub camera: Option<String>,
+
+    // an xmp keyword or color label, ignoring case
+    pub keyword: Option<String>,
+    pub label: Option<String>,
+
+    pub bbox: Option<BoundingBox>,
+
+    pub sort: Sort,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    #[default]
+    Newest,
+    Oldest,
+    Name,
+}
+
+/// An area on the map, given as `west,south,east,north` in degrees. The
+/// box crosses the antimeridian if west is larger than east.
+#[derive(Clone, Copy, Debug, DeserializeFromStr)]
+pub struct BoundingBox {
+    pub west: f32,
+    pub south: f32,
+    pub east: f32,
+    pub north: f32,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, latitude: f32, longitude: f32) -> bool {
+        let longitude_matches = match self.west <= self.east {
+            true => (self.west..=self.east).contains(&longitude),
+            false => longitude >= self.west || longitude <= self.east,
+        };
+
+        longitude_matches && (self.south..=self.north).contains(&latitude)
+    }
+}
+
+impl FromStr for BoundingBox {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f32> = s.split(',').map(|value| value.trim().parse()).collect::<Result<_, _>>()?;
+
+        let [west, south, east, north] = values[..] else {
+            return Err(anyhow!("expected west,south,east,north, got {:?}", s));
+        };
+
+        ensure!(south <= north, "south must not be larger than north in {:?}", s);
+
+        Ok(Self { west, south, east, north })
+    }
+}
+
+/// Returns the items matching the query, sorted as requested.
+#[instrument(skip_all)]
+pub fn search(conf: &album::Config, items: Vec<MediaItem>, query: &Query) -> Vec<MediaItem> {
+    let words = query
+        .text
+        .iter()
+        .flat_map(|text| text.split_whitespace())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    // only group by albums if we need to
+    let albums: HashMap<MediaId, (AlbumId, ArcStr)> = match query.album.is_some() || !words.is_empty() {
+        true => album::by_directory(conf, items.iter().cloned())
+            .into_iter()
+            .flat_map(|album| {
+                let info = album.info;
+                album.items.into_iter().map(move |item| (item.id, (info.id, info.name.clone())))
+            })
+            .collect(),
+
+        false => HashMap::new(),
+    };
+
+    let mut items = items
+        .into_iter()
+        .filter(|item| matches(query, item, albums.get(&item.id)))
+        .filter(|item| {
+            let album = albums.get(&item.id).map(|(_, name)| name.as_str());
+            words.iter().all(|word| matches_text(item, album, word))
+        })
+        .collect::<Vec<_>>();
+
+    match query.sort {
+        Sort::Newest => items.sort_by_key(|item| Reverse(item.info.timestamp)),
+        Sort::Oldest => items.sort_by_key(|item| item.info.timestamp),
+        Sort::Name => items.sort_by(|a, b| a.name.cmp(&b.name).then(a.info.timestamp.cmp(&b.info.timestamp))),
+    }
+
+    items
+}
+
+fn matches(query: &Query, item: &MediaItem, album: Option<&(AlbumId, ArcStr)>) -> bool {
+    let date = item.info.timestamp.date_naive();
+    let city = item.location.as_ref().and_then(|location| location.city.as_ref());
+
+    query.from.is_none_or(|from| date >= from)
+        && query.to.is_none_or(|to| date <= to)
+        && query.name.as_ref().is_none_or(|name| contains_ignore_case(&item.name, name))
+        && query.album.is_none_or(|id| album.is_some_and(|(album, _)| *album == id))
+        && query.source.as_ref().is_none_or(|source| item.source == *source)
+        && query.city.as_ref().is_none_or(|name| city.is_some_and(|city| city.name.eq_ignore_ascii_case(name)))
+        && query.country.as_ref().is_none_or(|name| city.is_some_and(|city| city.country.eq_ignore_ascii_case(name)))
+        && query.region.as_ref().is_none_or(|name| {
+            city.and_then(|city| city.region.as_ref()).is_some_and(|region| region.eq_ignore_ascii_case(name))
+        })
+        && query.camera.as_ref().is_none_or(|camera| {
+            item.info.camera.as_ref().is_some_and(|model| contains_ignore_case(model, camera))
+        })
+        && query.keyword.as_ref().is_none_or(|keyword| {
+            item.xmp.keywords.iter().any(|known| known.eq_ignore_ascii_case(keyword))
+        })
+        && query.label.as_ref().is_none_or(|label| {
+            item.xmp.label.as_ref().is_some_and(|known| known.eq_ignore_ascii_case(label))
+        })
+        && query.bbox.is_none_or(|bbox| {
+            item.location.as_ref().is_some_and(|location| bbox.contains(location.latitude, location.longitude))
+        })
+}
+
+fn matches_text(item: &MediaItem, album: Option<&str>, word: &str) -> bool {
+    let city = item.location.as_ref().and_then(|location| location.city.as_ref());
+
+    [
+        Some(item.name.as_str()),
+        album,
+        city.map(|city| city.name.as_str()),
+        city.map(|city| city.country.as_str()),
+        city.and_then(|city| city.region.as_deref()),
+        item.info.camera.as_deref(),
+        item.xmp.label.as_deref(),
+        item.xmp.description.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(item.xmp.keywords.iter().map(ArcStr::as_str))
+    .any(|value| value.to_lowercase().contains(word))
+}
+
+fn contains_ignore_case(value: &str, needle: &str) -> bool {
+    value.to_lowercase().contains(&needle.to_lowercase())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::pica::testing::media_item;
+    use crate::pica::City;
+
+    fn item(id: u8, day: (u32, u32), city: Option<(&str, f32, f32)>, keywords: &[&str]) -> MediaItem {
+        let timestamp = Utc.with_ymd_and_hms(2024, day.0, day.1, 12, id.into(), 0).unwrap();
+
+        let mut item = media_item(id, timestamp, city.map(|(_, latitude, longitude)| (latitude, longitude)));
+        item.xmp.keywords = keywords.iter().map(|keyword| ArcStr::from(*keyword)).collect();
+
+        if let (Some(location), Some((name, latitude, longitude))) = (item.location.as_mut(), city) {
+            location.city = Some(City {
+                latitude,
+                longitude,
+                name: name.into(),
+                country: "XX".into(),
+                region: None,
+            });
+        }
+
+        item
+    }
+
+    fn items() -> Vec<MediaItem> {
+        let mut sunset = item(1, (6, 1), Some(("Berlin", 52.52, 13.40)), &["Holiday", "Beach"]);
+        sunset.info.camera = Some("Canon EOS R5".into());
+        sunset.xmp.description = Some("Sunset at the lake".into());
+
+        vec![
+            sunset,
+            item(2, (6, 1), Some(("Paris", 48.86, 2.35)), &["Holiday"]),
+            item(3, (7, 15), Some(("Berlin", 52.52, 13.40)), &["holiday"]),
+            item(4, (6, 1), None, &["Holiday"]),
+            item(5, (6, 2), Some(("Berlin", 52.52, 13.40)), &[]),
+        ]
+    }
+
+    fn search_ids(query: Query) -> Vec<u8> {
+        let conf = album::Config {
+            classify_as_album: regex::bytes::Regex::new(".*").unwrap(),
+            strip_title: None,
+        };
+
+        let query = Query {
+            sort: Sort::Oldest,
+            ..query
+        };
+
+        search(&conf, items(), &query).iter().map(|item| item.id.as_bytes()[0]).collect()
+    }
+
+    fn text(text: &str) -> Query {
+        Query {
+            text: Some(text.into()),
+            ..Query::default()
+        }
+    }
+
+    #[test]
+    fn test_text_words() {
+        // every word must match, in any field
+        assert_eq!(search_ids(text("  sunset\tberlin ")), [1]);
+        assert_eq!(search_ids(text("sunset paris")), [] as [u8; 0]);
+        assert_eq!(search_ids(text("eos beach")), [1]);
+
+        // the album name
+        assert_eq!(search_ids(text("album")), [1, 2, 4, 5, 3]);
+
+        // no words match everything
+        assert_eq!(search_ids(text(" ")), [1, 2, 4, 5, 3]);
+        assert_eq!(search_ids(Query::default()), [1, 2, 4, 5, 3]);
+    }
+
+    #[test]
+    fn test_ignores_case() {
+        assert_eq!(search_ids(text("SUNSET Lake")), [1]);
+        assert_eq!(search_ids(text("HOLIDAY")), [1, 2, 4, 3]);
+
+        let query = Query {
+            keyword: Some("HOLIDAY".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1, 2, 4, 3]);
+
+        let query = Query {
+            city: Some("berlin".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1, 5, 3]);
+
+        let query = Query {
+            camera: Some("eos".into()),
+            name: Some("1.JPG".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1]);
+    }
+
+    #[test]
+    fn test_combined_filters() {
+        let query = Query {
+            keyword: Some("holiday".into()),
+            from: NaiveDate::from_ymd_opt(2024, 6, 1),
+            to: NaiveDate::from_ymd_opt(2024, 6, 30),
+            city: Some("Berlin".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1]);
+
+        // the same place given as an area on the map
+        let query = Query {
+            keyword: Some("holiday".into()),
+            from: NaiveDate::from_ymd_opt(2024, 6, 1),
+            to: NaiveDate::from_ymd_opt(2024, 6, 30),
+            bbox: Some("13,52,14,53".parse().unwrap()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1]);
+
+        // the last day is inclusive
+        let query = Query {
+            keyword: Some("holiday".into()),
+            from: NaiveDate::from_ymd_opt(2024, 7, 1),
+            to: NaiveDate::from_ymd_opt(2024, 7, 15),
+            city: Some("Berlin".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [3]);
+
+        // items without a place never match a place filter
+        let query = Query {
+            keyword: Some("holiday".into()),
+            from: NaiveDate::from_ymd_opt(2024, 6, 1),
+            to: NaiveDate::from_ymd_opt(2024, 6, 1),
+            country: Some("xx".into()),
+            ..Query::default()
+        };
+        assert_eq!(search_ids(query), [1, 2]);
+    }
+}
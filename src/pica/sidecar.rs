@@ -0,0 +1,85 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use tracing::{debug, instrument};
+use ultrahdr_rs::XmpUpdate;
+
+use crate::pica::index::{find_xmp_sidecar, timestamp_from_metadata, xmp_sidecar_path};
+use crate::pica::db::correction::Correction;
+use crate::pica::MediaItem;
+
+/// The values pica writes back for a media item: the rating of the owner of its
+/// source, the keywords of the file together with its tags, and the corrected timestamp
+/// and location. `item` must already have the correction applied, its keywords must only
+/// be the ones of the file. A rating of `Some(None)` removes the rating from the sidecar.
+pub fn update_for(item: &MediaItem, rating: Option<Option<u8>>, tags: &[String], correction: Option<&Correction>) -> XmpUpdate {
+    let keywords = item
+        .xmp
+        .keywords
+        .iter()
+        .map(|keyword| keyword.to_string())
+        .chain(tags.iter().cloned())
+        .fold(Vec::new(), |mut keywords, keyword| {
+            if !keywords.contains(&keyword) {
+                keywords.push(keyword);
+            }
+
+            keywords
+        });
+
+    let correction = correction.copied().unwrap_or_default();
+
+    // timestamps are the local time the picture was taken, even though we treat them as utc
+    let date_time_original = (correction.timestamp.is_some() || correction.shift.is_some())
+        .then(|| item.info.timestamp.format("%Y-%m-%dT%H:%M:%S").to_string());
+
+    let gps = correction
+        .latitude
+        .zip(correction.longitude)
+        .map(|(latitude, longitude)| (latitude as f64, longitude as f64));
+
+    XmpUpdate {
+        rating,
+        keywords: Some(keywords),
+        tags: Some(tags.to_vec()),
+        description: None,
+        date_time_original,
+        gps,
+    }
+}
+
+/// Writes the update to the `<file>.xmp` sidecar of the media file at `path`. An existing
+/// sidecar is updated, a lightroom style `<name>.xmp` is used as a template for a new one.
+/// Returns the modification time of the written file.
+#[instrument(skip(update))]
+pub fn write_sidecar(path: &Path, update: &XmpUpdate) -> Result<DateTime<Utc>> {
+    let sidecar = find_xmp_sidecar(path);
+
+    let target = match &sidecar {
+        Some(sidecar) if sidecar.path == xmp_sidecar_path(path, "XMP") => sidecar.path.clone(),
+        _ => xmp_sidecar_path(path, "xmp"),
+    };
+
+    let existing = match sidecar {
+        Some(sidecar) => Some(std::fs::read(&sidecar.path)?),
+        None => None,
+    };
+
+    let xml = ultrahdr_rs::update_xmp(existing.as_deref(), update)?;
+
+    // write to a hidden file first, the scanner ignores those
+    let name = target.file_name().ok_or_else(|| anyhow!("no file name in {:?}", target))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".tmp");
+
+    let temp = target.with_file_name(temp_name);
+    std::fs::write(&temp, xml)?;
+    std::fs::rename(&temp, &target)?;
+
+    debug!("Wrote xmp sidecar {:?}", target);
+
+    timestamp_from_metadata(&std::fs::metadata(&target)?)
+}
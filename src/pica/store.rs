@@ -1,15 +1,120 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
-
+use serde_with::{DeserializeFromStr, SerializeDisplay};
 use tokio::sync::RwLock;
 use tracing::instrument;
 
-use crate::pica::{MediaId, MediaItem};
+use crate::pica::{MediaId, MediaItem, SourceId};
+
+// number of changes to remember for clients syncing incrementally
+const MAX_CHANGES: usize = 100_000;
 
 struct MediaItemsState {
     items: HashMap<MediaId, MediaItem>,
+
+    // all items in stream order, newest first
+    ordered: BTreeSet<(Reverse<DateTime<Utc>>, Reverse<MediaId>)>,
+
+    // identifies this instance of the store, sync tokens of a previous run are not valid
+    epoch: u64,
+
+    // incremented with every change
+    revision: u64,
+
+    // the most recent changes, oldest first
+    changes: VecDeque<Change>,
+}
+
+struct Change {
+    revision: u64,
+    id: MediaId,
+    source: SourceId,
+    removed: bool,
+}
+
+/// A position in the stream, the last item of the previous page.
+#[derive(Clone, Copy, Debug, SerializeDisplay, DeserializeFromStr)]
+pub struct Cursor {
+    pub timestamp: DateTime<Utc>,
+    pub id: MediaId,
+}
+
+impl Cursor {
+    fn key(&self) -> (Reverse<DateTime<Utc>>, Reverse<MediaId>) {
+        (Reverse(self.timestamp), Reverse(self.id))
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.timestamp.timestamp_millis(), self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the millis are negative for timestamps before 1970
+        let (millis, id) = s.rsplit_once('-').ok_or_else(|| anyhow!("invalid cursor {:?}", s))?;
+
+        let timestamp = DateTime::from_timestamp_millis(millis.parse()?)
+            .ok_or_else(|| anyhow!("timestamp out of range in cursor {:?}", s))?;
+
+        Ok(Self { timestamp, id: id.parse()? })
+    }
+}
+
+/// Identifies the state of the store a client has seen.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, SerializeDisplay, DeserializeFromStr)]
+pub struct SyncToken {
+    epoch: u64,
+    revision: u64,
+}
+
+impl Display for SyncToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}-{}", self.epoch, self.revision)
+    }
+}
+
+impl FromStr for SyncToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, revision) = s.split_once('-').ok_or_else(|| anyhow!("invalid sync token {:?}", s))?;
+
+        Ok(Self {
+            epoch: u64::from_str_radix(epoch, 16)?,
+            revision: revision.parse()?,
+        })
+    }
+}
+
+/// A page of the stream
+pub struct Page {
+    pub items: Vec<MediaItem>,
+
+    // set if there are more items after this page
+    pub next: Option<Cursor>,
+
+    pub token: SyncToken,
+}
+
+/// Everything that changed since a [SyncToken] was handed out
+pub struct Changes {
+    // new and updated items
+    pub added: Vec<MediaItem>,
+    pub removed: Vec<(MediaId, SourceId)>,
+    pub token: SyncToken,
 }
 
 #[derive(Clone)]
@@ -20,7 +125,19 @@ pub struct MediaStore {
 
 impl MediaStore {
     pub fn empty() -> Self {
-        let items = MediaItemsState { items: HashMap::new() };
+        let epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        let items = MediaItemsState {
+            items: HashMap::new(),
+            ordered: BTreeSet::new(),
+            epoch,
+            revision: 0,
+            changes: VecDeque::new(),
+        };
+
         Self {
             state: Arc::new(RwLock::new(items)),
         }
@@ -30,14 +147,39 @@ impl MediaStore {
     #[instrument(skip_all, fields(?item.relpath))]
     pub async fn add(&self, item: MediaItem) -> usize {
         let mut state = self.state.write().await;
-        state.items.insert(item.id, item);
+
+        let key = (Reverse(item.info.timestamp), Reverse(item.id));
+        state.record(item.id, item.source.clone(), false);
+
+        // the timestamp of an updated item might have changed
+        if let Some(previous) = state.items.insert(item.id, item) {
+            state.ordered.remove(&(Reverse(previous.info.timestamp), Reverse(previous.id)));
+        }
+
+        state.ordered.insert(key);
+
         state.items.len()
     }
 
     #[instrument(skip_all, fields(id))]
     pub async fn remove(&self, id: MediaId) {
         let mut state = self.state.write().await;
-        state.items.remove(&id);
+
+        if let Some(item) = state.items.remove(&id) {
+            state.ordered.remove(&(Reverse(item.info.timestamp), Reverse(item.id)));
+            state.record(item.id, item.source, true);
+        }
+    }
+
+    /// Marks an item as changed for clients syncing incrementally, e.g. after
+    /// something stored next to the item was updated.
+    #[instrument(skip_all, fields(id))]
+    pub async fn touch(&self, id: MediaId) {
+        let mut state = self.state.write().await;
+
+        if let Some(source) = state.items.get(&id).map(|item| item.source.clone()) {
+            state.record(id, source, false);
+        }
     }
 
     #[instrument(skip_all, fields(id))]
@@ -51,4 +193,198 @@ impl MediaStore {
         let state = self.state.read().await;
         state.items.values().cloned().collect_vec()
     }
+
+    /// Returns up to `limit` items matching the filter in stream order,
+    /// starting after the given cursor.
+    #[instrument(skip_all)]
+    pub async fn page(&self, after: Option<Cursor>, limit: usize, filter: impl Fn(&MediaItem) -> bool) -> Page {
+        let state = self.state.read().await;
+
+        let range = match after {
+            Some(cursor) => state.ordered.range((std::ops::Bound::Excluded(cursor.key()), std::ops::Bound::Unbounded)),
+            None => state.ordered.range(..),
+        };
+
+        let mut items = range
+            .filter_map(|(_, Reverse(id))| state.items.get(id))
+            .filter(|item| filter(item))
+            .take(limit + 1)
+            .cloned()
+            .collect_vec();
+
+        let next = match items.len() > limit {
+            true => {
+                items.truncate(limit);
+                items.last().map(|item| Cursor {
+                    timestamp: item.info.timestamp,
+                    id: item.id,
+                })
+            }
+
+            false => None,
+        };
+
+        Page {
+            items,
+            next,
+            token: state.token(),
+        }
+    }
+
+    /// Returns the changes since the token was handed out, or None
+    /// if the token is too old and the client needs to start over.
+    #[instrument(skip_all)]
+    pub async fn changes_since(&self, token: SyncToken) -> Option<Changes> {
+        let state = self.state.read().await;
+
+        if token.epoch != state.epoch || token.revision > state.revision {
+            return None;
+        }
+
+        // we need to remember every change after the token
+        let oldest = state.changes.front().map(|change| change.revision).unwrap_or(state.revision + 1);
+        if token.revision + 1 < oldest {
+            return None;
+        }
+
+        // only the latest change of an item counts
+        let latest: HashMap<MediaId, &Change> = state
+            .changes
+            .iter()
+            .filter(|change| change.revision > token.revision)
+            .map(|change| (change.id, change))
+            .collect();
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+
+        for change in latest.into_values() {
+            match state.items.get(&change.id) {
+                Some(item) if !change.removed => added.push(item.clone()),
+                _ => removed.push((change.id, change.source.clone())),
+            }
+        }
+
+        Some(Changes {
+            added,
+            removed,
+            token: state.token(),
+        })
+    }
+}
+
+impl MediaItemsState {
+    fn token(&self) -> SyncToken {
+        SyncToken {
+            epoch: self.epoch,
+            revision: self.revision,
+        }
+    }
+
+    fn record(&mut self, id: MediaId, source: SourceId, removed: bool) {
+        self.revision += 1;
+
+        self.changes.push_back(Change {
+            revision: self.revision,
+            id,
+            source,
+            removed,
+        });
+
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::pica::testing::media_item;
+
+    fn at(year: i32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        for timestamp in [at(2024), at(1969), at(1900), DateTime::UNIX_EPOCH] {
+            let cursor = Cursor {
+                timestamp,
+                id: MediaId::from([0xab; 8]),
+            };
+
+            let parsed: Cursor = cursor.to_string().parse().expect("parse cursor");
+            assert_eq!(parsed.timestamp, timestamp);
+            assert_eq!(parsed.id, cursor.id);
+        }
+
+        assert!(Cursor::from_str("1234").is_err());
+        assert!(Cursor::from_str("-abababababababab").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_page() {
+        let store = MediaStore::empty();
+        for (id, year) in [(1, 2001), (2, 2002), (3, 2003), (4, 1960)] {
+            store.add(media_item(id, at(year), None)).await;
+        }
+
+        let ids = |page: &Page| page.items.iter().map(|item| item.id).collect_vec();
+
+        let first = store.page(None, 2, |_| true).await;
+        assert_eq!(ids(&first), [MediaId::from([3; 8]), MediaId::from([2; 8])]);
+
+        // continues right after the cursor, also when it went through a string
+        let cursor: Cursor = first.next.expect("next").to_string().parse().expect("parse cursor");
+        let second = store.page(Some(cursor), 2, |_| true).await;
+        assert_eq!(ids(&second), [MediaId::from([1; 8]), MediaId::from([4; 8])]);
+
+        // exactly at the end there is no next page
+        assert!(second.next.is_none());
+
+        let all = store.page(None, 4, |_| true).await;
+        assert_eq!(all.items.len(), 4);
+        assert!(all.next.is_none());
+
+        let filtered = store.page(None, 1, |item| item.info.timestamp < at(2000)).await;
+        assert_eq!(ids(&filtered), [MediaId::from([4; 8])]);
+        assert!(filtered.next.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_changes_since() {
+        let store = MediaStore::empty();
+        store.add(media_item(1, at(2001), None)).await;
+
+        let token = store.page(None, 0, |_| true).await.token;
+
+        store.add(media_item(2, at(2002), None)).await;
+        store.remove(MediaId::from([1; 8])).await;
+
+        let changes = store.changes_since(token).await.expect("changes");
+        assert_eq!(changes.added.iter().map(|item| item.id).collect_vec(), [MediaId::from([2; 8])]);
+        assert_eq!(changes.removed.iter().map(|(id, _)| *id).collect_vec(), [MediaId::from([1; 8])]);
+
+        let unchanged = store.changes_since(changes.token).await.expect("changes");
+        assert!(unchanged.added.is_empty() && unchanged.removed.is_empty());
+
+        // tokens of a previous run of pica, clients need to start over
+        let previous = SyncToken {
+            epoch: token.epoch - 1,
+            ..token
+        };
+
+        assert!(store.changes_since(previous).await.is_none());
+
+        // tokens from the future are not valid either
+        let future = SyncToken {
+            revision: changes.token.revision + 1,
+            ..changes.token
+        };
+
+        assert!(store.changes_since(future).await.is_none());
+    }
 }
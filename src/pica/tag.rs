@@ -0,0 +1,24 @@
+/// Cleans up a tag as entered by a user. Tags form a hierarchy, the levels are separated
+/// by slashes, e.g. `people/anna`. Whitespace and empty levels are removed.
+/// Returns None if nothing is left.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag
+        .split('/')
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some(tag).filter(|tag| !tag.is_empty())
+}
+
+/// Returns the tag itself followed by all of its parents, e.g. `people/anna` and `people`.
+pub fn with_parents(tag: &str) -> impl Iterator<Item = &str> {
+    let parents = tag.rmatch_indices('/').map(|(idx, _)| &tag[..idx]);
+    std::iter::once(tag).chain(parents)
+}
+
+/// Checks if `tag` is `parent` or one of its children.
+pub fn is_within(tag: &str, parent: &str) -> bool {
+    tag.strip_prefix(parent).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum_login::tower_sessions::Session;
+use itertools::Itertools;
+
+use crate::pica::db::share::{Share, ShareTarget};
+use crate::pica::store::MediaStore;
+use crate::pica::{album, db, MediaId, MediaItem, SourceId};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+/// The media items a logged-in user is allowed to see. Every handler that serves
+/// media items must look them up through this, so items of sources the user has
+/// no access to behave exactly like items that do not exist.
+#[derive(Clone)]
+pub struct Access {
+    sources: Vec<SourceId>,
+    store: MediaStore,
+
+    // further restricts the visible items, e.g. to the items of a share
+    scope: Option<Scope>,
+}
+
+type Scope = Arc<dyn Fn(&MediaItem) -> bool + Send + Sync>;
+
+impl Access {
+    pub fn new(state: &AppState, user: &str) -> Self {
+        // find all sources the user is allowed to access
+        let sources = state
+            .sources
+            .iter()
+            .filter(|source| source.access.iter().any(|name| name == user))
+            .map(|source| SourceId::from(source.name.as_str()))
+            .collect();
+
+        Self {
+            sources,
+            store: state.store.clone(),
+            scope: None,
+        }
+    }
+
+    /// Restricts access to the items matching the given predicate.
+    pub fn scoped(self, scope: impl Fn(&MediaItem) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            scope: Some(Arc::new(scope)),
+            ..self
+        }
+    }
+
+    pub fn allows(&self, item: &MediaItem) -> bool {
+        self.allows_source(&item.source) && self.scope.as_ref().is_none_or(|scope| scope(item))
+    }
+
+    pub fn allows_source(&self, source: &SourceId) -> bool {
+        self.sources.contains(source)
+    }
+
+    /// Looks up a single media item.
+    pub async fn media(&self, id: MediaId) -> Option<MediaItem> {
+        self.store.get(id).await.filter(|item| self.allows(item))
+    }
+
+    /// All media items the user can see.
+    pub async fn items(&self) -> Vec<MediaItem> {
+        self.store.items().await.into_iter().filter(|item| self.allows(item)).collect_vec()
+    }
+}
+
+impl FromRequestParts<AppState> for Access {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = User::from_request_parts(parts, state).await?;
+        Ok(Access::new(state, &user.name))
+    }
+}
+
+/// Access to the items of the share named by the `token` in the request path, on
+/// behalf of the user that created the share. Does not require a login, but
+/// password protected shares need to be unlocked in the session first.
+pub struct ShareAccess {
+    pub share: Share,
+    pub access: Access,
+}
+
+impl ShareAccess {
+    /// The session key that marks a password protected share as unlocked.
+    pub fn unlock_key(token: &str) -> String {
+        format!("share.{}", token)
+    }
+}
+
+impl FromRequestParts<AppState> for ShareAccess {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let Some(token) = params.get("token") else {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+
+        let share = read_share(state, token).await.map_err(IntoResponse::into_response)?;
+
+        // expired shares behave exactly like revoked ones
+        let Some(share) = share.filter(|share| !share.is_expired()) else {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+
+        if share.passwd.is_some() {
+            let session = Session::from_request_parts(parts, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+
+            let unlocked = session
+                .get::<bool>(&Self::unlock_key(&share.token))
+                .await
+                .map_err(|err| WebError::from(err).into_response())?;
+
+            if unlocked != Some(true) {
+                return Err((StatusCode::UNAUTHORIZED, "share is locked").into_response());
+            }
+        }
+
+        // a share never grants more than its owner can access
+        let conf = state.album_config.clone();
+        let target = share.target.clone();
+
+        let access = Access::new(state, &share.owner).scoped(move |item| match &target {
+            ShareTarget::Album(relpath) => album::directory_of(&conf, item) == Some(relpath.as_path()),
+            ShareTarget::Media(ids) => ids.contains(&item.id),
+        });
+
+        Ok(Self { share, access })
+    }
+}
+
+async fn read_share(state: &AppState, token: &str) -> Result<Option<Share>, WebError> {
+    let mut tx = state.db.begin().await?;
+    let share = db::share::read_share(&mut tx, token).await?;
+    tx.commit().await?;
+
+    Ok(share)
+}
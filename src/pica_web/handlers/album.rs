@@ -0,0 +1,306 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::pica::db::album::CustomAlbum;
+use crate::pica::{album, db, AlbumId, MediaId};
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+/// A user created album as seen by its owner when managing it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CustomAlbumView {
+    id: AlbumId,
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover: Option<MediaId>,
+
+    // in the order chosen by the user
+    items: Vec<MediaId>,
+
+    created: DateTime<Utc>,
+}
+
+impl CustomAlbumView {
+    /// Only lists the items the user can still access.
+    async fn new(album: CustomAlbum, access: &Access) -> Self {
+        let mut items = Vec::with_capacity(album.items.len());
+        for id in album.items {
+            if access.media(id).await.is_some() {
+                items.push(id);
+            }
+        }
+
+        Self {
+            id: album.id,
+            name: album.name,
+            description: album.description,
+            cover: album.cover.filter(|cover| items.contains(cover)),
+            items,
+            created: album.created,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAlbumRequest {
+    name: String,
+    description: Option<String>,
+
+    #[serde(default)]
+    items: Vec<MediaId>,
+
+    cover: Option<MediaId>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateAlbumRequest {
+    name: Option<String>,
+
+    // an empty description removes it
+    description: Option<String>,
+
+    cover: Option<MediaId>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddItemsRequest {
+    items: Vec<MediaId>,
+
+    // insert the items at this index, defaults to the end of the album
+    position: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReorderItemsRequest {
+    // moves these items to the front in the given order
+    items: Vec<MediaId>,
+}
+
+#[derive(Deserialize)]
+pub struct RemoveItemsRequest {
+    #[serde(rename = "m")]
+    items: Vec<MediaId>,
+}
+
+/// Lists the albums the user created, including the ones without any items.
+#[instrument(skip_all)]
+pub async fn handle_custom_albums_get(
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let albums = db::album::list_albums(&mut tx, &user.name).await?;
+    tx.commit().await?;
+
+    let mut views = Vec::with_capacity(albums.len());
+    for album in albums {
+        views.push(CustomAlbumView::new(album, &access).await);
+    }
+
+    Ok(Json(views).into_response())
+}
+
+#[instrument(skip_all)]
+pub async fn handle_album_post(
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<CreateAlbumRequest>,
+) -> Result<Response, WebError> {
+    let mut album = CustomAlbum {
+        id: album::new_custom_album_id(),
+        owner: user.name,
+        name: String::new(),
+        description: None,
+        cover: None,
+        items: Vec::new(),
+        created: Utc::now(),
+    };
+
+    let update = UpdateAlbumRequest {
+        name: Some(req.name),
+        description: req.description,
+        cover: req.cover,
+    };
+
+    // add the items first, the cover must be one of them
+    if let Err(err) = add_items(&mut album, &access, req.items, None).await {
+        return Ok(err.into_response());
+    }
+
+    if let Err(err) = update_album(&mut album, update) {
+        return Ok(err.into_response());
+    }
+
+    store(&state, album, &access).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_patch(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<UpdateAlbumRequest>,
+) -> Result<Response, WebError> {
+    let Some(mut album) = read_album(&state, id, &user).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if let Err(err) = update_album(&mut album, req) {
+        return Ok(err.into_response());
+    }
+
+    store(&state, album, &access).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_delete(
+    Path(id): Path<AlbumId>,
+    user: User,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let deleted = db::album::delete_album(&mut tx, id, &user.name).await?;
+    tx.commit().await?;
+
+    match deleted {
+        true => Ok(().into_response()),
+        false => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_items_post(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<AddItemsRequest>,
+) -> Result<Response, WebError> {
+    let Some(mut album) = read_album(&state, id, &user).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if let Err(err) = add_items(&mut album, &access, req.items, req.position).await {
+        return Ok(err.into_response());
+    }
+
+    store(&state, album, &access).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_items_put(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<ReorderItemsRequest>,
+) -> Result<Response, WebError> {
+    let Some(mut album) = read_album(&state, id, &user).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if !req.items.iter().all(|id| album.items.contains(id)) || !req.items.iter().all_unique() {
+        return Ok((StatusCode::BAD_REQUEST, "items must be unique and part of the album").into_response());
+    }
+
+    // items not mentioned keep their relative order after the given ones
+    let rest = album.items.iter().filter(|id| !req.items.contains(id)).copied().collect_vec();
+    album.items = req.items.into_iter().chain(rest).collect();
+
+    store(&state, album, &access).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_items_delete(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Query(req): Query<RemoveItemsRequest>,
+) -> Result<Response, WebError> {
+    let Some(mut album) = read_album(&state, id, &user).await? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    album.items.retain(|id| !req.items.contains(id));
+    album.cover = album.cover.filter(|cover| album.items.contains(cover));
+
+    store(&state, album, &access).await
+}
+
+/// Adds the items that are not yet part of the album. All of them must be accessible.
+async fn add_items(album: &mut CustomAlbum, access: &Access, items: Vec<MediaId>, position: Option<usize>) -> Result<(), (StatusCode, &'static str)> {
+    for id in &items {
+        if access.media(*id).await.is_none() {
+            return Err((StatusCode::NOT_FOUND, "media item not found"));
+        }
+    }
+
+    let items = items.into_iter().unique().filter(|id| !album.items.contains(id)).collect_vec();
+
+    let position = position.unwrap_or(album.items.len()).min(album.items.len());
+    album.items.splice(position..position, items);
+
+    Ok(())
+}
+
+fn update_album(album: &mut CustomAlbum, req: UpdateAlbumRequest) -> Result<(), (StatusCode, &'static str)> {
+    if let Some(name) = req.name {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "name must not be empty"));
+        }
+
+        album.name = name.to_owned();
+    }
+
+    if let Some(description) = req.description {
+        album.description = Some(description).filter(|description| !description.is_empty());
+    }
+
+    if let Some(cover) = req.cover {
+        if !album.items.contains(&cover) {
+            return Err((StatusCode::BAD_REQUEST, "cover must be part of the album"));
+        }
+
+        album.cover = Some(cover);
+    }
+
+    Ok(())
+}
+
+async fn read_album(state: &AppState, id: AlbumId, user: &User) -> Result<Option<CustomAlbum>, WebError> {
+    let mut tx = state.db.begin().await?;
+    let album = db::album::read_album(&mut tx, id, &user.name).await?;
+    tx.commit().await?;
+
+    Ok(album)
+}
+
+async fn store(state: &AppState, album: CustomAlbum, access: &Access) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    db::album::store_album(&mut tx, &album).await?;
+    tx.commit().await?;
+
+    Ok(Json(CustomAlbumView::new(album, access).await).into_response())
+}
@@ -1,27 +1,32 @@
-use std::cmp::Reverse;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::anyhow;
 use arcstr::ArcStr;
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use itertools::Itertools;
-use serde::Serialize;
-use tracing::instrument;
+use axum_extra::extract::Query;
+use serde::{Deserialize, Serialize};
+use tokio::task::block_in_place;
+use tracing::{instrument, warn};
 
 use pica_image::exif::parse_exif_generic;
 
-use crate::pica::{album, Album, AlbumId, Location, MediaId, MediaItem, SourceId};
+use crate::pica::db::flags::MediaFlags;
+use crate::pica::duplicates::DuplicateGroup;
+use crate::pica::search::{BoundingBox, Sort};
+use crate::pica::store::{Cursor, SyncToken};
+use crate::pica::{album, db, duplicates, event, search, sidecar, tag, Album, AlbumId, Location, MediaId, MediaItem, SourceId};
 use crate::pica_web::handlers::WebError;
+use crate::pica_web::access::Access;
 use crate::pica_web::{AppState, User};
 
 #[derive(Serialize)]
-struct MediaItemView {
+pub struct MediaItemView {
     id: MediaId,
     name: ArcStr,
     timestamp: DateTime<Utc>,
@@ -30,6 +35,65 @@ struct MediaItemView {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     location: Option<LocationView>,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    video: bool,
+
+    // duration of a video in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<f64>,
+
+    // the image has a short video attached, e.g. a live photo
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    motion: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera: Option<ArcStr>,
+
+    // color label and keywords from the xmp data of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<ArcStr>,
+
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    keywords: Arc<[ArcStr]>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<ArcStr>,
+
+    // tags added in pica, e.g. 'people/anna'
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+
+    // the marks of the current user
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    favorite: bool,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    hidden: bool,
+
+    // the rating of the user, falls back to the one stored in the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rating: Option<u8>,
+}
+
+impl MediaItemView {
+    pub fn with_flags(self, flags: Option<&MediaFlags>) -> Self {
+        let flags = flags.copied().unwrap_or_default();
+
+        Self {
+            favorite: flags.favorite,
+            hidden: flags.hidden,
+            rating: flags.rating.or(self.rating),
+            ..self
+        }
+    }
+
+    pub fn with_tags(self, tags: Option<&Vec<String>>) -> Self {
+        Self {
+            tags: tags.cloned().unwrap_or_default(),
+            ..self
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -38,6 +102,13 @@ struct LocationView {
     longitude: f32,
     city: Option<ArcStr>,
     country: Option<ArcStr>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<ArcStr>,
+
+    // inferred from a gpx track instead of recorded by the camera
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    inferred: bool,
 }
 
 impl From<Location> for LocationView {
@@ -47,6 +118,8 @@ impl From<Location> for LocationView {
             longitude: value.longitude,
             city: value.city.as_ref().map(|city| city.name.clone()),
             country: value.city.as_ref().map(|city| city.country.clone()),
+            region: value.city.as_ref().and_then(|city| city.region.clone()),
+            inferred: value.inferred,
         }
     }
 }
@@ -54,12 +127,23 @@ impl From<Location> for LocationView {
 impl From<MediaItem> for MediaItemView {
     fn from(media: MediaItem) -> Self {
         Self {
+            video: media.is_video(),
+            duration: media.info.duration.map(|duration| duration.as_secs_f64()),
+            motion: media.motion.is_some(),
+            camera: media.info.camera,
             id: media.id,
             name: media.name,
             timestamp: media.info.timestamp,
             width: media.info.width,
             height: media.info.height,
             location: media.location.map(LocationView::from),
+            label: media.xmp.label,
+            keywords: media.xmp.keywords,
+            description: media.xmp.description,
+            tags: Vec::new(),
+            favorite: false,
+            hidden: false,
+            rating: media.xmp.rating,
         }
     }
 }
@@ -75,126 +159,649 @@ struct ExifView {
 #[serde(rename_all = "camelCase")]
 struct AlbumView {
     id: AlbumId,
+    kind: album::Kind,
     name: ArcStr,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<ArcStr>,
+
     items: Vec<MediaItemView>,
     timestamp: DateTime<Utc>,
     relpath: Option<Arc<PathBuf>>,
     cover: MediaItemView,
 }
 
-impl From<Album> for AlbumView {
-    fn from(value: Album) -> Self {
-        Self::from_album(value, usize::MAX)
-    }
-}
-
 impl AlbumView {
-    fn from_album(album: Album, n: usize) -> AlbumView {
+    fn from_album(album: Album, n: usize, annotations: &Annotations) -> AlbumView {
+        let view = |item: MediaItem| annotations.view(item);
+
         Self {
             id: album.info.id,
+            kind: album::Kind::of(album.info.id),
             name: album.info.name,
+            description: album.info.description,
             timestamp: album.info.timestamp,
-            items: album.items.into_iter().take(n).map(MediaItemView::from).collect(),
+            items: album.items.into_iter().take(n).map(view).collect(),
             relpath: album.relpath,
-            cover: album.cover.into(),
+            cover: view(album.cover),
         }
     }
 }
 
+/// Filters on the marks the user put on media items. Hidden items are left out by default.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagsQuery {
+    #[serde(default)]
+    include_hidden: bool,
+
+    // only favorites
+    #[serde(default)]
+    favorites: bool,
+
+    // only items rated with at least this many stars, by the user or in the file
+    min_rating: Option<u8>,
+}
+
+impl FlagsQuery {
+    pub fn matches(&self, item: &MediaItem, flags: Option<&MediaFlags>) -> bool {
+        let flags = flags.copied().unwrap_or_default();
+        let rating = flags.rating.or(item.xmp.rating);
+
+        (self.include_hidden || !flags.hidden)
+            && (!self.favorites || flags.favorite)
+            && self.min_rating.is_none_or(|min| rating.is_some_and(|rating| rating >= min))
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 struct StreamView {
     items: Vec<MediaItemView>,
+
+    // pass as `cursor` to get the next page, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<Cursor>,
+
+    // pass to /api/stream/changes to get what changed after this page was read
+    sync_token: SyncToken,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamQuery {
+    // continue after the last item of the previous page
+    cursor: Option<Cursor>,
+
+    // maximum number of items on this page
+    limit: Option<usize>,
+
+    // show only one of multiple identical files
+    #[serde(default)]
+    collapse_duplicates: bool,
 }
 
 #[instrument(skip_all)]
-pub async fn handle_stream_get(user: User, State(state): State<AppState>) -> Result<Response, WebError> {
-    let mut items = state.store.items().await;
+pub async fn handle_stream_get(
+    user: User,
+    access: Access,
+    Query(query): Query<StreamQuery>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let hidden = match query.collapse_duplicates {
+        true => duplicates::redundant_copies(&access.items().await),
+        false => HashSet::new(),
+    };
 
-    let has_access = user_has_access_pred(&state, &user);
+    // an empty page would end the stream for the client, even with more items left
+    let limit = query.limit.unwrap_or(10000).clamp(1, 10000);
 
-    items.sort_unstable_by_key(|img| Reverse(img.info.timestamp));
+    let page = state.store
+        .page(query.cursor, limit, |item| {
+            access.allows(item) && !hidden.contains(&item.id) && filter.matches(item, annotations.flags(item.id))
+        })
+        .await;
 
-    let items = items.into_iter()
-        .filter(has_access)
-        .take(10000)
-        .map(MediaItemView::from)
+    let items = page.items.into_iter()
+        .map(|item| annotations.view(item))
         .collect_vec();
 
-    encode_json(StreamView { items })
+    encode_json(StreamView { items, next_cursor: page.next, sync_token: page.token })
 }
 
-fn user_has_access_pred<'a>(state: &'a AppState, user: &'a User) -> impl Fn(&MediaItem) -> bool + 'a {
-    // find all sources the customer is allowed to access
-    let sources: Vec<_> = state.sources.iter()
-        .filter(|s| s.access.contains(&user.name))
-        .map(|s| SourceId::from(s.name.as_str()))
-        .collect();
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    since: SyncToken,
+}
 
-    move |item: &MediaItem| sources.contains(&item.source)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangesView {
+    // new and modified items
+    added: Vec<MediaItemView>,
+    removed: Vec<MediaId>,
+    sync_token: SyncToken,
 }
 
+/// Returns the items added and removed since the sync token was handed out. Responds
+/// with 410 Gone if the token is no longer valid, the client needs to reload the stream then.
+/// Items that no longer match the filter, e.g. because they were hidden, count as removed.
 #[instrument(skip_all)]
-pub async fn handle_albums_get(user: User, State(state): State<AppState>) -> Result<Response, WebError> {
-    albums_get(state, user, 0).await
+pub async fn handle_stream_changes_get(
+    user: User,
+    access: Access,
+    Query(query): Query<ChangesQuery>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let Some(changes) = state.store.changes_since(query.since).await else {
+        return Ok(StatusCode::GONE.into_response());
+    };
+
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let (added, filtered): (Vec<_>, Vec<_>) = changes.added.into_iter()
+        .filter(|item| access.allows(item))
+        .partition(|item| filter.matches(item, annotations.flags(item.id)));
+
+    let added = added.into_iter()
+        .map(|item| annotations.view(item))
+        .collect_vec();
+
+    let removed = changes.removed.into_iter()
+        .filter(|(_, source)| access.allows_source(source))
+        .map(|(id, _)| id)
+        .chain(filtered.into_iter().map(|item| item.id))
+        .collect_vec();
+
+    encode_json(ChangesView { added, removed, sync_token: changes.token })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumsQuery {
+    // the kinds of albums to list, can be given multiple times.
+    // Defaults to directory albums and the albums the user created.
+    #[serde(default)]
+    kind: Vec<album::Kind>,
 }
 
 #[instrument(skip_all)]
-pub async fn handle_albums_get_full(user: User, State(state): State<AppState>) -> Result<Response, WebError> {
-    albums_get(state, user, usize::MAX).await
+pub async fn handle_albums_get(
+    user: User,
+    access: Access,
+    Query(query): Query<AlbumsQuery>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    albums_get(state, user, access, query, filter, 0).await
 }
 
 #[instrument(skip_all)]
-async fn albums_get(state: AppState, user: User, n: usize) -> Result<Response, WebError> {
-    let images = state.store.items().await
-        .into_iter()
-        .filter(user_has_access_pred(&state, &user))
-        .collect_vec();
+pub async fn handle_albums_get_full(
+    user: User,
+    access: Access,
+    Query(query): Query<AlbumsQuery>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    albums_get(state, user, access, query, filter, usize::MAX).await
+}
 
-    let albums = album::by_directory(&state.album_config, images);
+#[instrument(skip_all)]
+async fn albums_get(
+    state: AppState,
+    user: User,
+    access: Access,
+    query: AlbumsQuery,
+    filter: FlagsQuery,
+    n: usize,
+) -> Result<Response, WebError> {
+    use album::Kind;
+
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let kinds = match query.kind.is_empty() {
+        true => vec![Kind::Directory, Kind::Custom],
+        false => query.kind,
+    };
 
-    let albums = albums.into_iter().map(|al| AlbumView::from_album(al, n)).collect_vec();
+    let mut albums = Vec::new();
+
+    if kinds.contains(&Kind::Directory) || kinds.contains(&Kind::Custom) {
+        let user_albums = user_albums(&state, &user, &access, |item| filter.matches(item, annotations.flags(item.id))).await?;
+        albums.extend(user_albums.into_iter().filter(|album| kinds.contains(&Kind::of(album.info.id))));
+    }
+
+    // virtual albums are derived from all items of the user
+    if kinds.iter().any(|kind| matches!(kind, Kind::Tag | Kind::Place | Kind::Trip | Kind::Day)) {
+        let items = access.items().await
+            .into_iter()
+            .filter(|item| filter.matches(item, annotations.flags(item.id)))
+            .collect_vec();
+
+        if kinds.contains(&Kind::Tag) {
+            albums.extend(album::by_tags(items.iter().cloned(), &annotations.tags));
+        }
+
+        if kinds.contains(&Kind::Place) {
+            albums.extend(album::by_place(items.iter().cloned()).into_iter().map(|(_, album)| album));
+        }
+
+        if kinds.contains(&Kind::Trip) || kinds.contains(&Kind::Day) {
+            let events = event::by_event(items);
+            albums.extend(events.into_iter().filter(|album| kinds.contains(&Kind::of(album.info.id))));
+        }
+    }
+
+    albums.sort_by_key(|album| album.info.timestamp);
+
+    let albums = albums.into_iter().map(|al| AlbumView::from_album(al, n, &annotations)).collect_vec();
 
     encode_json(albums)
 }
 
 #[instrument(skip_all, fields(? id))]
-pub async fn handle_album_get(Path(id): Path<AlbumId>, user: User, State(state): State<AppState>) -> Result<Response, WebError> {
-    let images = state.store.items().await
-        .into_iter()
-        .filter(user_has_access_pred(&state, &user))
+pub async fn handle_album_get(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    if album::is_tag(id) {
+        let items = access.items().await
+            .into_iter()
+            .filter(|item| filter.matches(item, annotations.flags(item.id)))
+            .collect_vec();
+
+        // the album of a tag only exists if the user can access items with this tag
+        let tag = items.iter()
+            .filter_map(|item| annotations.tags(item.id))
+            .flatten()
+            .flat_map(|tag| tag::with_parents(tag))
+            .find(|tag| album::tag_album_id(tag) == id)
+            .map(str::to_owned);
+
+        let Some(album) = tag.and_then(|tag| album::by_tag(&tag, items, &annotations.tags)) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        return encode_json(AlbumView::from_album(album, usize::MAX, &annotations));
+    }
+
+    if album::is_event(id) {
+        let items = access.items().await
+            .into_iter()
+            .filter(|item| filter.matches(item, annotations.flags(item.id)))
+            .collect_vec();
+
+        let Some(album) = event::by_event(items).into_iter().find(|album| album.info.id == id) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        return encode_json(AlbumView::from_album(album, usize::MAX, &annotations));
+    }
+
+    if album::is_place(id) {
+        let items = access.items().await
+            .into_iter()
+            .filter(|item| filter.matches(item, annotations.flags(item.id)))
+            .collect_vec();
+
+        let Some((_, album)) = album::by_place(items).into_iter().find(|(_, album)| album.info.id == id) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        return encode_json(AlbumView::from_album(album, usize::MAX, &annotations));
+    }
+
+    let albums = user_albums(&state, &user, &access, |item| filter.matches(item, annotations.flags(item.id))).await?;
+
+    // albums only exist for the media items a user can access
+    let Some(album) = albums.into_iter().find(|a| a.info.id == id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    encode_json(AlbumView::from_album(album, usize::MAX, &annotations))
+}
+
+/// All albums of the user sorted by time, the directory albums together with
+/// the albums the user created. Albums without any matching item are left out.
+pub async fn user_albums(
+    state: &AppState,
+    user: &User,
+    access: &Access,
+    filter: impl Fn(&MediaItem) -> bool,
+) -> Result<Vec<Album>, WebError> {
+    let mut tx = state.db.begin().await?;
+    let custom = db::album::list_albums(&mut tx, &user.name).await?;
+    tx.commit().await?;
+
+    let items = access.items().await.into_iter().filter(|item| filter(item)).collect_vec();
+
+    let by_id: HashMap<MediaId, MediaItem> = match custom.is_empty() {
+        true => HashMap::new(),
+        false => items.iter().map(|item| (item.id, item.clone())).collect(),
+    };
+
+    let mut albums = album::by_directory(&state.album_config, items);
+    albums.extend(custom.iter().filter_map(|custom| album::custom(custom, &by_id)));
+    albums.sort_by_key(|album| album.info.timestamp);
+
+    Ok(albums)
+}
+
+/// What was added to media items in pica: the flags of the current user and the tags.
+pub struct Annotations {
+    flags: HashMap<MediaId, MediaFlags>,
+    tags: HashMap<MediaId, Vec<String>>,
+}
+
+impl Annotations {
+    pub async fn load(state: &AppState, user: &User) -> Result<Self, WebError> {
+        let mut tx = state.db.begin().await?;
+        let flags = db::flags::list_flags(&mut tx, &user.name).await?;
+        let tags = db::tag::list_tags(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(Self { flags, tags })
+    }
+
+    pub fn flags(&self, id: MediaId) -> Option<&MediaFlags> {
+        self.flags.get(&id)
+    }
+
+    pub fn tags(&self, id: MediaId) -> Option<&Vec<String>> {
+        self.tags.get(&id)
+    }
+
+    pub fn view(&self, item: MediaItem) -> MediaItemView {
+        let (flags, tags) = (self.flags(item.id), self.tags(item.id));
+        MediaItemView::from(item).with_flags(flags).with_tags(tags)
+    }
+}
+
+/// Writes the rating of the user, the tags and corrections of the items into xmp sidecars, if enabled
+/// for their source. Failures are only logged, the change itself is already stored. Ratings are only
+/// removed from the sidecars with `clear_rating`, otherwise the ones set by other tools are kept.
+///
+/// Ratings are per user, but there is only one sidecar. Only the owner of a source, the first user
+/// with access to it, writes ratings. The ones of other users stay in pica.
+pub async fn write_sidecars(state: &AppState, user: &User, items: &[MediaItem], clear_rating: bool) -> Result<(), WebError> {
+    let items = items
+        .iter()
+        .filter_map(|item| {
+            let source = state.sources.iter().find(|source| source.name == item.source.as_str())?;
+            let owner = source.access.first() == Some(&user.name);
+            source.write_xmp_sidecars.then(|| (item, source.path.join(item.relpath.as_ref()), owner))
+        })
         .collect_vec();
 
-    let albums = album::by_directory(&state.album_config, images);
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let annotations = Annotations::load(state, user).await?;
+
+    let mut corrections = HashMap::new();
+
+    let mut tx = state.db.begin().await?;
+    for (item, _, _) in &items {
+        if let Some(correction) = db::correction::read_correction(&mut tx, item.id).await? {
+            corrections.insert(item.id, correction);
+        }
+    }
+    tx.commit().await?;
+
+    for (item, path, owner) in items {
+        let rating = annotations.flags(item.id).and_then(|flags| flags.rating);
+
+        let rating = match owner {
+            true if clear_rating => Some(rating),
+            true => rating.map(Some),
+            false => None,
+        };
+
+        let tags = annotations.tags(item.id).map(Vec::as_slice).unwrap_or_default();
+
+        let update = sidecar::update_for(item, rating, tags, corrections.get(&item.id));
+
+        match block_in_place(|| sidecar::write_sidecar(&path, &update)) {
+            Ok(xmp_mtime) => {
+                // keep the scanner from reading our own changes again
+                let mut tx = state.db.begin().await?;
+                db::media::update_xmp_mtime(&mut tx, item.id, xmp_mtime).await?;
+                tx.commit().await?;
+
+                state.scan_queue.lock().await.sidecar_written(path, xmp_mtime);
+            }
+
+            Err(err) => warn!("Failed to write xmp sidecar of {:?}: {:?}", path, err),
+        }
+    }
+
+    Ok(())
+}
 
-    let album = albums
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequest {
+    q: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    name: Option<String>,
+    album: Option<AlbumId>,
+    source: Option<SourceId>,
+    city: Option<String>,
+    country: Option<String>,
+    region: Option<String>,
+    camera: Option<String>,
+    keyword: Option<String>,
+    label: Option<String>,
+
+    // west,south,east,north
+    bbox: Option<BoundingBox>,
+
+    #[serde(default)]
+    sort: Sort,
+
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchView {
+    items: Vec<MediaItemView>,
+
+    // number of matching items, including the ones not on this page
+    total: usize,
+}
+
+#[instrument(skip_all)]
+pub async fn handle_search_get(
+    user: User,
+    access: Access,
+    Query(req): Query<SearchRequest>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let items = access.items().await
         .into_iter()
-        .find(|a| a.info.id == id)
-        .ok_or_else(|| anyhow!("no album found for id {:?}", id))?;
+        .filter(|item| filter.matches(item, annotations.flags(item.id)))
+        .collect_vec();
 
-    encode_json(AlbumView::from(album))
+    let query = search::Query {
+        text: req.q,
+        from: req.from,
+        to: req.to,
+        name: req.name,
+        album: req.album,
+        source: req.source,
+        city: req.city,
+        country: req.country,
+        region: req.region,
+        camera: req.camera,
+        keyword: req.keyword,
+        label: req.label,
+        bbox: req.bbox,
+        sort: req.sort,
+    };
+
+    let items = block_in_place(|| search::search(&state.album_config, items, &query));
+
+    let total = items.len();
+    let limit = req.limit.unwrap_or(100).min(1000);
+
+    let items = items.into_iter()
+        .skip(req.offset)
+        .take(limit)
+        .map(|item| annotations.view(item))
+        .collect_vec();
+
+    encode_json(SearchView { items, total })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateGroupView {
+    // all files are identical, otherwise they show similar pictures
+    exact: bool,
+    items: Vec<DuplicateItemView>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DuplicateItemView {
+    #[serde(flatten)]
+    item: MediaItemView,
+    source: SourceId,
+    relpath: Arc<PathBuf>,
+}
+
+impl DuplicateGroupView {
+    fn new(group: DuplicateGroup, annotations: &Annotations) -> Self {
+        let items = group.items
+            .into_iter()
+            .map(|media| DuplicateItemView {
+                source: media.source.clone(),
+                relpath: media.relpath.clone(),
+                item: annotations.view(media),
+            })
+            .collect();
+
+        Self { exact: group.exact, items }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn handle_duplicates_get(user: User, access: Access, State(state): State<AppState>) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let items = access.items().await;
+
+    let groups = block_in_place(|| duplicates::find_duplicates(items));
+
+    let groups = groups.into_iter().map(|group| DuplicateGroupView::new(group, &annotations)).collect_vec();
+
+    encode_json(groups)
 }
 
 #[instrument(skip_all, fields(? id))]
-pub async fn handle_exif_get(Path(id): Path<MediaId>, user: User, State(state): State<AppState>) -> Result<Response, WebError> {
-    let Some(media) = state.store.get(id).await else {
+pub async fn handle_exif_get(
+    Path(id): Path<MediaId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await else {
         return Ok(StatusCode::NOT_FOUND.into_response());
     };
 
-    if !user_has_access_pred(&state, &user)(&media) {
-        return Ok(StatusCode::FORBIDDEN.into_response());
-    }
+    let mut tx = state.db.begin().await?;
+    let flags = db::flags::read_flags(&mut tx, &user.name, id).await?;
+    let tags = db::tag::read_tags(&mut tx, id).await?;
+    tx.commit().await?;
 
     let path = state.accessor.full(&media)?;
     let exif = parse_exif_generic(path)?;
     let result = ExifView {
-        item: media.into(),
+        item: MediaItemView::from(media).with_flags(Some(&flags)).with_tags(Some(&tags)),
         exif: exif.map(|raw| raw.0),
     };
 
     encode_json(result)
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagsRequest {
+    favorite: Option<bool>,
+    hidden: Option<bool>,
+
+    // 0 removes the rating
+    rating: Option<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FlagsView {
+    favorite: bool,
+    hidden: bool,
+    rating: Option<u8>,
+}
+
+/// Updates the given flags of a media item for the current user, the others stay unchanged.
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_flags_put(
+    Path(id): Path<MediaId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<FlagsRequest>,
+) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if req.rating.is_some_and(|rating| rating > 5) {
+        return Ok((StatusCode::BAD_REQUEST, "rating must be between 0 and 5").into_response());
+    }
+
+    let mut tx = state.db.begin().await?;
+
+    let mut flags = db::flags::read_flags(&mut tx, &user.name, id).await?;
+    flags.favorite = req.favorite.unwrap_or(flags.favorite);
+    flags.hidden = req.hidden.unwrap_or(flags.hidden);
+
+    if let Some(rating) = req.rating {
+        flags.rating = Some(rating).filter(|rating| *rating > 0);
+    }
+
+    db::flags::store_flags(&mut tx, &user.name, id, flags).await?;
+    tx.commit().await?;
+
+    // let syncing clients pick up the change
+    state.store.touch(id).await;
+
+    write_sidecars(&state, &user, &[media], req.rating.is_some()).await?;
+
+    encode_json(FlagsView {
+        favorite: flags.favorite,
+        hidden: flags.hidden,
+        rating: flags.rating,
+    })
+}
+
 #[instrument(skip_all)]
 fn encode_json<T: Serialize>(value: T) -> Result<Response, WebError> {
     Ok(Json(value).into_response())
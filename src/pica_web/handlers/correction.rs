@@ -0,0 +1,203 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, TimeDelta, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Deserializer};
+use tracing::instrument;
+
+use crate::pica::db::correction::Correction;
+use crate::pica::{db, AlbumId, MediaId, MediaItem};
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::api::{user_albums, write_sidecars, Annotations};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+// clocks of cameras are not off by more than this, larger shifts might not fit into a timestamp
+const MAX_SHIFT: TimeDelta = TimeDelta::days(100 * 365);
+
+/// Changes the correction of the items. Fields missing from the request keep their
+/// current value, fields set to `null` are removed.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CorrectionRequest {
+    #[serde(default, deserialize_with = "deserialize_field")]
+    timestamp: Option<Option<DateTime<Utc>>>,
+
+    // in seconds, replaces an absolute timestamp and the other way round
+    #[serde(default, deserialize_with = "deserialize_field")]
+    shift: Option<Option<i64>>,
+
+    #[serde(default, deserialize_with = "deserialize_field")]
+    latitude: Option<Option<f32>>,
+
+    #[serde(default, deserialize_with = "deserialize_field")]
+    longitude: Option<Option<f32>>,
+}
+
+/// A validated correction request.
+#[derive(Clone, Copy)]
+struct CorrectionUpdate {
+    timestamp: Option<Option<DateTime<Utc>>>,
+    shift: Option<Option<i64>>,
+    location: Option<Option<(f32, f32)>>,
+}
+
+impl TryFrom<CorrectionRequest> for CorrectionUpdate {
+    type Error = &'static str;
+
+    fn try_from(req: CorrectionRequest) -> Result<Self, Self::Error> {
+        if matches!((req.timestamp, req.shift), (Some(Some(_)), Some(Some(_)))) {
+            return Err("either set the timestamp or shift it");
+        }
+
+        let shift = req.shift.flatten().map(TimeDelta::try_seconds);
+        if shift.is_some_and(|shift| shift.is_none_or(|shift| shift.abs() > MAX_SHIFT)) {
+            return Err("shift out of range");
+        }
+
+        let location = match (req.latitude, req.longitude) {
+            (Some(Some(latitude)), Some(Some(longitude))) => {
+                if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                    return Err("location out of range");
+                }
+
+                Some(Some((latitude, longitude)))
+            }
+
+            (Some(None), Some(None)) => Some(None),
+            (None, None) => None,
+            _ => return Err("latitude and longitude must be given together"),
+        };
+
+        Ok(Self {
+            timestamp: req.timestamp,
+            shift: req.shift,
+            location,
+        })
+    }
+}
+
+impl CorrectionUpdate {
+    /// Applies the fields given in the request to the current correction of an item.
+    fn merge(self, mut correction: Correction) -> Correction {
+        // a zero shift removes it
+        let shift = self.shift.map(|shift| shift.filter(|shift| *shift != 0));
+
+        // setting a timestamp removes the shift and the other way round
+        match (self.timestamp, shift) {
+            (Some(Some(timestamp)), _) => (correction.timestamp, correction.shift) = (Some(timestamp), None),
+            (_, Some(Some(shift))) => (correction.timestamp, correction.shift) = (None, Some(shift)),
+
+            (timestamp, shift) => {
+                correction.timestamp = timestamp.unwrap_or(correction.timestamp);
+                correction.shift = shift.unwrap_or(correction.shift);
+            }
+        }
+
+        if let Some(location) = self.location {
+            correction.latitude = location.map(|(latitude, _)| latitude);
+            correction.longitude = location.map(|(_, longitude)| longitude);
+        }
+
+        correction
+    }
+}
+
+/// Tells a field set to `null` apart from a missing one.
+fn deserialize_field<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_media_correction_put(
+    Path(id): Path<MediaId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<CorrectionRequest>,
+) -> Result<Response, WebError> {
+    let Some(item) = access.media(id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let update = match CorrectionUpdate::try_from(req) {
+        Ok(update) => update,
+        Err(err) => return Ok((StatusCode::BAD_REQUEST, err).into_response()),
+    };
+
+    let mut items = apply(&state, vec![item], update).await?;
+
+    write_sidecars(&state, &user, &items, false).await?;
+
+    let annotations = Annotations::load(&state, &user).await?;
+    let Some(item) = items.pop() else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok(Json(annotations.view(item)).into_response())
+}
+
+/// Applies the same correction to all items of an album, e.g. to shift all
+/// pictures taken with a camera that had its clock set wrong.
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_album_correction_put(
+    Path(id): Path<AlbumId>,
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<CorrectionRequest>,
+) -> Result<Response, WebError> {
+    let albums = user_albums(&state, &user, &access, |_| true).await?;
+
+    let Some(album) = albums.into_iter().find(|album| album.info.id == id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let update = match CorrectionUpdate::try_from(req) {
+        Ok(update) => update,
+        Err(err) => return Ok((StatusCode::BAD_REQUEST, err).into_response()),
+    };
+
+    let items = apply(&state, album.items, update).await?;
+
+    write_sidecars(&state, &user, &items, false).await?;
+
+    let annotations = Annotations::load(&state, &user).await?;
+    let items = items.into_iter().map(|item| annotations.view(item)).collect_vec();
+
+    Ok(Json(items).into_response())
+}
+
+/// Updates the stored corrections and the items in the store. Returns the corrected items.
+async fn apply(state: &AppState, items: Vec<MediaItem>, update: CorrectionUpdate) -> Result<Vec<MediaItem>, WebError> {
+    let mut tx = state.db.begin().await?;
+
+    let mut corrected = Vec::with_capacity(items.len());
+
+    for item in items {
+        let current = db::correction::read_correction(&mut tx, item.id).await?;
+        let correction = update.merge(current.unwrap_or_default());
+        db::correction::store_correction(&mut tx, item.id, correction).await?;
+
+        // corrections always apply to what was parsed from the file
+        let parsed = match db::media::read_media_item(&mut tx, item.id).await? {
+            Some(cached) => cached.item,
+            None => item,
+        };
+
+        let item = parsed.corrected(&correction)?;
+        corrected.push(state.geotagger.apply(item)?);
+    }
+
+    tx.commit().await?;
+
+    for item in &corrected {
+        state.store.add(item.clone()).await;
+    }
+
+    Ok(corrected)
+}
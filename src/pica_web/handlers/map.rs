@@ -0,0 +1,53 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use itertools::Itertools;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::pica::map;
+use crate::pica::search::BoundingBox;
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::api::{Annotations, FlagsQuery};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MapRequest {
+    // the visible part of the map as west,south,east,north. Defaults to the whole world.
+    bbox: Option<BoundingBox>,
+
+    // zoom level of the map, as used for map tiles
+    #[serde(default)]
+    zoom: u8,
+}
+
+/// Clusters the items with a location within the visible part of the map.
+#[instrument(skip_all, fields(zoom = req.zoom))]
+pub async fn handle_map_get(
+    user: User,
+    access: Access,
+    Query(req): Query<MapRequest>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let items = access
+        .items()
+        .await
+        .into_iter()
+        .filter(|item| {
+            item.location.as_ref().is_some_and(|location| {
+                req.bbox.is_none_or(|bbox| bbox.contains(location.latitude, location.longitude))
+            })
+        })
+        .filter(|item| filter.matches(item, annotations.flags(item.id)))
+        .collect_vec();
+
+    let clusters = map::cluster(&items, req.zoom);
+
+    Ok(Json(clusters).into_response())
+}
@@ -1,7 +1,7 @@
 use crate::pica::accessor::MediaAccessor;
 use crate::pica::scale::Image;
-use crate::pica::{MediaId, MediaItem};
-use crate::pica_web::auth::AuthSession;
+use crate::pica::{MediaId, MediaItem, Motion};
+use crate::pica_web::access::Access;
 use crate::pica_web::handlers::WebError;
 use crate::pica_web::{streamzip, AppState};
 use anyhow::anyhow;
@@ -10,15 +10,15 @@ use axum::body::Body;
 use axum::extract::{Path, State};
 use axum::http;
 use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
-use axum::http::{HeaderValue, Request};
+use axum::http::{HeaderValue, Request, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum_extra::extract::Query;
 use futures_util::StreamExt;
-use itertools::Itertools;
 use mime::Mime;
 use serde::Deserialize;
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, SeekFrom, Write};
 use std::str::FromStr;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::{oneshot, Mutex, Notify};
 use tokio::task::spawn_blocking;
@@ -28,7 +28,7 @@ use tower_http::services::ServeFile;
 use tracing::{debug, debug_span, instrument, Instrument, Span};
 
 #[derive(Debug)]
-enum ImageType {
+pub enum ImageType {
     Thumbnail,
     Preview,
 }
@@ -36,37 +36,35 @@ enum ImageType {
 #[instrument(skip_all, fields(? id))]
 pub async fn handle_thumbnail(
     Path((id, _)): Path<(MediaId, String)>,
-    auth_session: AuthSession,
+    access: Access,
     State(state): State<AppState>,
 ) -> Result<Response, WebError> {
-    handle_image_scaled(id, auth_session, state, ImageType::Thumbnail).await
+    handle_image_scaled(id, access, state, ImageType::Thumbnail).await
 }
 
 #[instrument(skip_all, fields(? id))]
 pub async fn handle_preview_sdr(
     Path((id, _)): Path<(MediaId, String)>,
-    auth_session: AuthSession,
+    access: Access,
     State(state): State<AppState>,
 ) -> Result<Response, WebError> {
-    handle_image_scaled(id, auth_session, state, ImageType::Preview).await
+    handle_image_scaled(id, access, state, ImageType::Preview).await
 }
 
 #[instrument(skip_all, fields(? id))]
 pub async fn handle_preview_hdr(
     Path((id, _)): Path<(MediaId, String)>,
-    auth_session: AuthSession,
+    access: Access,
     State(state): State<AppState>,
 ) -> Result<Response, WebError> {
-    handle_image_scaled(id, auth_session, state, ImageType::Preview).await
+    handle_image_scaled(id, access, state, ImageType::Preview).await
 }
 
 #[instrument(skip_all, fields(? id, ? image_type))]
-async fn handle_image_scaled(id: MediaId, _auth: AuthSession, state: AppState, image_type: ImageType) -> Result<Response, WebError> {
-    let media = state
-        .store
-        .get(id)
-        .await
-        .ok_or_else(|| anyhow!("unknown image {:?}", id))?;
+pub async fn handle_image_scaled(id: MediaId, access: Access, state: AppState, image_type: ImageType) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
 
     // scale image
     let image = state.scale_queue.scaled(media, image_type)
@@ -84,23 +82,136 @@ async fn handle_image_scaled(id: MediaId, _auth: AuthSession, state: AppState, i
 #[instrument(skip_all, fields(? id))]
 pub async fn handle_fullsize(
     Path((id, _)): Path<(MediaId, String)>,
-    state: State<AppState>,
+    access: Access,
+    State(state): State<AppState>,
     request: Request<Body>,
 ) -> Result<Response, WebError> {
-    let media = state
-        .store
-        .get(id)
-        .await
-        .ok_or_else(|| anyhow!("unknown image {:?}", id))?;
+    serve_fullsize(id, access, state, request).await
+}
+
+pub async fn serve_fullsize(id: MediaId, access: Access, state: AppState, request: Request<Body>) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
 
     debug!("Serve full image for {:?}", media.relpath);
 
     // guess mime from the media path
     let mime = mime_guess::from_path(media.relpath.as_ref()).first_or(Mime::from_str("image/jpeg")?);
 
+    serve_file(&state, &media, &mime, request).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_video(
+    Path((id, _)): Path<(MediaId, String)>,
+    access: Access,
+    State(state): State<AppState>,
+    request: Request<Body>,
+) -> Result<Response, WebError> {
+    serve_video(id, access, state, request).await
+}
+
+pub async fn serve_video(id: MediaId, access: Access, state: AppState, request: Request<Body>) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await.filter(|media| media.is_video()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    debug!("Serve video for {:?}", media.relpath);
+
+    let mime = mime_guess::from_path(media.relpath.as_ref()).first_or(Mime::from_str("video/mp4")?);
+
+    serve_file(&state, &media, &mime, request).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_motion(
+    Path((id, _)): Path<(MediaId, String)>,
+    access: Access,
+    State(state): State<AppState>,
+    request: Request<Body>,
+) -> Result<Response, WebError> {
+    serve_motion(id, access, state, request).await
+}
+
+pub async fn serve_motion(id: MediaId, access: Access, state: AppState, request: Request<Body>) -> Result<Response, WebError> {
+    let Some(media) = access.media(id).await else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    debug!("Serve motion video for {:?}", media.relpath);
+
+    match media.motion {
+        Some(Motion::Sidecar(ref relpath)) => {
+            let path = state.accessor.motion(&media)?.ok_or_else(|| anyhow!("no motion video"))?;
+            let mime = mime_guess::from_path(relpath.as_ref()).first_or(Mime::from_str("video/mp4")?);
+            serve_path(&path, &mime, request).await
+        }
+
+        Some(Motion::Embedded { offset, len }) => {
+            let path = state.accessor.full(&media)?;
+            serve_embedded(&path, offset, len, request).await
+        }
+
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+/// Serves a video that is embedded in a larger file, e.g. a motion photo.
+/// Only supports a single byte range, which is enough for video playback in a browser.
+async fn serve_embedded(path: &std::path::Path, offset: u64, len: u64, request: Request<Body>) -> Result<Response, WebError> {
+    let range = request
+        .headers()
+        .get(http::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range(value, len));
+
+    let (start, end) = range.unwrap_or((0, len.saturating_sub(1)));
+    if len == 0 || start > end || end >= len {
+        return Ok(StatusCode::RANGE_NOT_SATISFIABLE.into_response());
+    }
+
+    let mut fp = tokio::fs::File::open(path).await?;
+    fp.seek(SeekFrom::Start(offset + start)).await?;
+
+    let mut buf = Vec::new();
+    fp.take(end - start + 1).read_to_end(&mut buf).await?;
+
+    let mut resp = Response::builder()
+        .header(CONTENT_TYPE, "video/mp4")
+        .header(http::header::ACCEPT_RANGES, "bytes")
+        .header(http::header::CACHE_CONTROL, "public, max-age=31536000, immutable");
+
+    if range.is_some() {
+        resp = resp
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(http::header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"));
+    }
+
+    Ok(resp.body(Body::from(buf))?)
+}
+
+/// Parses a `bytes=start-end` range header into an inclusive range.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+
+    match (start.trim(), end.trim()) {
+        // the last n bytes
+        ("", suffix) => Some((len.saturating_sub(suffix.parse().ok()?), len.checked_sub(1)?)),
+        (start, "") => Some((start.parse().ok()?, len.checked_sub(1)?)),
+        (start, end) => Some((start.parse().ok()?, end.parse::<u64>().ok()?.min(len.checked_sub(1)?))),
+    }
+}
+
+/// Serves the original file of a media item. This supports range requests.
+async fn serve_file(state: &AppState, media: &MediaItem, mime: &Mime, request: Request<Body>) -> Result<Response, WebError> {
+    let path = state.accessor.full(media)?;
+    serve_path(&path, mime, request).await
+}
+
+async fn serve_path(path: &std::path::Path, mime: &Mime, request: Request<Body>) -> Result<Response, WebError> {
     // serve file to response
-    let path = state.accessor.full(&media)?;
-    let mut resp = ServeFile::new_with_mime(&path, &mime).oneshot(request).await?;
+    let mut resp = ServeFile::new_with_mime(path, mime).oneshot(request).await?;
 
     //  on success inject cache header into response
     if resp.status().is_success() {
@@ -116,23 +227,31 @@ pub async fn handle_fullsize(
 #[derive(Deserialize)]
 pub struct DownloadZipRequest {
     #[serde(rename = "m")]
-    items: Vec<MediaId>,
+    pub items: Vec<MediaId>,
 }
 
 #[instrument(skip_all)]
 pub async fn handle_download_zip(
-    state: State<AppState>,
-    q: Query<DownloadZipRequest>,
+    access: Access,
+    State(state): State<AppState>,
+    Query(query): Query<DownloadZipRequest>,
 ) -> Result<Response, WebError> {
+    serve_zip(&query.items, access, state).await
+}
+
+/// Streams a zip file containing the original files of the given media items.
+pub async fn serve_zip(items: &[MediaId], access: Access, state: AppState) -> Result<Response, WebError> {
     let span = debug_span!("create zip file");
 
-    // collect files to download
-    let files: Vec<_> = state
-        .store
-        .items().await
-        .into_iter().filter(|item| q.items.contains(&item.id))
-        .map(|media| state.accessor.full(&media))
-        .try_collect()?;
+    // collect files to download, all of them must be accessible
+    let mut files = Vec::new();
+    for id in items {
+        let Some(media) = access.media(*id).await else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        files.push(state.accessor.full(&media)?);
+    }
 
     // bridge a sync Write with an async Receiver
     let (w, recv) = WriteToChannel::new();
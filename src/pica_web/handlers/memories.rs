@@ -0,0 +1,97 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use chrono::{Datelike, Local, NaiveDate};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::task::block_in_place;
+use tracing::instrument;
+
+use crate::pica::memories::{self, Memory};
+use crate::pica::MediaItem;
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::api::{Annotations, FlagsQuery, MediaItemView};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoriesQuery {
+    // the day to look back from, defaults to today
+    date: Option<NaiveDate>,
+
+    // favorites and higher rated items first
+    #[serde(default)]
+    ranked: bool,
+
+    // show only one picture of each set of near duplicates
+    #[serde(default)]
+    skip_duplicates: bool,
+
+    // maximum number of items per year
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MemoryView {
+    year: i32,
+    years_ago: i32,
+
+    // number of items of the year, including the ones left out due to the limit
+    count: usize,
+
+    items: Vec<MediaItemView>,
+}
+
+/// Lists the items taken on the same day in previous years, grouped by year.
+#[instrument(skip_all)]
+pub async fn handle_memories_get(
+    user: User,
+    access: Access,
+    Query(query): Query<MemoriesQuery>,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let items = access
+        .items()
+        .await
+        .into_iter()
+        .filter(|item| filter.matches(item, annotations.flags(item.id)))
+        .collect_vec();
+
+    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+
+    let opts = memories::Options {
+        ranked: query.ranked,
+        skip_duplicates: query.skip_duplicates,
+    };
+
+    // favorites first, then by rating
+    let score = |item: &MediaItem| {
+        let flags = annotations.flags(item.id).copied().unwrap_or_default();
+        let rating = flags.rating.or(item.xmp.rating).unwrap_or_default();
+        u32::from(flags.favorite) * 10 + u32::from(rating)
+    };
+
+    let memories = block_in_place(|| memories::on_this_day(items, date, opts, score));
+
+    let memories = memories
+        .into_iter()
+        .map(|Memory { year, items }| MemoryView {
+            year,
+            years_ago: date.year() - year,
+            count: items.len(),
+            items: items
+                .into_iter()
+                .take(query.limit.unwrap_or(usize::MAX))
+                .map(|item| annotations.view(item))
+                .collect(),
+        })
+        .collect_vec();
+
+    Ok(Json(memories).into_response())
+}
@@ -1,10 +1,18 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 
+pub mod album;
 pub mod api;
 pub mod frontend;
+pub mod map;
 pub mod media;
+pub mod memories;
+pub mod place;
+pub mod share;
+pub mod tag;
+pub mod track;
 pub mod auth;
+pub mod correction;
 
 pub struct WebError(anyhow::Error);
 
@@ -0,0 +1,104 @@
+use std::cmp::Reverse;
+
+use arcstr::ArcStr;
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::pica::{album, Album, AlbumId};
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::api::{Annotations, FlagsQuery, MediaItemView};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+/// A country with its cities, or a city
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaceView {
+    name: ArcStr,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<ArcStr>,
+
+    // the virtual album with all items of this place
+    album: AlbumId,
+
+    count: usize,
+    cover: MediaItemView,
+
+    // time of the oldest and the newest item
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    cities: Vec<PlaceView>,
+}
+
+impl PlaceView {
+    fn new(region: Option<ArcStr>, album: Album, annotations: &Annotations) -> Self {
+        let from = album.items.iter().map(|item| item.info.timestamp).min().unwrap_or(album.info.timestamp);
+
+        Self {
+            name: album.info.name,
+            region,
+            album: album.info.id,
+            count: album.items.len(),
+            cover: annotations.view(album.cover),
+            from,
+            to: album.info.timestamp,
+            cities: Vec::new(),
+        }
+    }
+}
+
+/// Lists the countries and cities of the media items the user can access, the
+/// places with the most items first.
+#[instrument(skip_all)]
+pub async fn handle_places_get(
+    user: User,
+    access: Access,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let items = access
+        .items()
+        .await
+        .into_iter()
+        .filter(|item| filter.matches(item, annotations.flags(item.id)))
+        .collect_vec();
+
+    let (countries, cities): (Vec<_>, Vec<_>) = album::by_place(items)
+        .into_iter()
+        .partition(|(place, _)| place.city.is_none());
+
+    let mut cities = cities.into_iter().into_group_map_by(|(place, _)| place.country.clone());
+
+    let by_count = |place: &PlaceView| (Reverse(place.count), place.name.clone());
+
+    let countries = countries
+        .into_iter()
+        .map(|(place, album)| {
+            let mut country = PlaceView::new(None, album, &annotations);
+
+            country.cities = cities
+                .remove(&place.country)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(place, album)| PlaceView::new(place.region, album, &annotations))
+                .sorted_by_key(by_count)
+                .collect();
+
+            country
+        })
+        .sorted_by_key(by_count)
+        .collect_vec();
+
+    Ok(Json(countries).into_response())
+}
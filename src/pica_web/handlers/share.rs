@@ -0,0 +1,336 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+use arcstr::ArcStr;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use axum_login::tower_sessions::Session;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::task::block_in_place;
+use tracing::instrument;
+
+use crate::pica::db::share::{Share, ShareTarget};
+use crate::pica::{album, db, AlbumId, MediaId};
+use crate::pica_web::access::{Access, ShareAccess};
+use crate::pica_web::handlers::api::MediaItemView;
+use crate::pica_web::handlers::media::{self, DownloadZipRequest, ImageType};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+// bcrypt cost for share passwords
+const PASSWORD_COST: u32 = 10;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateShareRequest {
+    name: Option<String>,
+
+    // share either a whole album or the given items
+    album: Option<AlbumId>,
+
+    #[serde(default)]
+    items: Vec<MediaId>,
+
+    expires: Option<DateTime<Utc>>,
+    password: Option<String>,
+}
+
+/// A share as seen by the user that created it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ShareInfoView {
+    token: String,
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    album: Option<AlbumId>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    items: Vec<MediaId>,
+
+    created: DateTime<Utc>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    password: bool,
+}
+
+impl From<Share> for ShareInfoView {
+    fn from(share: Share) -> Self {
+        let (album, items) = match share.target {
+            ShareTarget::Album(relpath) => (Some(album::album_id_for_relpath(&relpath)), Vec::new()),
+            ShareTarget::Media(ids) => (None, ids.into_iter().sorted().collect_vec()),
+        };
+
+        Self {
+            token: share.token,
+            name: share.name,
+            album,
+            items,
+            created: share.created,
+            expires: share.expires,
+            password: share.passwd.is_some(),
+        }
+    }
+}
+
+/// A share as seen by the recipient. Like an album, but without anything
+/// that reveals where the files are stored.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SharedAlbumView {
+    name: String,
+    items: Vec<MediaItemView>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover: Option<MediaItemView>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<DateTime<Utc>>,
+}
+
+#[instrument(skip_all)]
+pub async fn handle_shares_get(user: User, State(state): State<AppState>) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let shares = db::share::list_shares(&mut tx, &user.name).await?;
+    tx.commit().await?;
+
+    let shares = shares.into_iter().map(ShareInfoView::from).collect_vec();
+
+    Ok(Json(shares).into_response())
+}
+
+#[instrument(skip_all)]
+pub async fn handle_shares_post(
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<CreateShareRequest>,
+) -> Result<Response, WebError> {
+    if req.expires.is_some_and(|expires| expires <= Utc::now()) {
+        return Ok((StatusCode::BAD_REQUEST, "expiry must be in the future").into_response());
+    }
+
+    // the user can only share what they can access themselves
+    let (target, default_name) = match (req.album, req.items.is_empty()) {
+        // a user created album is shared as a selection of its current items
+        (Some(id), true) if album::is_custom(id) => {
+            let mut tx = state.db.begin().await?;
+            let custom = db::album::read_album(&mut tx, id, &user.name).await?;
+            tx.commit().await?;
+
+            let Some(custom) = custom else {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            };
+
+            let mut ids = HashSet::new();
+            for id in custom.items {
+                if access.media(id).await.is_some() {
+                    ids.insert(id);
+                }
+            }
+
+            if ids.is_empty() {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            }
+
+            (ShareTarget::Media(ids), ArcStr::from(custom.name))
+        }
+
+        (Some(id), true) => {
+            let albums = album::by_directory(&state.album_config, access.items().await);
+
+            let Some(album) = albums.into_iter().find(|album| album.info.id == id) else {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            };
+
+            let Some(relpath) = album.relpath else {
+                return Ok(StatusCode::NOT_FOUND.into_response());
+            };
+
+            (ShareTarget::Album(relpath), album.info.name)
+        }
+
+        (None, false) => {
+            for id in &req.items {
+                if access.media(*id).await.is_none() {
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                }
+            }
+
+            let ids = req.items.into_iter().collect::<HashSet<_>>();
+            let name = ArcStr::from(format!("{} items", ids.len()));
+
+            (ShareTarget::Media(ids), name)
+        }
+
+        _ => return Ok((StatusCode::BAD_REQUEST, "share either an album or a list of items").into_response()),
+    };
+
+    let passwd = match req.password.filter(|password| !password.is_empty()) {
+        Some(password) => Some(block_in_place(|| bcrypt::hash(password, PASSWORD_COST))?),
+        None => None,
+    };
+
+    let share = Share {
+        token: hex::encode(rand::random::<[u8; 16]>()),
+        owner: user.name,
+        name: req.name.unwrap_or_else(|| default_name.to_string()),
+        target,
+        created: Utc::now(),
+        expires: req.expires,
+        passwd,
+    };
+
+    let mut tx = state.db.begin().await?;
+    db::share::store_share(&mut tx, &share).await?;
+    tx.commit().await?;
+
+    Ok(Json(ShareInfoView::from(share)).into_response())
+}
+
+#[instrument(skip_all)]
+pub async fn handle_share_delete(
+    Path(token): Path<String>,
+    user: User,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let deleted = db::share::delete_share(&mut tx, &token, &user.name).await?;
+    tx.commit().await?;
+
+    match deleted {
+        true => Ok(().into_response()),
+        false => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn handle_share_get(share: ShareAccess) -> Result<Response, WebError> {
+    let items = share
+        .access
+        .items()
+        .await
+        .into_iter()
+        .sorted_by_key(|item| Reverse(item.info.timestamp))
+        .collect_vec();
+
+    let view = SharedAlbumView {
+        name: share.share.name,
+        timestamp: items.first().map(|item| item.info.timestamp),
+        cover: items.first().cloned().map(MediaItemView::from),
+        items: items.into_iter().map(MediaItemView::from).collect(),
+        expires: share.share.expires,
+    };
+
+    Ok(Json(view).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UnlockRequest {
+    password: String,
+}
+
+/// Unlocks a password protected share for the current session.
+#[instrument(skip_all)]
+pub async fn handle_share_unlock(
+    Path(token): Path<String>,
+    session: Session,
+    State(state): State<AppState>,
+    Json(req): Json<UnlockRequest>,
+) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let share = db::share::read_share(&mut tx, &token).await?;
+    tx.commit().await?;
+
+    let Some(share) = share.filter(|share| !share.is_expired()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    if let Some(passwd) = &share.passwd {
+        if !block_in_place(|| bcrypt::verify(&req.password, passwd))? {
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+
+        session.insert(&ShareAccess::unlock_key(&share.token), true).await?;
+    }
+
+    Ok(().into_response())
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_thumbnail(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    media::handle_image_scaled(id, share.access, state, ImageType::Thumbnail).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_preview_sdr(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    media::handle_image_scaled(id, share.access, state, ImageType::Preview).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_preview_hdr(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    media::handle_image_scaled(id, share.access, state, ImageType::Preview).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_fullsize(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+    request: Request<Body>,
+) -> Result<Response, WebError> {
+    media::serve_fullsize(id, share.access, state, request).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_video(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+    request: Request<Body>,
+) -> Result<Response, WebError> {
+    media::serve_video(id, share.access, state, request).await
+}
+
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_motion(
+    Path((_, id, _)): Path<(String, MediaId, String)>,
+    share: ShareAccess,
+    State(state): State<AppState>,
+    request: Request<Body>,
+) -> Result<Response, WebError> {
+    media::serve_motion(id, share.access, state, request).await
+}
+
+#[instrument(skip_all)]
+pub async fn handle_download_zip(
+    share: ShareAccess,
+    State(state): State<AppState>,
+    Query(query): Query<DownloadZipRequest>,
+) -> Result<Response, WebError> {
+    media::serve_zip(&query.items, share.access, state).await
+}
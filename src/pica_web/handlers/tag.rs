@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::pica::{album, db, tag, AlbumId, MediaId};
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::api::{write_sidecars, Annotations, FlagsQuery};
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TagView {
+    tag: String,
+
+    // number of items with this tag or one of its children
+    count: usize,
+
+    // the virtual album with all of these items
+    album: AlbumId,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateTagsRequest {
+    items: Vec<MediaId>,
+
+    #[serde(default)]
+    add: Vec<String>,
+
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+/// Lists all tags of the media items the user can access, including their parents.
+#[instrument(skip_all)]
+pub async fn handle_tags_get(
+    user: User,
+    access: Access,
+    Query(filter): Query<FlagsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let annotations = Annotations::load(&state, &user).await?;
+
+    let mut counts = BTreeMap::<&str, usize>::new();
+
+    let items = access.items().await;
+
+    for item in &items {
+        if !filter.matches(item, annotations.flags(item.id)) {
+            continue;
+        }
+
+        let Some(tags) = annotations.tags(item.id) else {
+            continue;
+        };
+
+        // count every item only once per tag, even if tagged with multiple children
+        let tags = tags.iter().flat_map(|tag| tag::with_parents(tag)).unique();
+
+        for tag in tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+
+    let tags = counts
+        .into_iter()
+        .map(|(tag, count)| TagView {
+            tag: tag.to_owned(),
+            count,
+            album: album::tag_album_id(tag),
+        })
+        .collect_vec();
+
+    Ok(Json(tags).into_response())
+}
+
+/// Adds and removes tags of one or many media items. Responds with the updated items.
+#[instrument(skip_all)]
+pub async fn handle_tags_post(
+    user: User,
+    access: Access,
+    State(state): State<AppState>,
+    Json(req): Json<UpdateTagsRequest>,
+) -> Result<Response, WebError> {
+    let mut items = Vec::with_capacity(req.items.len());
+
+    for id in req.items.iter().unique() {
+        let Some(item) = access.media(*id).await else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+
+        items.push(item);
+    }
+
+    let Some(add) = req.add.iter().map(|tag| tag::normalize(tag)).collect::<Option<Vec<_>>>() else {
+        return Ok((StatusCode::BAD_REQUEST, "tags must not be empty").into_response());
+    };
+
+    let remove = req.remove.iter().filter_map(|tag| tag::normalize(tag)).collect_vec();
+
+    let mut tx = state.db.begin().await?;
+
+    for item in &items {
+        for tag in &remove {
+            db::tag::remove_tag(&mut tx, item.id, tag).await?;
+        }
+
+        for tag in &add {
+            db::tag::add_tag(&mut tx, item.id, tag).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    // let syncing clients pick up the change
+    for item in &items {
+        state.store.touch(item.id).await;
+    }
+
+    write_sidecars(&state, &user, &items, false).await?;
+
+    let annotations = Annotations::load(&state, &user).await?;
+    let items = items.into_iter().map(|item| annotations.view(item)).collect_vec();
+
+    Ok(Json(items).into_response())
+}
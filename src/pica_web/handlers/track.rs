@@ -0,0 +1,123 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use axum_extra::extract::Query;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::pica::db::track::Track;
+use crate::pica::{db, geotag, SourceId};
+use crate::pica_web::access::Access;
+use crate::pica_web::handlers::WebError;
+use crate::pica_web::{AppState, User};
+
+#[derive(Deserialize)]
+pub struct UploadTrackQuery {
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TrackView {
+    id: i64,
+    name: String,
+
+    // the source a track file was found in, none for uploaded tracks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+
+    points: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<DateTime<Utc>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end: Option<DateTime<Utc>>,
+}
+
+impl From<Track> for TrackView {
+    fn from(track: Track) -> Self {
+        Self {
+            id: track.id,
+            name: track.name,
+            source: track.source,
+            points: track.points,
+            start: track.start,
+            end: track.end,
+        }
+    }
+}
+
+/// Lists the tracks within the sources of the user and the tracks they uploaded.
+#[instrument(skip_all)]
+pub async fn handle_tracks_get(user: User, access: Access, State(state): State<AppState>) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let tracks = db::track::list_tracks(&mut tx).await?;
+    tx.commit().await?;
+
+    let tracks = tracks
+        .into_iter()
+        .filter(|track| match (&track.source, &track.owner) {
+            (Some(source), _) => access.allows_source(&SourceId::from(source.as_str())),
+            (None, owner) => owner.as_deref() == Some(user.name.as_str()),
+        })
+        .map(TrackView::from)
+        .collect_vec();
+
+    Ok(Json(tracks).into_response())
+}
+
+/// Uploads a gpx file. Its tracks are used for all sources of the user that have
+/// geotagging enabled.
+#[instrument(skip_all)]
+pub async fn handle_tracks_post(
+    user: User,
+    State(state): State<AppState>,
+    Query(query): Query<UploadTrackQuery>,
+    body: String,
+) -> Result<Response, WebError> {
+    let points = match geotag::parse_gpx(body.as_bytes()) {
+        Ok(points) if !points.is_empty() => points,
+        _ => return Ok((StatusCode::BAD_REQUEST, "no track points with a timestamp found").into_response()),
+    };
+
+    let name = query.name.unwrap_or_else(|| "track.gpx".into());
+
+    let mut tx = state.db.begin().await?;
+    let id = db::track::store_uploaded_track(&mut tx, &name, &user.name, &points).await?;
+    let track = db::track::read_track(&mut tx, id).await?;
+    tx.commit().await?;
+
+    state.geotagger.reload(&state.db).await?;
+    state.geotagger.apply_to_store(&state.store).await?;
+
+    let Some(track) = track else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok(Json(TrackView::from(track)).into_response())
+}
+
+/// Deletes an uploaded track. Tracks within a source go away with their file.
+#[instrument(skip_all, fields(? id))]
+pub async fn handle_track_delete(
+    Path(id): Path<i64>,
+    user: User,
+    State(state): State<AppState>,
+) -> Result<Response, WebError> {
+    let mut tx = state.db.begin().await?;
+    let deleted = db::track::delete_uploaded_track(&mut tx, id, &user.name).await?;
+    tx.commit().await?;
+
+    if !deleted {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    state.geotagger.reload(&state.db).await?;
+    state.geotagger.apply_to_store(&state.store).await?;
+
+    Ok(().into_response())
+}
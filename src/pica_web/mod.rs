@@ -1,5 +1,5 @@
 use anyhow::Result;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, patch, post, put};
 use axum::Router;
 use axum_login::tower_sessions::cookie::time::Duration;
 use axum_login::tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer};
@@ -9,6 +9,7 @@ use std::fmt::Display;
 use std::sync::Arc;
 use tokio::net::ToSocketAddrs;
 use tokio::signal;
+use tokio::sync::Mutex;
 use tokio::task::AbortHandle;
 use tower_http::compression::CompressionLayer;
 use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
@@ -17,12 +18,18 @@ use tower_sessions_sqlx_store::SqliteStore;
 use tracing::{info, warn, Level};
 
 use crate::pica::accessor::MediaAccessor;
+use crate::pica::geotag::Geotagger;
+use crate::pica::queue::ScanQueue;
 use crate::pica::store::MediaStore;
 
+mod access;
 mod auth;
 mod handlers;
 mod streamzip;
 
+#[cfg(test)]
+mod test;
+
 use crate::pica::album;
 use crate::pica::config::SourceConfig;
 use crate::pica_web::handlers::media::ScaleQueue;
@@ -30,6 +37,7 @@ pub use auth::User;
 
 pub struct Options<A> {
     pub store: MediaStore,
+    pub geotagger: Geotagger,
     pub accessor: MediaAccessor,
     pub sources: Vec<SourceConfig>,
     pub album_config: album::Config,
@@ -37,6 +45,7 @@ pub struct Options<A> {
     pub session_secure: bool,
     pub db: SqlitePool,
     pub users: Vec<User>,
+    pub scan_queue: Arc<Mutex<ScanQueue>>,
 }
 
 #[derive(Clone)]
@@ -46,29 +55,73 @@ pub struct AppState {
     pub sources: Vec<SourceConfig>,
     pub scale_queue: Arc<ScaleQueue>,
     pub album_config: album::Config,
+    pub geotagger: Geotagger,
+    pub db: SqlitePool,
+
+    // shared with the scanners, to let them know about the sidecars we write
+    pub scan_queue: Arc<Mutex<ScanQueue>>,
+}
+
+impl AppState {
+    pub fn new(
+        store: MediaStore,
+        accessor: MediaAccessor,
+        sources: Vec<SourceConfig>,
+        album_config: album::Config,
+        geotagger: Geotagger,
+        db: SqlitePool,
+        scan_queue: Arc<Mutex<ScanQueue>>,
+    ) -> Self {
+        let scale_queue = Arc::new(ScaleQueue::new(accessor.clone()));
+
+        for _idx in 0..8 {
+            let queue = scale_queue.clone();
+            tokio::spawn(async move { queue.work().await });
+        }
+
+        Self {
+            store,
+            accessor,
+            sources,
+            album_config,
+            scale_queue,
+            geotagger,
+            db,
+            scan_queue,
+        }
+    }
 }
 
 pub async fn serve<A>(opts: Options<A>) -> Result<()>
 where
     A: ToSocketAddrs + Display,
 {
-    let scale_queue = Arc::new(ScaleQueue::new(opts.accessor.clone()));
+    let state = AppState::new(
+        opts.store,
+        opts.accessor,
+        opts.sources,
+        opts.album_config,
+        opts.geotagger,
+        opts.db,
+        opts.scan_queue,
+    );
 
-    for _idx in 0..8 {
-        let queue = scale_queue.clone();
-        tokio::spawn(async move { queue.work().await });
-    }
+    let (app, delete_task) = router(state, opts.users, opts.session_secure).await?;
 
-    let state = AppState {
-        store: opts.store,
-        accessor: opts.accessor,
-        sources: opts.sources,
-        album_config: opts.album_config,
-        scale_queue,
-    };
+    info!("Starting webserver on http://{}/", opts.addr);
+    let listener = tokio::net::TcpListener::bind(opts.addr).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(delete_task))
+        .await?;
+
+    Ok(())
+}
 
+/// Builds the router serving the api, the media files and the frontend. Also returns
+/// the handle of a task that cleans up expired sessions in the background.
+async fn router(state: AppState, users: Vec<User>, session_secure: bool) -> Result<(Router, AbortHandle)> {
     info!("Create session store in database");
-    let session_store = SqliteStore::new(opts.db);
+    let session_store = SqliteStore::new(state.db.clone());
     session_store.migrate().await?;
 
     // cleanup expired sessions from time to time
@@ -78,24 +131,48 @@ where
             .continuously_delete_expired(std::time::Duration::from_secs(60)),
     );
 
-    if !opts.session_secure {
+    if !session_secure {
         warn!("Secure session cookie is disabled. If you serve pica over ssl only it is best to disable the config option 'allowAccessOverHTTP'");
     }
 
     let session_layer = SessionManagerLayer::new(session_store)
         .with_expiry(Expiry::OnInactivity(Duration::weeks(4)))
-        .with_secure(opts.session_secure);
+        .with_secure(session_secure);
 
-    let auth_backend = auth::Backend::from(opts.users);
+    let auth_backend = auth::Backend::from(users);
 
     let auth_layer = AuthManagerLayerBuilder::new(auth_backend, session_layer).build();
 
     let app = Router::new()
         .route("/api/stream", get(handlers::api::handle_stream_get))
+        .route("/api/stream/changes", get(handlers::api::handle_stream_changes_get))
         .route("/api/albums", get(handlers::api::handle_albums_get))
+        .route("/api/albums", post(handlers::album::handle_album_post))
         .route("/api/albums/full", get(handlers::api::handle_albums_get_full))
+        .route("/api/albums/custom", get(handlers::album::handle_custom_albums_get))
         .route("/api/albums/{id}", get(handlers::api::handle_album_get))
+        .route("/api/albums/{id}", patch(handlers::album::handle_album_patch))
+        .route("/api/albums/{id}", delete(handlers::album::handle_album_delete))
+        .route("/api/albums/{id}/items", post(handlers::album::handle_album_items_post))
+        .route("/api/albums/{id}/items", put(handlers::album::handle_album_items_put))
+        .route("/api/albums/{id}/items", delete(handlers::album::handle_album_items_delete))
+        .route("/api/albums/{id}/correction", put(handlers::correction::handle_album_correction_put))
         .route("/api/media/{id}/exif", get(handlers::api::handle_exif_get))
+        .route("/api/media/{id}/flags", put(handlers::api::handle_flags_put))
+        .route("/api/media/{id}/correction", put(handlers::correction::handle_media_correction_put))
+        .route("/api/duplicates", get(handlers::api::handle_duplicates_get))
+        .route("/api/search", get(handlers::api::handle_search_get))
+        .route("/api/map", get(handlers::map::handle_map_get))
+        .route("/api/places", get(handlers::place::handle_places_get))
+        .route("/api/memories", get(handlers::memories::handle_memories_get))
+        .route("/api/tags", get(handlers::tag::handle_tags_get))
+        .route("/api/tags", post(handlers::tag::handle_tags_post))
+        .route("/api/tracks", get(handlers::track::handle_tracks_get))
+        .route("/api/tracks", post(handlers::track::handle_tracks_post))
+        .route("/api/tracks/{id}", delete(handlers::track::handle_track_delete))
+        .route("/api/shares", get(handlers::share::handle_shares_get))
+        .route("/api/shares", post(handlers::share::handle_shares_post))
+        .route("/api/shares/{token}", delete(handlers::share::handle_share_delete))
         .layer(CompressionLayer::new().gzip(true).quality(CompressionLevel::Fastest))
         .route("/media/thumb/{id}/{*path}", get(handlers::media::handle_thumbnail))
         .route(
@@ -107,10 +184,22 @@ where
             get(handlers::media::handle_preview_hdr),
         )
         .route("/media/fullsize/{id}/{*path}", get(handlers::media::handle_fullsize))
+        .route("/media/video/{id}/{*path}", get(handlers::media::handle_video))
+        .route("/media/motion/{id}/{*path}", get(handlers::media::handle_motion))
         .route("/media/multi", get(handlers::media::handle_download_zip))
         .route("/api/auth/touch", post(handlers::auth::touch))
         .route_layer(login_required!(auth::Backend))
         .route("/api/auth/login", post(handlers::auth::login))
+        // share links can be opened without an account
+        .route("/api/share/{token}", get(handlers::share::handle_share_get))
+        .route("/api/share/{token}/unlock", post(handlers::share::handle_share_unlock))
+        .route("/share/{token}/media/thumb/{id}/{*path}", get(handlers::share::handle_thumbnail))
+        .route("/share/{token}/media/preview/sdr/{id}/{*path}", get(handlers::share::handle_preview_sdr))
+        .route("/share/{token}/media/preview/hdr/{id}/{*path}", get(handlers::share::handle_preview_hdr))
+        .route("/share/{token}/media/fullsize/{id}/{*path}", get(handlers::share::handle_fullsize))
+        .route("/share/{token}/media/video/{id}/{*path}", get(handlers::share::handle_video))
+        .route("/share/{token}/media/motion/{id}/{*path}", get(handlers::share::handle_motion))
+        .route("/share/{token}/media/multi", get(handlers::share::handle_download_zip))
         .layer(auth_layer)
         .fallback_service(handlers::frontend::frontend())
         .layer(
@@ -120,13 +209,7 @@ where
         )
         .with_state(state);
 
-    info!("Starting webserver on http://{}/", opts.addr);
-    let listener = tokio::net::TcpListener::bind(opts.addr).await?;
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(delete_task.abort_handle()))
-        .await?;
-
-    Ok(())
+    Ok((app, delete_task.abort_handle()))
 }
 
 async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
@@ -0,0 +1,1493 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure, Result};
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use chrono::{Duration, SecondsFormat, Utc};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+
+use crate::pica::accessor::{MediaAccessor, Sizes, Storage};
+use crate::pica::config::{GeotagConfig, SourceConfig};
+use crate::pica::db::correction::Correction;
+use crate::pica::db::share::{Share, ShareTarget};
+use crate::pica::geotag::Geotagger;
+use crate::pica::index::{Indexer, Scanner};
+use crate::pica::queue::{QueueItem, ScanQueue};
+use crate::pica::scale::{Image, ImageType, MediaScaler, Options};
+use crate::pica::store::MediaStore;
+use crate::pica::{album, db, index, Identity, MediaHashes, MediaId, MediaInfo, MediaItem, XmpInfo};
+use crate::pica_web::{router, AppState, User};
+
+// htpasswd hash of the password 'docker'
+const PASSWD: &str = "$2y$07$vhLM7t39q9VNvc7m5r9cgeSFgFrOIMbHtXIxT.ZiNiuKmIsfUH.5u";
+
+/// Two sources, 'private' can only be accessed by alice, 'shared' by alice and bob.
+/// Only 'shared' writes xmp sidecars and infers locations from gpx tracks.
+struct TestApp {
+    app: Router,
+    db: SqlitePool,
+    store: MediaStore,
+    queue: Arc<Mutex<ScanQueue>>,
+    private: MediaItem,
+    shared: MediaItem,
+    dir: TempDir,
+}
+
+impl TestApp {
+    async fn new() -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+
+        let mut private = media_item(dir.path(), "private", [1; 8])?;
+        private.xmp = XmpInfo {
+            rating: Some(2),
+            label: Some("Red".into()),
+            keywords: ["alps".into(), "hiking".into()].into(),
+            description: Some("On the way up".into()),
+        };
+
+        let shared = media_item(dir.path(), "shared", [2; 8])?;
+
+        let mut sources = vec![
+            source_config(dir.path(), "private", &["alice"]),
+            source_config(dir.path(), "shared", &["alice", "bob"]),
+        ];
+
+        // edits of shared items are written to xmp sidecars
+        sources[1].write_xmp_sidecars = true;
+
+        // shared items without gps data are located using gpx tracks
+        sources[1].geotag = Some(GeotagConfig {
+            camera_offset_in_seconds: 0,
+            max_gap_in_seconds: 300,
+        });
+
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+
+        sqlx::migrate!("./sql").run(&db).await?;
+
+        let store = MediaStore::empty();
+
+        // scaled images reference the cached media item
+        let mut tx = db.begin().await?;
+        for item in [&private, &shared] {
+            db::media::store_media_item(&mut tx, item, Utc::now(), None, Identity::Path).await?;
+            store.add(item.clone()).await;
+        }
+        tx.commit().await?;
+
+        let scaler = MediaScaler::new(Options {
+            prefer_ultra_hdr: false,
+            use_image_magick: false,
+            image_type: ImageType::Jpeg,
+        });
+
+        let sizes = Sizes { thumb: 16, preview: 32 };
+        let roots = sources.iter().map(|s| (s.name.clone(), s.path.clone())).collect::<HashMap<_, _>>();
+        let accessor = MediaAccessor::new(Storage::new(db.clone()), scaler, sizes, roots);
+
+        let album_config = album::Config {
+            classify_as_album: regex::bytes::Regex::new(".*")?,
+            strip_title: None,
+        };
+
+        let geotagger = Geotagger::new(&sources);
+        let queue = Arc::new(Mutex::new(ScanQueue::default()));
+        let state = AppState::new(store.clone(), accessor, sources, album_config, geotagger, db.clone(), queue.clone());
+
+        let users = vec![User::new("alice", PASSWD), User::new("bob", PASSWD)];
+        let (app, _) = router(state, users, false).await?;
+
+        Ok(Self {
+            app,
+            db,
+            store,
+            queue,
+            private,
+            shared,
+            dir,
+        })
+    }
+
+    /// Logs in and returns the session cookie.
+    async fn login(&self, user: &str) -> Result<String> {
+        let body = serde_json::json!({ "username": user, "password": "docker" });
+
+        let (status, cookie, _) = self.post("", "/api/auth/login", body).await?;
+        ensure!(status == StatusCode::OK, "login failed: {}", status);
+
+        Ok(cookie.expect("session cookie"))
+    }
+
+    /// Posts json and returns the status, the new session cookie if any, and the body.
+    async fn post(&self, cookie: &str, uri: &str, body: Value) -> Result<(StatusCode, Option<String>, Vec<u8>)> {
+        self.send(Method::POST, cookie, uri, body).await
+    }
+
+    async fn send(&self, method: Method, cookie: &str, uri: &str, body: Value) -> Result<(StatusCode, Option<String>, Vec<u8>)> {
+        self.send_raw(method, cookie, uri, "application/json", body.to_string()).await
+    }
+
+    async fn send_raw(
+        &self,
+        method: Method,
+        cookie: &str,
+        uri: &str,
+        content_type: &str,
+        body: String,
+    ) -> Result<(StatusCode, Option<String>, Vec<u8>)> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::COOKIE, cookie)
+            .body(Body::from(body))?;
+
+        let resp = self.app.clone().oneshot(request).await?;
+        let status = resp.status();
+
+        let cookie = match resp.headers().get(header::SET_COOKIE) {
+            Some(cookie) => Some(cookie.to_str()?.split(';').next().unwrap_or_default().to_owned()),
+            None => None,
+        };
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+
+        Ok((status, cookie, body.to_vec()))
+    }
+
+    /// Creates a share and returns its token.
+    async fn share(&self, cookie: &str, body: Value) -> Result<String> {
+        let (status, _, body) = self.post(cookie, "/api/shares", body).await?;
+        ensure!(status == StatusCode::OK, "creating share failed: {}", status);
+
+        let share: Value = serde_json::from_slice(&body)?;
+        Ok(share["token"].as_str().expect("token").to_owned())
+    }
+
+    async fn get(&self, cookie: &str, uri: &str) -> Result<(StatusCode, Vec<u8>)> {
+        let request = Request::get(uri).header(header::COOKIE, cookie).body(Body::empty())?;
+
+        let resp = self.app.clone().oneshot(request).await?;
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await?;
+
+        Ok((status, body.to_vec()))
+    }
+
+    async fn get_json(&self, cookie: &str, uri: &str) -> Result<Value> {
+        let (status, body) = self.get(cookie, uri).await?;
+        ensure!(status == StatusCode::OK, "GET {} failed: {}", uri, status);
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Adds a shared item for each timestamp to the store, after passing it through `update`
+    /// together with its index. Returns the added items.
+    async fn add_items(
+        &self,
+        timestamps: impl IntoIterator<Item = &str>,
+        update: impl Fn(usize, MediaItem) -> Result<MediaItem>,
+    ) -> Result<Vec<MediaItem>> {
+        let mut items = Vec::new();
+
+        for (idx, timestamp) in timestamps.into_iter().enumerate() {
+            let mut item = media_item(self.dir.path(), "shared", [0x10, idx as u8, 0, 0, 0, 0, 0, 0])?;
+            item.info.timestamp = timestamp.parse()?;
+
+            let item = update(idx, item)?;
+            self.store.add(item.clone()).await;
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+}
+
+fn media_item(root: &Path, source: &str, id: [u8; 8]) -> Result<MediaItem> {
+    let path = root.join(source).join("Album").join(format!("{}.jpg", source));
+    std::fs::create_dir_all(path.parent().unwrap())?;
+
+    image::RgbImage::from_pixel(64, 48, image::Rgb([200, 100, 50])).save(&path)?;
+
+    let info = MediaInfo {
+        timestamp: Utc::now(),
+        width: 64,
+        height: 48,
+        latitude: None,
+        longitude: None,
+        duration: None,
+        camera: None,
+    };
+
+    let filesize = std::fs::metadata(&path)?.len();
+    MediaItem::from_media_info(MediaId::from(id), source.into(), path, filesize, info)
+}
+
+fn source_config(root: &Path, name: &str, access: &[&str]) -> SourceConfig {
+    SourceConfig {
+        name: name.into(),
+        path: root.join(name),
+        access: access.iter().map(|user| user.to_string()).collect(),
+        watch: false,
+        rescan_interval_in_seconds: None,
+        identity: Identity::Path,
+        write_xmp_sidecars: false,
+        geotag: None,
+    }
+}
+
+fn share_media_uris(token: &str, id: MediaId) -> Vec<String> {
+    vec![
+        format!("/share/{}/media/thumb/{}/image.jpg", token, id),
+        format!("/share/{}/media/preview/sdr/{}/image.jpg", token, id),
+        format!("/share/{}/media/preview/hdr/{}/image.jpg", token, id),
+        format!("/share/{}/media/fullsize/{}/image.jpg", token, id),
+        format!("/share/{}/media/multi?m={}", token, id),
+    ]
+}
+
+fn media_uris(id: MediaId) -> Vec<String> {
+    vec![
+        format!("/media/thumb/{}/image.jpg", id),
+        format!("/media/preview/sdr/{}/image.jpg", id),
+        format!("/media/preview/hdr/{}/image.jpg", id),
+        format!("/media/fullsize/{}/image.jpg", id),
+        format!("/media/multi?m={}", id),
+        format!("/api/media/{}/exif", id),
+    ]
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_media_of_other_sources_is_not_found() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    for uri in media_uris(app.private.id) {
+        let (status, _) = app.get(&bob, &uri).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "GET {}", uri);
+    }
+
+    for uri in [
+        format!("/media/video/{}/video.mp4", app.private.id),
+        format!("/media/motion/{}/video.mp4", app.private.id),
+        format!("/media/multi?m={}&m={}", app.shared.id, app.private.id),
+    ] {
+        let (status, _) = app.get(&bob, &uri).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_media_of_own_sources_is_served() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+    let alice = app.login("alice").await?;
+
+    for uri in media_uris(app.shared.id) {
+        let (status, _) = app.get(&bob, &uri).await?;
+        assert_eq!(status, StatusCode::OK, "GET {}", uri);
+    }
+
+    for uri in media_uris(app.private.id) {
+        let (status, _) = app.get(&alice, &uri).await?;
+        assert_eq!(status, StatusCode::OK, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_albums_of_other_sources_are_not_found() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+    let alice = app.login("alice").await?;
+
+    let albums = app.get_json(&alice, "/api/albums/full").await?;
+    let albums = albums.as_array().expect("albums");
+    assert_eq!(albums.len(), 2);
+
+    for album in albums {
+        let id = album["id"].as_str().expect("album id");
+        let private = album["items"][0]["id"] == app.private.id.to_string();
+
+        let (status, _) = app.get(&bob, &format!("/api/albums/{}", id)).await?;
+        let expected = if private { StatusCode::NOT_FOUND } else { StatusCode::OK };
+        assert_eq!(status, expected, "album {}", id);
+    }
+
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(1));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_listings_only_contain_own_sources() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    let shared = serde_json::json!([app.shared.id.to_string()]);
+
+    for uri in ["/api/stream", "/api/search"] {
+        let listing = app.get_json(&bob, uri).await?;
+
+        let ids: Vec<_> = listing["items"].as_array().expect("items").iter().map(|item| item["id"].clone()).collect();
+        assert_eq!(Value::from(ids), shared, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stream_pages() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    // a limit of zero still returns an item, otherwise the client would stop
+    for limit in [0, 1] {
+        let first = app.get_json(&alice, &format!("/api/stream?limit={}", limit)).await?;
+        assert_eq!(first["items"].as_array().map(Vec::len), Some(1));
+
+        let cursor = first["nextCursor"].as_str().expect("next cursor");
+        let second = app.get_json(&alice, &format!("/api/stream?limit={}&cursor={}", limit, cursor)).await?;
+        assert_eq!(second["items"].as_array().map(Vec::len), Some(1));
+        assert_ne!(second["items"][0]["id"], first["items"][0]["id"]);
+        assert!(second["nextCursor"].is_null());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_login_is_required() -> Result<()> {
+    let app = TestApp::new().await?;
+
+    let uris = media_uris(app.shared.id).into_iter().chain(["/api/stream".to_owned(), "/api/albums".to_owned()]);
+
+    for uri in uris {
+        let (status, _) = app.get("", &uri).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_serves_only_its_items() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let token = app.share(&alice, serde_json::json!({ "items": [app.private.id] })).await?;
+
+    // no login required to open the share
+    let share = app.get_json("", &format!("/api/share/{}", token)).await?;
+    assert_eq!(share["items"].as_array().map(Vec::len), Some(1));
+    assert_eq!(share["items"][0]["id"], app.private.id.to_string());
+    assert!(share.get("relpath").is_none());
+
+    for uri in share_media_uris(&token, app.private.id) {
+        let (status, _) = app.get("", &uri).await?;
+        assert_eq!(status, StatusCode::OK, "GET {}", uri);
+    }
+
+    for uri in share_media_uris(&token, app.shared.id) {
+        let (status, _) = app.get("", &uri).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "GET {}", uri);
+    }
+
+    for uri in share_media_uris("unknown", app.private.id) {
+        let (status, _) = app.get("", &uri).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_album() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    let album = albums[0]["id"].clone();
+
+    let token = app.share(&bob, serde_json::json!({ "album": album, "name": "Holiday" })).await?;
+
+    let share = app.get_json("", &format!("/api/share/{}", token)).await?;
+    assert_eq!(share["name"], "Holiday");
+    assert_eq!(share["items"][0]["id"], app.shared.id.to_string());
+    assert_eq!(share["items"].as_array().map(Vec::len), Some(1));
+
+    let (status, _) = app.get("", &format!("/share/{}/media/thumb/{}/image.jpg", token, app.private.id)).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_only_accessible_items() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    let (status, _, _) = app.post(&bob, "/api/shares", serde_json::json!({ "items": [app.private.id] })).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let expired = Utc::now() - Duration::hours(1);
+    let body = serde_json::json!({ "items": [app.shared.id], "expires": expired });
+    let (status, _, _) = app.post(&bob, "/api/shares", body).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_password() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let body = serde_json::json!({ "items": [app.shared.id], "password": "secret" });
+    let token = app.share(&alice, body).await?;
+
+    let uris = share_media_uris(&token, app.shared.id).into_iter().chain([format!("/api/share/{}", token)]);
+    let uris = uris.collect::<Vec<_>>();
+
+    for uri in &uris {
+        let (status, _) = app.get("", uri).await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "GET {}", uri);
+    }
+
+    let unlock = format!("/api/share/{}/unlock", token);
+
+    let (status, _, _) = app.post("", &unlock, serde_json::json!({ "password": "wrong" })).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, cookie, _) = app.post("", &unlock, serde_json::json!({ "password": "secret" })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let cookie = cookie.expect("session cookie");
+    for uri in &uris {
+        let (status, _) = app.get(&cookie, uri).await?;
+        assert_eq!(status, StatusCode::OK, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_share_revoked_or_expired() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    let token = app.share(&alice, serde_json::json!({ "items": [app.shared.id] })).await?;
+    let uri = format!("/api/share/{}", token);
+
+    let shares = app.get_json(&alice, "/api/shares").await?;
+    assert_eq!(shares[0]["token"], token);
+
+    // only the owner can revoke a share
+    let request = |cookie: &str| Request::delete(format!("/api/shares/{}", token)).header(header::COOKIE, cookie).body(Body::empty());
+
+    let resp = app.app.clone().oneshot(request(&bob)?).await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(app.get("", &uri).await?.0, StatusCode::OK);
+
+    let resp = app.app.clone().oneshot(request(&alice)?).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(app.get("", &uri).await?.0, StatusCode::NOT_FOUND);
+
+    // shares that expired are gone too
+    let share = Share {
+        token: "expired".into(),
+        owner: "alice".into(),
+        name: "Expired".into(),
+        target: ShareTarget::Media([app.shared.id].into()),
+        created: Utc::now() - Duration::days(2),
+        expires: Some(Utc::now() - Duration::days(1)),
+        passwd: None,
+    };
+
+    let mut tx = app.db.begin().await?;
+    db::share::store_share(&mut tx, &share).await?;
+    tx.commit().await?;
+
+    for uri in share_media_uris("expired", app.shared.id).into_iter().chain(["/api/share/expired".to_owned()]) {
+        let (status, _) = app.get("", &uri).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND, "GET {}", uri);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_custom_albums() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    let body = serde_json::json!({
+        "name": "Favorites",
+        "description": "The best ones",
+        "items": [app.private.id, app.shared.id],
+        "cover": app.shared.id,
+    });
+
+    let (status, _, body) = app.post(&alice, "/api/albums", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let album: Value = serde_json::from_slice(&body)?;
+    let id = album["id"].as_str().expect("album id").to_owned();
+    let uri = format!("/api/albums/{}", id);
+
+    // listed alongside the directory albums, in the chosen order
+    let albums = app.get_json(&alice, "/api/albums/full").await?;
+    let custom = albums.as_array().expect("albums").iter().find(|album| album["id"] == id.as_str()).expect("custom album");
+    assert_eq!(albums.as_array().map(Vec::len), Some(3));
+    assert_eq!(custom["description"], "The best ones");
+    assert_eq!(custom["cover"]["id"], app.shared.id.to_string());
+    assert_eq!(custom["items"][0]["id"], app.private.id.to_string());
+    assert_eq!(custom["items"][1]["id"], app.shared.id.to_string());
+
+    // reorder, rename and remove
+    let (status, _, _) = app.send(Method::PUT, &alice, &format!("{}/items", uri), serde_json::json!({ "items": [app.shared.id] })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = app.send(Method::PATCH, &alice, &uri, serde_json::json!({ "name": "Best" })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let album = app.get_json(&alice, &uri).await?;
+    assert_eq!(album["name"], "Best");
+    assert_eq!(album["items"][0]["id"], app.shared.id.to_string());
+    assert_eq!(album["items"][1]["id"], app.private.id.to_string());
+
+    let remove = format!("{}/items?m={}", uri, app.shared.id);
+    let (status, _, _) = app.send(Method::DELETE, &alice, &remove, Value::Null).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let album = app.get_json(&alice, &uri).await?;
+    assert_eq!(album["items"].as_array().map(Vec::len), Some(1));
+    assert_eq!(album["cover"]["id"], app.private.id.to_string());
+
+    // albums are private to the user that created them
+    let (status, _) = app.get(&bob, &uri).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, _) = app.send(Method::PATCH, &bob, &uri, serde_json::json!({ "name": "Mine" })).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(1));
+
+    let (status, _, _) = app.send(Method::DELETE, &alice, &uri, Value::Null).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app.get(&alice, &uri).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_custom_albums_only_contain_accessible_items() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    let body = serde_json::json!({ "name": "Stolen", "items": [app.private.id] });
+    let (status, _, _) = app.post(&bob, "/api/albums", body).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // empty albums are only listed for managing them
+    let (status, _, body) = app.post(&bob, "/api/albums", serde_json::json!({ "name": "Empty" })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let album: Value = serde_json::from_slice(&body)?;
+    let items = format!("/api/albums/{}/items", album["id"].as_str().expect("album id"));
+
+    let (status, _, _) = app.post(&bob, &items, serde_json::json!({ "items": [app.private.id] })).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(1));
+
+    let custom = app.get_json(&bob, "/api/albums/custom").await?;
+    assert_eq!(custom[0]["name"], "Empty");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_flags_are_per_user() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    let flags = format!("/api/media/{}/flags", app.shared.id);
+
+    let (status, _, body) = app.send(Method::PUT, &alice, &flags, serde_json::json!({ "favorite": true, "rating": 4 })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let body: Value = serde_json::from_slice(&body)?;
+    assert_eq!(body, serde_json::json!({ "favorite": true, "hidden": false, "rating": 4 }));
+
+    // other flags stay unchanged
+    let (status, _, _) = app.send(Method::PUT, &alice, &flags, serde_json::json!({ "hidden": false })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let item = app.get_json(&alice, &format!("/api/media/{}/exif", app.shared.id)).await?;
+    assert_eq!(item["item"]["favorite"], true);
+    assert_eq!(item["item"]["rating"], 4);
+
+    let item = app.get_json(&bob, &format!("/api/media/{}/exif", app.shared.id)).await?;
+    assert!(item["item"].get("favorite").is_none());
+    assert!(item["item"].get("rating").is_none());
+
+    // only accessible items can be marked
+    let private = format!("/api/media/{}/flags", app.private.id);
+    let (status, _, _) = app.send(Method::PUT, &bob, &private, serde_json::json!({ "favorite": true })).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, _) = app.send(Method::PUT, &alice, &flags, serde_json::json!({ "rating": 6 })).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_filter_by_flags() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let ids = |listing: &Value| -> Vec<Value> {
+        let items = listing["items"].as_array().expect("items");
+        items.iter().map(|item| item["id"].clone()).collect()
+    };
+
+    let stream = app.get_json(&alice, "/api/stream").await?;
+    assert_eq!(ids(&stream).len(), 2);
+
+    let token = stream["syncToken"].as_str().expect("sync token").to_owned();
+
+    let hide = serde_json::json!({ "hidden": true });
+    app.send(Method::PUT, &alice, &format!("/api/media/{}/flags", app.private.id), hide).await?;
+
+    let favorite = serde_json::json!({ "favorite": true, "rating": 3 });
+    app.send(Method::PUT, &alice, &format!("/api/media/{}/flags", app.shared.id), favorite).await?;
+
+    let shared = vec![Value::from(app.shared.id.to_string())];
+
+    // hidden items are left out by default
+    for uri in ["/api/stream", "/api/search", "/api/stream?favorites=true", "/api/search?minRating=3"] {
+        let listing = app.get_json(&alice, uri).await?;
+        assert_eq!(ids(&listing), shared, "GET {}", uri);
+    }
+
+    let listing = app.get_json(&alice, "/api/stream?includeHidden=true").await?;
+    assert_eq!(ids(&listing).len(), 2);
+
+    let listing = app.get_json(&alice, "/api/stream?minRating=4").await?;
+    assert!(ids(&listing).is_empty());
+
+    // albums only contain matching items
+    let albums = app.get_json(&alice, "/api/albums").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(1));
+
+    let albums = app.get_json(&alice, "/api/albums?includeHidden=true").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(2));
+
+    // clients syncing incrementally see hidden items as removed
+    let changes = app.get_json(&alice, &format!("/api/stream/changes?since={}", token)).await?;
+    assert_eq!(changes["removed"], serde_json::json!([app.private.id.to_string()]));
+    assert_eq!(changes["added"][0]["favorite"], true);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_xmp_metadata() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let exif = format!("/api/media/{}/exif", app.private.id);
+
+    let item = app.get_json(&alice, &exif).await?;
+    assert_eq!(item["item"]["rating"], 2);
+    assert_eq!(item["item"]["label"], "Red");
+    assert_eq!(item["item"]["keywords"], serde_json::json!(["alps", "hiking"]));
+    assert_eq!(item["item"]["description"], "On the way up");
+
+    // the rating of the user wins over the one in the file
+    let flags = format!("/api/media/{}/flags", app.private.id);
+    app.send(Method::PUT, &alice, &flags, serde_json::json!({ "rating": 5 })).await?;
+
+    let item = app.get_json(&alice, &exif).await?;
+    assert_eq!(item["item"]["rating"], 5);
+
+    let private = vec![Value::from(app.private.id.to_string())];
+
+    for uri in ["/api/search?keyword=Alps", "/api/search?label=red", "/api/search?q=hiking", "/api/search?q=way"] {
+        let listing = app.get_json(&alice, uri).await?;
+        let ids: Vec<Value> = listing["items"].as_array().expect("items").iter().map(|item| item["id"].clone()).collect();
+        assert_eq!(ids, private, "GET {}", uri);
+    }
+
+    let listing = app.get_json(&alice, "/api/search?keyword=alp").await?;
+    assert_eq!(listing["total"], 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tags() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    let both = serde_json::json!([app.private.id.to_string(), app.shared.id.to_string()]);
+    let body = serde_json::json!({ "items": both, "add": ["people / anna", "places/alps"] });
+
+    let (status, _, body) = app.post(&alice, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let items: Value = serde_json::from_slice(&body)?;
+    assert_eq!(items[0]["tags"], serde_json::json!(["people/anna", "places/alps"]));
+
+    let body = serde_json::json!({ "items": [app.shared.id.to_string()], "add": ["people/bob"], "remove": ["places/alps"] });
+    let (status, _, _) = app.post(&alice, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // parents count every item once
+    let tags = app.get_json(&alice, "/api/tags").await?;
+    let counts: Vec<(Value, Value)> = tags.as_array().expect("tags").iter().map(|tag| (tag["tag"].clone(), tag["count"].clone())).collect();
+    assert_eq!(
+        counts,
+        [("people", 2), ("people/anna", 2), ("people/bob", 1), ("places", 1), ("places/alps", 1)]
+            .map(|(tag, count)| (Value::from(tag), Value::from(count))),
+    );
+
+    // bob only sees the tags of the items he can access
+    let tags = app.get_json(&bob, "/api/tags").await?;
+    assert_eq!(tags.as_array().map(Vec::len), Some(3));
+
+    let people = album::tag_album_id("people");
+    let album = app.get_json(&alice, &format!("/api/albums/{}", people)).await?;
+    assert_eq!(album["name"], "people");
+    assert_eq!(album["items"].as_array().map(Vec::len), Some(2));
+
+    // every tag and parent is listed as an album
+    let albums = app.get_json(&alice, "/api/albums/full?kind=tag").await?;
+    let counts: HashMap<String, usize> = albums
+        .as_array()
+        .expect("albums")
+        .iter()
+        .map(|album| (album["name"].as_str().unwrap_or_default().to_owned(), album["items"].as_array().map_or(0, Vec::len)))
+        .collect();
+
+    let expected = [("people", 2), ("people/anna", 2), ("people/bob", 1), ("places", 1), ("places/alps", 1)];
+    assert_eq!(counts, expected.map(|(tag, count)| (tag.to_owned(), count)).into());
+
+    let alps = format!("/api/albums/{}", album::tag_album_id("places/alps"));
+    assert_eq!(app.get(&bob, &alps).await?.0, StatusCode::NOT_FOUND);
+
+    // only accessible items can be tagged
+    let body = serde_json::json!({ "items": [app.private.id.to_string()], "add": ["x"] });
+    let (status, _, _) = app.post(&bob, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let body = serde_json::json!({ "items": [app.shared.id.to_string()], "add": [" / "] });
+    let (status, _, _) = app.post(&bob, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_write_xmp_sidecars() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    // index the shared source for real, so that the sidecars we write are read again. The
+    // scanner skips tiny files, use some noise to get a file large enough.
+    let root = app.shared.relpath.parent().and_then(Path::parent).expect("source root").to_owned();
+
+    let noise = image::RgbImage::from_fn(256, 256, |x, y| image::Rgb([(x * 7 + y * 13) as u8, (x * y) as u8, (x ^ y) as u8]));
+    noise.save(root.join("Album").join("noise.jpg"))?;
+
+    let mut scanner = Scanner::new(&root, app.queue.clone(), "shared", Identity::Path);
+    let indexer = tokio::spawn(Indexer::new(app.db.clone(), app.queue.clone(), app.store.clone(), None, Geotagger::new(&[])).run());
+
+    let item = rescan(&app.store, &mut scanner).await?.pop().expect("indexed item");
+    let exif = format!("/api/media/{}/exif", item.id);
+    let flags = format!("/api/media/{}/flags", item.id);
+
+    for id in [app.private.id, item.id] {
+        let flags = format!("/api/media/{}/flags", id);
+        let (status, _, _) = app.send(Method::PUT, &alice, &flags, serde_json::json!({ "rating": 4 })).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let body = serde_json::json!({ "items": [item.id.to_string()], "add": ["people/anna"] });
+    let (status, _, _) = app.post(&alice, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(!index::xmp_sidecar_path(&app.private.relpath, "xmp").exists());
+
+    let sidecar = index::xmp_sidecar_path(&root.join(item.relpath.as_ref()), "xmp");
+
+    let metadata = ultrahdr_rs::xmp_metadata(std::fs::read(&sidecar)?)?;
+    assert_eq!(metadata.rating, Some(4));
+    assert_eq!(metadata.keywords, ["people/anna"]);
+
+    // ratings are per user, only the ones of the owner of the source are written
+    let bob = app.login("bob").await?;
+    let (status, _, _) = app.send(Method::PUT, &bob, &flags, serde_json::json!({ "rating": 2 })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let metadata = ultrahdr_rs::xmp_metadata(std::fs::read(&sidecar)?)?;
+    assert_eq!(metadata.rating, Some(4));
+
+    // the scanner does not read the sidecar again
+    {
+        let mut tx = app.db.begin().await?;
+        let cached = db::media::read_media_item(&mut tx, item.id).await?.expect("cached item");
+        assert!(cached.xmp_mtime.is_some());
+    }
+
+    let token = app.store.page(None, 0, |_| true).await.token;
+    scanner.scan().await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert!(app.store.changes_since(token).await.expect("changes").added.is_empty());
+
+    // unless another tool changed it. Our own tags do not show up as keywords of the file.
+    touch(&sidecar, 60)?;
+    let rescanned = rescan(&app.store, &mut scanner).await?;
+    assert!(rescanned[0].xmp.keywords.is_empty());
+    assert_eq!(rescanned[0].xmp.rating, Some(4));
+
+    let view = app.get_json(&alice, &exif).await?;
+    assert_eq!(view["item"]["tags"], serde_json::json!(["people/anna"]));
+    assert_eq!(view["item"]["rating"], 4);
+    assert!(view["item"].get("keywords").is_none());
+
+    // removing the last tag and clearing the rating is written too
+    let (status, _, _) = app.send(Method::PUT, &alice, &flags, serde_json::json!({ "rating": 0 })).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let body = serde_json::json!({ "items": [item.id.to_string()], "remove": ["people/anna"] });
+    let (status, _, _) = app.post(&alice, "/api/tags", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let metadata = ultrahdr_rs::xmp_metadata(std::fs::read(&sidecar)?)?;
+    assert_eq!(metadata.rating, None);
+    assert!(metadata.keywords.is_empty());
+    assert!(metadata.tags.is_empty());
+
+    touch(&sidecar, 120)?;
+    rescan(&app.store, &mut scanner).await?;
+
+    let view = app.get_json(&alice, &exif).await?;
+    assert!(view["item"].get("tags").is_none());
+    assert!(view["item"].get("rating").is_none());
+
+    indexer.abort();
+
+    Ok(())
+}
+
+/// Scans a source again and returns the items the indexer added or updated.
+async fn rescan(store: &MediaStore, scanner: &mut Scanner) -> Result<Vec<MediaItem>> {
+    let token = store.page(None, 0, |_| true).await.token;
+
+    scanner.scan().await;
+
+    for _ in 0..100 {
+        let changes = store.changes_since(token).await.expect("changes");
+        if !changes.added.is_empty() {
+            return Ok(changes.added);
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    bail!("indexer did not pick up any changes")
+}
+
+/// Moves the modification time of a file into the future, like an editor saving it would.
+fn touch(path: &Path, seconds: u64) -> Result<()> {
+    let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(seconds);
+    std::fs::File::options().write(true).open(path)?.set_modified(modified)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_corrections() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    let correction = format!("/api/media/{}/correction", app.private.id);
+    let body = serde_json::json!({ "timestamp": "2001-02-03T04:05:06Z", "latitude": 53.55, "longitude": 9.99 });
+
+    let (status, _, body) = app.send(Method::PUT, &alice, &correction, body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let item: Value = serde_json::from_slice(&body)?;
+    assert_eq!(item["timestamp"], "2001-02-03T04:05:06Z");
+    assert_eq!(item["location"]["country"], "Germany");
+
+    // the corrected item moves to the end of the stream
+    let stream = app.get_json(&alice, "/api/stream").await?;
+    assert_eq!(stream["items"][1]["id"], app.private.id.to_string());
+
+    // the same shift again does not add up, corrections apply to the parsed values
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    let album = albums[0]["id"].as_str().expect("album id").to_owned();
+
+    for _ in 0..2 {
+        let body = serde_json::json!({ "shift": 3600 });
+        let (status, _, _) = app.send(Method::PUT, &bob, &format!("/api/albums/{}/correction", album), body).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let item = app.get_json(&bob, &format!("/api/media/{}/exif", app.shared.id)).await?;
+    let expected = app.shared.info.timestamp + Duration::hours(1);
+    assert_eq!(item["item"]["timestamp"], serde_json::to_value(expected)?);
+
+    let sidecar = index::xmp_sidecar_path(&app.shared.relpath, "xmp");
+    let expected = expected.format("<exif:DateTimeOriginal>%Y-%m-%dT%H:%M:%S</exif:DateTimeOriginal>").to_string();
+    assert!(std::fs::read_to_string(sidecar)?.contains(&expected));
+
+    // removing all fields restores the parsed values
+    let body = serde_json::json!({ "timestamp": null, "latitude": null, "longitude": null });
+    let (status, _, body) = app.send(Method::PUT, &alice, &correction, body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let item: Value = serde_json::from_slice(&body)?;
+    assert_eq!(item["timestamp"], serde_json::to_value(app.private.info.timestamp)?);
+    assert!(item.get("location").is_none());
+
+    let mut tx = app.db.begin().await?;
+    assert!(db::correction::read_correction(&mut tx, app.private.id).await?.is_none());
+    drop(tx);
+
+    let invalid = [
+        serde_json::json!({ "timestamp": "2001-02-03T04:05:06Z", "shift": 60 }),
+        serde_json::json!({ "latitude": 53.55 }),
+        serde_json::json!({ "latitude": 53.55, "longitude": null }),
+        serde_json::json!({ "latitude": 91, "longitude": 0 }),
+        serde_json::json!({ "shift": i64::MAX }),
+        serde_json::json!({ "shift": -200_i64 * 365 * 86400 }),
+    ];
+
+    for body in invalid {
+        let (status, _, _) = app.send(Method::PUT, &alice, &correction, body).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let (status, _, _) = app.send(Method::PUT, &bob, &correction, serde_json::json!({})).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // shifts that do not fit into a timestamp fail instead of panicking
+    let shift = Correction { shift: Some(i64::MAX), ..Correction::default() };
+    assert!(app.private.clone().corrected(&shift).is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_switching_identity_keeps_annotations() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let id = app.shared.id;
+
+    let token = app.share(&alice, serde_json::json!({ "items": [id] })).await?;
+
+    let body = serde_json::json!({ "name": "Best", "items": [id], "cover": id });
+    let (status, _, body) = app.post(&alice, "/api/albums", body).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let album: Value = serde_json::from_slice(&body)?;
+    let album = format!("/api/albums/{}", album["id"].as_str().expect("album id"));
+
+    app.send(Method::PUT, &alice, &format!("/api/media/{}/flags", id), serde_json::json!({ "favorite": true })).await?;
+    app.post(&alice, "/api/tags", serde_json::json!({ "items": [id.to_string()], "add": ["people/anna"] })).await?;
+
+    let correction = serde_json::json!({ "timestamp": "2001-02-03T04:05:06Z" });
+    app.send(Method::PUT, &alice, &format!("/api/media/{}/correction", id), correction).await?;
+
+    let source = "shared".into();
+    let root = app.shared.relpath.parent().and_then(Path::parent).expect("source root");
+    index::migrate_identity(&app.db, &source, root, Identity::Content).await?;
+
+    let cached = {
+        let mut tx = app.db.begin().await?;
+        db::media::list_media_items(&mut tx, &source).await?
+    };
+
+    let migrated = cached.into_iter().next().expect("cached item").item;
+    assert_ne!(migrated.id, id);
+
+    app.store.remove(id).await;
+    app.store.add(migrated.clone()).await;
+
+    let share = app.get_json("", &format!("/api/share/{}", token)).await?;
+    assert_eq!(share["items"][0]["id"], migrated.id.to_string());
+
+    let album = app.get_json(&alice, &album).await?;
+    assert_eq!(album["items"][0]["id"], migrated.id.to_string());
+    assert_eq!(album["cover"]["id"], migrated.id.to_string());
+
+    let item = app.get_json(&alice, &format!("/api/media/{}/exif", migrated.id)).await?;
+    assert_eq!(item["item"]["favorite"], true);
+    assert_eq!(item["item"]["tags"], serde_json::json!(["people/anna"]));
+
+    let mut tx = app.db.begin().await?;
+    let correction = db::correction::read_correction(&mut tx, migrated.id).await?.expect("correction");
+    assert_eq!(correction.timestamp, Some("2001-02-03T04:05:06Z".parse()?));
+    assert!(db::correction::read_correction(&mut tx, id).await?.is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_load_cached_removes_missing_files() -> Result<()> {
+    let app = TestApp::new().await?;
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().join("moved");
+    std::fs::create_dir_all(root.join("Album"))?;
+
+    // the scanner skips tiny files, use some noise to get a file large enough
+    let path = root.join("Album").join("noise.jpg");
+    let noise = image::RgbImage::from_fn(256, 256, |x, y| image::Rgb([(x * 7 + y * 13) as u8, (x * y) as u8, (x ^ y) as u8]));
+    noise.save(&path)?;
+
+    let queue = Arc::new(Mutex::new(ScanQueue::default()));
+    let scanned = {
+        let mut scanner = Scanner::new(&root, queue.clone(), "moved", Identity::Content);
+        scanner.scan().await;
+
+        let Some(QueueItem::Add(scanned)) = queue.lock().await.poll() else {
+            bail!("file was not scanned");
+        };
+
+        scanned
+    };
+
+    let info = MediaInfo {
+        timestamp: Utc::now(),
+        width: 256,
+        height: 256,
+        latitude: None,
+        longitude: None,
+        duration: None,
+        camera: None,
+    };
+
+    let item = MediaItem::from_media_info(scanned.id, "moved".into(), scanned.relpath.clone(), scanned.filesize, info)?;
+    let thumb = Image {
+        typ: ImageType::Jpeg,
+        blob: vec![0xff, 0xd8],
+    };
+
+    {
+        let mut tx = app.db.begin().await?;
+        db::media::store_media_item(&mut tx, &item, scanned.timestamp, None, Identity::Content).await?;
+        db::image::store(&mut tx, item.id, 16, &thumb).await?;
+        db::tag::add_tag(&mut tx, item.id, "people/anna").await?;
+        tx.commit().await?;
+    }
+
+    // moved while we were not watching. Not loaded, but the thumbnails are kept.
+    let renamed = root.join("Album").join("renamed.jpg");
+    std::fs::rename(&path, &renamed)?;
+
+    let (cached, missing) = index::load_cached(&app.db, &"moved".into(), &root, Identity::Content).await?;
+    assert!(cached.is_empty());
+    assert_eq!(missing, [item.id]);
+
+    {
+        let mut tx = app.db.begin().await?;
+        assert!(db::media::read_media_item(&mut tx, item.id).await?.is_some());
+        assert!(db::image::load(&mut tx, item.id, 16).await?.is_some());
+    }
+
+    // the scanner finds it under its new path with the same id
+    let mut scanner = Scanner::new(&root, queue.clone(), "moved", Identity::Content);
+    scanner.assume_missing(missing.clone());
+    scanner.scan().await;
+
+    match queue.lock().await.poll() {
+        Some(QueueItem::Add(rescanned)) => {
+            assert_eq!(rescanned.id, item.id);
+            assert_eq!(rescanned.relpath, Path::new("Album/renamed.jpg"));
+        }
+
+        _ => bail!("moved file was not scanned"),
+    }
+
+    // files that are gone for good are purged after the first full scan
+    std::fs::remove_file(&renamed)?;
+
+    let mut scanner = Scanner::new(&root, queue.clone(), "moved", Identity::Content);
+    scanner.assume_missing(missing);
+    scanner.scan().await;
+
+    let indexer = tokio::spawn(Indexer::new(app.db.clone(), queue, app.store.clone(), None, Geotagger::new(&[])).run());
+
+    for _ in 0..100 {
+        let mut tx = app.db.begin().await?;
+        if db::media::read_media_item(&mut tx, item.id).await?.is_none() {
+            break;
+        }
+
+        drop(tx);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    indexer.abort();
+
+    let mut tx = app.db.begin().await?;
+    assert!(db::media::read_media_item(&mut tx, item.id).await?.is_none());
+    assert!(db::image::load(&mut tx, item.id, 16).await?.is_none());
+
+    // kept in case the file shows up again under another path with the same content
+    assert_eq!(db::tag::read_tags(&mut tx, item.id).await?, ["people/anna"]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_geotag() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    // two points around innsbruck, one minute before and after the shared item was taken
+    let point = |offset: i64, lat: f32, lon: f32| {
+        let time = app.shared.info.timestamp + Duration::seconds(offset);
+        let time = time.to_rfc3339_opts(SecondsFormat::Secs, true);
+        format!(r#"<trkpt lat="{}" lon="{}"><ele>574</ele><time>{}</time></trkpt>"#, lat, lon, time)
+    };
+
+    let gpx = format!(
+        r#"<?xml version="1.0"?><gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1"><trk><trkseg>{}{}</trkseg></trk></gpx>"#,
+        point(-60, 47.25, 11.38),
+        point(60, 47.27, 11.40),
+    );
+
+    let (status, _, body) = app.send_raw(Method::POST, &alice, "/api/tracks?name=hike.gpx", "application/gpx+xml", gpx).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let track: Value = serde_json::from_slice(&body)?;
+    assert_eq!(track["name"], "hike.gpx");
+    assert_eq!(track["points"], 2);
+
+    // the location is interpolated between the two points
+    let item = app.get_json(&bob, &format!("/api/media/{}/exif", app.shared.id)).await?;
+    let location = &item["item"]["location"];
+    assert_eq!(location["inferred"], true);
+    assert_eq!(location["country"], "Austria");
+    assert_eq!(location["region"], "Tirol");
+    assert!((location["latitude"].as_f64().unwrap() - 47.26).abs() < 0.001);
+    assert!((location["longitude"].as_f64().unwrap() - 11.39).abs() < 0.001);
+
+    // the private source does not use gpx tracks
+    let item = app.get_json(&alice, &format!("/api/media/{}/exif", app.private.id)).await?;
+    assert!(item["item"].get("location").is_none());
+
+    // uploaded tracks are only listed for their owner
+    let tracks = app.get_json(&alice, "/api/tracks").await?;
+    assert_eq!(tracks.as_array().map(Vec::len), Some(1));
+
+    let tracks = app.get_json(&bob, "/api/tracks").await?;
+    assert_eq!(tracks.as_array().map(Vec::len), Some(0));
+
+    // a manual location replaces the inferred one, and removing it brings it back
+    let correction = format!("/api/media/{}/correction", app.shared.id);
+    let body = serde_json::json!({ "latitude": 53.55, "longitude": 9.99 });
+    let (_, _, body) = app.send(Method::PUT, &bob, &correction, body).await?;
+
+    let item: Value = serde_json::from_slice(&body)?;
+    assert!(item["location"].get("inferred").is_none());
+    assert_eq!(item["location"]["country"], "Germany");
+
+    let body = serde_json::json!({ "latitude": null, "longitude": null });
+    let (_, _, body) = app.send(Method::PUT, &bob, &correction, body).await?;
+    let item: Value = serde_json::from_slice(&body)?;
+    assert_eq!(item["location"]["inferred"], true);
+
+    let (status, _, _) = app.send_raw(Method::POST, &alice, "/api/tracks", "application/gpx+xml", "<gpx/>".into()).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // a camera offset that does not fit into a timestamp leaves the items alone
+    let mut source = source_config(Path::new("/"), "shared", &["alice"]);
+    source.geotag = Some(GeotagConfig {
+        camera_offset_in_seconds: i64::MAX,
+        max_gap_in_seconds: 300,
+    });
+
+    let geotagger = Geotagger::new(&[source]);
+    geotagger.reload(&app.db).await?;
+    assert!(geotagger.apply(app.shared.clone())?.location.is_none());
+
+    // deleting the track removes the inferred location
+    let id = track["id"].as_i64().expect("track id");
+
+    let (status, _, _) = app.send(Method::DELETE, &bob, &format!("/api/tracks/{}", id), Value::Null).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, _) = app.send(Method::DELETE, &alice, &format!("/api/tracks/{}", id), Value::Null).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let item = app.get_json(&bob, &format!("/api/media/{}/exif", app.shared.id)).await?;
+    assert!(item["item"].get("location").is_none());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_corrections_are_merged() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+
+    let correction = format!("/api/media/{}/correction", app.private.id);
+    let shifted = app.private.info.timestamp + Duration::hours(2);
+
+    // a location given after a shift keeps the shift
+    for body in [serde_json::json!({ "shift": 7200 }), serde_json::json!({ "latitude": 53.55, "longitude": 9.99 })] {
+        let (status, _, _) = app.send(Method::PUT, &alice, &correction, body).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (_, _, body) = app.send(Method::PUT, &alice, &correction, serde_json::json!({})).await?;
+    let item: Value = serde_json::from_slice(&body)?;
+    assert_eq!(item["timestamp"], serde_json::to_value(shifted)?);
+    assert_eq!(item["location"]["country"], "Germany");
+
+    // an absolute timestamp replaces the shift, the location stays
+    let body = serde_json::json!({ "timestamp": "2001-02-03T04:05:06Z" });
+    app.send(Method::PUT, &alice, &correction, body).await?;
+
+    let mut tx = app.db.begin().await?;
+    let stored = db::correction::read_correction(&mut tx, app.private.id).await?;
+    drop(tx);
+
+    let expected = Correction {
+        timestamp: Some("2001-02-03T04:05:06Z".parse()?),
+        shift: None,
+        latitude: Some(53.55),
+        longitude: Some(9.99),
+    };
+
+    assert_eq!(stored, Some(expected));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_map() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    // the private item in hamburg, the shared one in innsbruck
+    for (item, latitude, longitude) in [(&app.private, 53.55, 9.99), (&app.shared, 47.26, 11.39)] {
+        let body = serde_json::json!({ "latitude": latitude, "longitude": longitude });
+        let (status, _, _) = app.send(Method::PUT, &alice, &format!("/api/media/{}/correction", item.id), body).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // zoomed out, both are in one cluster with the newest item as its cover
+    let clusters = app.get_json(&alice, "/api/map?zoom=0").await?;
+    assert_eq!(clusters.as_array().map(Vec::len), Some(1));
+    assert_eq!(clusters[0]["count"], 2);
+    assert_eq!(clusters[0]["cover"], app.shared.id.to_string());
+    assert!((clusters[0]["latitude"].as_f64().unwrap() - 50.405).abs() < 0.001);
+
+    // zoomed in, they are apart
+    let clusters = app.get_json(&alice, "/api/map?zoom=8").await?;
+    assert_eq!(clusters.as_array().map(Vec::len), Some(2));
+
+    let clusters = app.get_json(&alice, "/api/map?zoom=8&bbox=5,50,15,55").await?;
+    assert_eq!(clusters.as_array().map(Vec::len), Some(1));
+    assert_eq!(clusters[0]["cover"], app.private.id.to_string());
+
+    // bob only sees the shared item
+    let clusters = app.get_json(&bob, "/api/map?zoom=0").await?;
+    assert_eq!(clusters.as_array().map(Vec::len), Some(1));
+    assert_eq!(clusters[0]["count"], 1);
+    assert_eq!(clusters[0]["cover"], app.shared.id.to_string());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_places() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.login("alice").await?;
+    let bob = app.login("bob").await?;
+
+    // the private item in lisbon, the shared one in porto
+    for (item, latitude, longitude) in [(&app.private, 38.72, -9.14), (&app.shared, 41.15, -8.61)] {
+        let body = serde_json::json!({ "latitude": latitude, "longitude": longitude });
+        let (status, _, _) = app.send(Method::PUT, &alice, &format!("/api/media/{}/correction", item.id), body).await?;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let places = app.get_json(&alice, "/api/places").await?;
+    assert_eq!(places.as_array().map(Vec::len), Some(1));
+    assert_eq!(places[0]["name"], "Portugal");
+    assert_eq!(places[0]["count"], 2);
+    assert_eq!(places[0]["from"], serde_json::to_value(app.private.info.timestamp)?);
+    assert_eq!(places[0]["to"], serde_json::to_value(app.shared.info.timestamp)?);
+
+    let cities = places[0]["cities"].as_array().expect("cities");
+    let names = cities.iter().map(|city| city["name"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, ["Lisbon", "Porto"]);
+
+    // the album of a city can be opened like any other album
+    let lisbon = cities[0]["album"].as_str().expect("album id").to_owned();
+    let album = app.get_json(&alice, &format!("/api/albums/{}", lisbon)).await?;
+    assert_eq!(album["name"], "Lisbon");
+    assert_eq!(album["items"][0]["id"], app.private.id.to_string());
+
+    let country = places[0]["album"].as_str().expect("album id");
+    let album = app.get_json(&alice, &format!("/api/albums/{}", country)).await?;
+    assert_eq!(album["items"].as_array().map(Vec::len), Some(2));
+
+    // bob only sees porto
+    let places = app.get_json(&bob, "/api/places").await?;
+    assert_eq!(places[0]["count"], 1);
+    assert_eq!(places[0]["cities"].as_array().map(Vec::len), Some(1));
+
+    let (status, _) = app.get(&bob, &format!("/api/albums/{}", lisbon)).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_events() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    let hamburg = (53.55, 9.99);
+    let lisbon = (38.72, -9.14);
+
+    // three days at home in hamburg with a trip to lisbon in between
+    let items = [
+        ("2024-05-01T10:00:00Z", hamburg),
+        ("2024-05-01T10:30:00Z", hamburg),
+        ("2024-05-01T11:00:00Z", hamburg),
+        ("2024-05-10T12:00:00Z", lisbon),
+        ("2024-05-11T12:00:00Z", lisbon),
+        ("2024-05-12T12:00:00Z", lisbon),
+        ("2024-05-20T09:00:00Z", hamburg),
+        ("2024-05-20T09:30:00Z", hamburg),
+        ("2024-05-20T10:00:00Z", hamburg),
+    ];
+
+    app.add_items(items.map(|(timestamp, _)| timestamp), |idx, item| {
+        let (latitude, longitude) = items[idx].1;
+
+        let correction = Correction {
+            latitude: Some(latitude),
+            longitude: Some(longitude),
+            ..Correction::default()
+        };
+
+        item.corrected(&correction)
+    })
+    .await?;
+
+    let albums = app.get_json(&bob, "/api/albums?kind=trip&kind=day").await?;
+    let albums = albums.as_array().expect("albums");
+
+    let names = albums.iter().map(|album| album["name"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(names, ["Hamburg, 1 May 2024", "Lisbon, 10 – 12 May 2024", "Hamburg, 20 May 2024"]);
+
+    let kinds = albums.iter().map(|album| album["kind"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(kinds, ["day", "trip", "day"]);
+
+    let trip = app.get_json(&bob, &format!("/api/albums/{}", albums[1]["id"].as_str().unwrap())).await?;
+    assert_eq!(trip["items"].as_array().map(Vec::len), Some(3));
+
+    // only trips
+    let albums = app.get_json(&bob, "/api/albums?kind=trip").await?;
+    assert_eq!(albums.as_array().map(Vec::len), Some(1));
+
+    // events are not listed with the regular albums
+    let albums = app.get_json(&bob, "/api/albums").await?;
+    assert!(albums.as_array().expect("albums").iter().all(|album| album["kind"] == "directory"));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_memories() -> Result<()> {
+    let app = TestApp::new().await?;
+    let bob = app.login("bob").await?;
+
+    // the first two are copies of the same file
+    let items = [
+        ("2023-05-01T10:00:00Z", Some(1)),
+        ("2023-05-01T12:00:00Z", Some(1)),
+        ("2021-05-01T08:00:00Z", None),
+        ("2021-05-02T08:00:00Z", None),
+        ("2024-05-01T08:00:00Z", None),
+        ("2020-02-29T08:00:00Z", None),
+    ];
+
+    let added = app
+        .add_items(items.map(|(timestamp, _)| timestamp), |idx, mut item| {
+            item.hashes = items[idx].1.map(|content| MediaHashes {
+                content: [content; 20],
+                perceptual: None,
+            });
+
+            Ok(item)
+        })
+        .await?;
+
+    let ids = added.iter().map(|item| item.id.to_string()).collect::<Vec<_>>();
+
+    let memories = app.get_json(&bob, "/api/memories?date=2024-05-01").await?;
+    let years = memories.as_array().expect("memories").iter().map(|memory| memory["year"].clone()).collect::<Vec<_>>();
+    assert_eq!(years, [2023, 2021]);
+    assert_eq!(memories[0]["yearsAgo"], 1);
+    assert_eq!(memories[0]["items"][0]["id"], ids[0]);
+    assert_eq!(memories[1]["items"].as_array().map(Vec::len), Some(1));
+
+    // favorites first
+    let favorite = serde_json::json!({ "favorite": true });
+    app.send(Method::PUT, &bob, &format!("/api/media/{}/flags", ids[1]), favorite).await?;
+
+    let memories = app.get_json(&bob, "/api/memories?date=2024-05-01&ranked=true&limit=1").await?;
+    assert_eq!(memories[0]["count"], 2);
+    assert_eq!(memories[0]["items"].as_array().map(Vec::len), Some(1));
+    assert_eq!(memories[0]["items"][0]["id"], ids[1]);
+
+    // of the two copies only the favorite is left
+    let memories = app.get_json(&bob, "/api/memories?date=2024-05-01&ranked=true&skipDuplicates=true").await?;
+    assert_eq!(memories[0]["count"], 1);
+    assert_eq!(memories[0]["items"][0]["id"], ids[1]);
+
+    // the leap day shows up on the 28th of february
+    let memories = app.get_json(&bob, "/api/memories?date=2023-02-28").await?;
+    assert_eq!(memories[0]["year"], 2020);
+    assert_eq!(memories[0]["items"][0]["id"], ids[5]);
+
+    Ok(())
+}
@@ -274,6 +274,190 @@ pub fn is_ultrahdr<R>(r: R) -> Result<bool>
     Ok(has_mpf && has_xmp_container)
 }
 
+/// A video embedded at the end of a jpeg file, e.g. a google motion photo.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MotionPhoto {
+    // start of the video, counted backwards from the end of the file
+    pub offset_from_end: u64,
+
+    // length of the video in bytes
+    pub len: u64,
+}
+
+/// Checks the xmp metadata of a jpeg file for an embedded motion photo video.
+pub fn motion_photo<R>(r: R) -> Result<Option<MotionPhoto>>
+    where R: Read,
+{
+    let mut reader = jfif::Reader::new(r)?;
+
+    while let Some(segment) = reader.next()? {
+        match &segment.kind {
+            SegmentKind::StartOfImage | SegmentKind::Comment => {
+                continue;
+            }
+
+            SegmentKind::App(app) => {
+                let Some(data) = app.data.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0") else {
+                    continue;
+                };
+
+                let Ok(xmp) = xmp::parse_motion(data) else {
+                    continue;
+                };
+
+                for description in &xmp.rdf.description {
+                    if let Some(motion) = motion_photo_from_xmp(description) {
+                        return Ok(Some(motion));
+                    }
+                }
+            }
+
+            _ => break
+        }
+    }
+
+    Ok(None)
+}
+
+fn motion_photo_from_xmp(description: &xmp::motion::Description) -> Option<MotionPhoto> {
+    if let Some(directory) = &description.directory {
+        // all items following the primary image are appended to the file in order
+        let items = directory.seq.li.get(1..)?;
+
+        let idx = items.iter().position(|li| li.item.semantic == "MotionPhoto")?;
+        let len = items[idx].item.length?;
+
+        let offset_from_end = items[idx..].iter().map(|li| li.item.length.unwrap_or_default()).sum();
+
+        return Some(MotionPhoto { offset_from_end, len });
+    }
+
+    // older format, the video is always at the end of the file
+    let offset = description.micro_video_offset.filter(|&offset| offset > 0)?;
+    Some(MotionPhoto { offset_from_end: offset, len: offset })
+}
+
+/// Metadata that photo management tools like lightroom or darktable store as xmp,
+/// either embedded into the file or in a sidecar file next to it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct XmpMetadata {
+    // from 1 to 5 stars. Rejected or unrated files have no rating.
+    pub rating: Option<u8>,
+
+    // a color label
+    pub label: Option<String>,
+
+    pub keywords: Vec<String>,
+    pub description: Option<String>,
+
+    // the keywords an application manages itself, see [XmpUpdate::tags]
+    pub tags: Vec<String>,
+}
+
+/// Parses the metadata of an xmp packet, e.g. the content of an xmp sidecar file.
+pub fn xmp_metadata(xml: impl AsRef<[u8]>) -> Result<XmpMetadata> {
+    let xmp = xmp::parse_metadata(xml)?;
+
+    let mut metadata = XmpMetadata::default();
+
+    for description in &xmp.rdf.description {
+        let rating = description.rating_attr.as_ref().or(description.rating.as_ref());
+        if let Some(rating) = rating.and_then(|rating| rating.trim().parse::<i32>().ok()) {
+            metadata.rating = u8::try_from(rating).ok().filter(|rating| (1..=5).contains(rating));
+        }
+
+        let label = description.label_attr.as_ref().or(description.label.as_ref());
+        if let Some(label) = label.map(|label| label.trim()).filter(|label| !label.is_empty()) {
+            metadata.label = Some(label.to_owned());
+        }
+
+        for keyword in description.subject.iter().flat_map(|list| list.items()) {
+            let keyword = keyword.text.trim();
+            if !keyword.is_empty() && !metadata.keywords.iter().any(|known| known == keyword) {
+                metadata.keywords.push(keyword.to_owned());
+            }
+        }
+
+        for tag in description.tags.iter().flat_map(|list| list.items()) {
+            let tag = tag.text.trim();
+            if !tag.is_empty() && !metadata.tags.iter().any(|known| known == tag) {
+                metadata.tags.push(tag.to_owned());
+            }
+        }
+
+        // prefer the default language
+        if let Some(list) = &description.description {
+            let text = list
+                .items()
+                .find(|item| item.lang.as_deref() == Some("x-default"))
+                .or_else(|| list.items().next())
+                .map(|item| item.text.trim())
+                .filter(|text| !text.is_empty());
+
+            if let Some(text) = text {
+                metadata.description = Some(text.to_owned());
+            }
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Reads the metadata of the xmp data embedded into a jpeg file.
+pub fn embedded_xmp_metadata<R>(r: R) -> Result<Option<XmpMetadata>>
+    where R: Read,
+{
+    let mut reader = jfif::Reader::new(r)?;
+
+    while let Some(segment) = reader.next()? {
+        match &segment.kind {
+            SegmentKind::StartOfImage | SegmentKind::Comment => {
+                continue;
+            }
+
+            SegmentKind::App(app) => {
+                let Some(data) = app.data.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0") else {
+                    continue;
+                };
+
+                return Ok(Some(xmp_metadata(data)?));
+            }
+
+            _ => break
+        }
+    }
+
+    Ok(None)
+}
+
+/// Values to write into an xmp sidecar file. Values that are None are left as they are.
+#[derive(Debug, Clone, Default)]
+pub struct XmpUpdate {
+    // Some(None) removes the rating
+    pub rating: Option<Option<u8>>,
+
+    // replaces all keywords, an empty list removes them
+    pub keywords: Option<Vec<String>>,
+
+    // the keywords an application added on its own. They are listed again in `pica:Tags`,
+    // so that they can be told apart from the other keywords when reading the file again.
+    pub tags: Option<Vec<String>>,
+
+    pub description: Option<String>,
+
+    // local time the picture was taken, e.g. `2024-01-28T12:56:32`
+    pub date_time_original: Option<String>,
+
+    // latitude and longitude in degrees
+    pub gps: Option<(f64, f64)>,
+}
+
+/// Applies the update to the content of an xmp sidecar file, or creates a new one
+/// if there is none yet. Anything else in the existing file is kept.
+pub fn update_xmp(existing: Option<&[u8]>, update: &XmpUpdate) -> Result<Vec<u8>> {
+    xmp::update_metadata(existing, update)
+}
+
 pub struct UltraHDR {
     pub primary: Jpeg,
     pub gainmap: Jpeg,
@@ -355,7 +539,7 @@ mod test {
     use hex_literal::hex;
     use sha1_smol::Sha1;
 
-    use crate::{Jpeg, UltraHDR};
+    use crate::{Jpeg, UltraHDR, XmpMetadata, XmpUpdate};
 
     #[test]
     fn ultrahdr_from_reader() -> anyhow::Result<()> {
@@ -396,6 +580,131 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn embedded_xmp_metadata() -> anyhow::Result<()> {
+        // the embedded xmp only describes the gainmap, but has no metadata
+        let r = include_bytes!("../data/PXL_20240128_125632590.jpg").as_slice();
+        let metadata = super::embedded_xmp_metadata(r)?;
+        assert_eq!(metadata, Some(XmpMetadata::default()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn xmp_metadata_rating() -> anyhow::Result<()> {
+        const XML: &str = r#"
+            <x:xmpmeta xmlns:x="adobe:ns:meta/">
+             <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+              <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="-1"/>
+              <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+               <dc:subject><rdf:Bag><rdf:li>alps</rdf:li><rdf:li> alps </rdf:li></rdf:Bag></dc:subject>
+              </rdf:Description>
+             </rdf:RDF>
+            </x:xmpmeta>
+        "#;
+
+        // rejected files have a negative rating
+        let metadata = super::xmp_metadata(XML)?;
+        assert_eq!(metadata.rating, None);
+        assert_eq!(metadata.keywords, ["alps"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_xmp_creates_sidecar() -> anyhow::Result<()> {
+        let update = XmpUpdate {
+            rating: Some(Some(4)),
+            keywords: Some(vec!["people/anna".into(), "alps & lakes".into()]),
+            tags: Some(vec!["people/anna".into()]),
+            description: Some("Snow".into()),
+            date_time_original: Some("2024-01-28T12:56:32".into()),
+            gps: Some((53.43018, -9.977647)),
+        };
+
+        let xml = super::update_xmp(None, &update)?;
+
+        let metadata = super::xmp_metadata(&xml)?;
+        assert_eq!(metadata.rating, Some(4));
+        assert_eq!(metadata.keywords, ["people/anna", "alps & lakes"]);
+        assert_eq!(metadata.tags, ["people/anna"]);
+        assert_eq!(metadata.description.as_deref(), Some("Snow"));
+
+        let xml = String::from_utf8(xml)?;
+        assert!(xml.contains("<exif:DateTimeOriginal>2024-01-28T12:56:32</exif:DateTimeOriginal>"));
+        assert!(xml.contains("<exif:GPSLatitude>53,25.810800N</exif:GPSLatitude>"));
+        assert!(xml.contains("<exif:GPSLongitude>9,58.658820W</exif:GPSLongitude>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_xmp_keeps_other_data() -> anyhow::Result<()> {
+        const XML: &str = r#"
+            <x:xmpmeta xmlns:x="adobe:ns:meta/">
+             <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+              <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+                xmlns:darktable="http://darktable.sf.net/" xmlns:dc="http://purl.org/dc/elements/1.1/"
+                xmp:Rating="2" xmp:Label="Red" darktable:history_end="3">
+               <dc:subject><rdf:Bag><rdf:li>alps</rdf:li></rdf:Bag></dc:subject>
+               <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Old</rdf:li></rdf:Alt></dc:description>
+               <darktable:history><rdf:Seq><rdf:li darktable:operation="exposure"/></rdf:Seq></darktable:history>
+              </rdf:Description>
+             </rdf:RDF>
+            </x:xmpmeta>
+        "#;
+
+        let update = XmpUpdate {
+            rating: Some(Some(5)),
+            keywords: Some(vec!["alps".into(), "hiking".into()]),
+            ..XmpUpdate::default()
+        };
+
+        let xml = super::update_xmp(Some(XML.as_bytes()), &update)?;
+
+        let metadata = super::xmp_metadata(&xml)?;
+        assert_eq!(metadata.rating, Some(5));
+        assert_eq!(metadata.label.as_deref(), Some("Red"));
+        assert_eq!(metadata.keywords, ["alps", "hiking"]);
+        assert_eq!(metadata.description.as_deref(), Some("Old"));
+
+        let xml = String::from_utf8(xml)?;
+        assert!(xml.contains(r#"darktable:history_end="3""#));
+        assert!(xml.contains(r#"<rdf:li darktable:operation="exposure"/>"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_xmp_removes_values() -> anyhow::Result<()> {
+        let update = XmpUpdate {
+            rating: Some(Some(3)),
+            keywords: Some(vec!["alps".into(), "people/anna".into()]),
+            tags: Some(vec!["people/anna".into()]),
+            ..XmpUpdate::default()
+        };
+
+        let xml = super::update_xmp(None, &update)?;
+
+        let update = XmpUpdate {
+            rating: Some(None),
+            keywords: Some(Vec::new()),
+            tags: Some(Vec::new()),
+            ..XmpUpdate::default()
+        };
+
+        let xml = super::update_xmp(Some(&xml), &update)?;
+
+        let metadata = super::xmp_metadata(&xml)?;
+        assert_eq!(metadata, XmpMetadata::default());
+
+        let xml = String::from_utf8(xml)?;
+        assert!(!xml.contains("subject"));
+        assert!(!xml.contains("pica:Tags"));
+
+        Ok(())
+    }
+
     #[test]
     fn write_ultra_hdr() -> anyhow::Result<()> {
         let primary = Jpeg::from_bytes(include_bytes!("../data/_primary-25.jpg"))?;
@@ -1,6 +1,10 @@
 use std::io::{BufReader};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::XmpUpdate;
 
 pub fn parse_container(xml: impl AsRef<[u8]>) -> Result<primary::Xmp> {
     let data = BufReader::new(xml.as_ref());
@@ -18,6 +22,255 @@ pub fn parse_gainmap(xml: impl AsRef<[u8]>) -> Result<gainmap::Xmp> {
     Ok(quick_xml::de::from_reader(data)?)
 }
 
+pub fn parse_motion(xml: impl AsRef<[u8]>) -> Result<motion::Xmp> {
+    let data = BufReader::new(xml.as_ref());
+
+    // We are pretty lenient in what we accept. It should just kind of match the
+    // expected structure. We currently do not care about the namespaces
+    Ok(quick_xml::de::from_reader(data)?)
+}
+
+pub fn parse_metadata(xml: impl AsRef<[u8]>) -> Result<metadata::Xmp> {
+    let data = BufReader::new(xml.as_ref());
+
+    // We are pretty lenient in what we accept. It should just kind of match the
+    // expected structure. We currently do not care about the namespaces
+    Ok(quick_xml::de::from_reader(data)?)
+}
+
+/// An empty xmp packet, used if there is no sidecar file yet.
+const EMPTY_PACKET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+const NAMESPACES: [(&str, &str); 4] = [
+    ("xmlns:xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmlns:dc", "http://purl.org/dc/elements/1.1/"),
+    ("xmlns:exif", "http://ns.adobe.com/exif/1.0/"),
+    ("xmlns:pica", "http://ns.pica/1.0/"),
+];
+
+/// Writes the values of `update` into an xmp packet, keeping everything else in `existing`,
+/// e.g. the edit history of darktable. Like when parsing, properties are matched by their
+/// local name, we do not resolve namespaces.
+pub fn update_metadata(existing: Option<&[u8]>, update: &XmpUpdate) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(existing.unwrap_or(EMPTY_PACKET.as_bytes()));
+    let mut writer = Writer::new(Vec::new());
+
+    // the properties we replace
+    let mut replaced = Vec::new();
+    if update.rating.is_some() {
+        replaced.push(b"Rating".as_slice());
+    }
+    if update.keywords.is_some() {
+        replaced.push(b"subject");
+    }
+    if update.tags.is_some() {
+        replaced.push(b"Tags");
+    }
+    if update.description.is_some() {
+        replaced.push(b"description");
+    }
+    if update.date_time_original.is_some() {
+        replaced.push(b"DateTimeOriginal");
+    }
+    if update.gps.is_some() {
+        replaced.extend([b"GPSLatitude".as_slice(), b"GPSLongitude"]);
+    }
+
+    // local names of the currently open elements
+    let mut open = Vec::<Vec<u8>>::new();
+
+    // depth within a property we are dropping
+    let mut skipping = 0_usize;
+
+    let mut written = false;
+
+    loop {
+        let event = reader.read_event()?;
+
+        if skipping > 0 {
+            match event {
+                Event::Start(_) => skipping += 1,
+                Event::End(_) => skipping -= 1,
+                Event::Eof => bail!("unexpected end of xmp data"),
+                _ => (),
+            }
+
+            continue;
+        }
+
+        let in_description = open.last().is_some_and(|name| name == b"Description");
+
+        match event {
+            Event::Eof => break,
+
+            Event::Start(start) if in_description && replaced.contains(&start.local_name().as_ref()) => {
+                skipping = 1;
+            }
+
+            Event::Empty(start) if in_description && replaced.contains(&start.local_name().as_ref()) => {}
+
+            Event::Start(start) if start.local_name().as_ref() == b"Description" => {
+                open.push(b"Description".to_vec());
+
+                let start = strip_attributes(&start, &replaced, !written)?;
+                writer.write_event(Event::Start(start))?;
+
+                if !written {
+                    write_properties(&mut writer, update)?;
+                    written = true;
+                }
+            }
+
+            Event::Empty(start) if start.local_name().as_ref() == b"Description" => {
+                let start = strip_attributes(&start, &replaced, !written)?;
+
+                if written {
+                    writer.write_event(Event::Empty(start))?;
+                    continue;
+                }
+
+                let end = start.to_end().into_owned();
+                writer.write_event(Event::Start(start))?;
+                write_properties(&mut writer, update)?;
+                writer.write_event(Event::End(end))?;
+                written = true;
+            }
+
+            // there was no description to put the properties into
+            Event::End(end) if end.local_name().as_ref() == b"RDF" && !written => {
+                let mut start = BytesStart::new("rdf:Description");
+                start.push_attribute(("rdf:about", ""));
+                start.extend_attributes(NAMESPACES);
+
+                writer.write_event(Event::Start(start))?;
+                write_properties(&mut writer, update)?;
+                writer.write_event(Event::End(BytesEnd::new("rdf:Description")))?;
+                writer.write_event(Event::End(end))?;
+
+                open.pop();
+                written = true;
+            }
+
+            Event::Start(start) => {
+                open.push(start.local_name().as_ref().to_vec());
+                writer.write_event(Event::Start(start))?;
+            }
+
+            Event::End(end) => {
+                open.pop();
+                writer.write_event(Event::End(end))?;
+            }
+
+            event => writer.write_event(event)?,
+        }
+    }
+
+    ensure!(written, "no rdf:RDF element found in xmp data");
+
+    Ok(writer.into_inner())
+}
+
+/// Copies the start tag without the attributes we replace. Adds the
+/// namespace declarations of our properties if requested.
+fn strip_attributes(start: &BytesStart, replaced: &[&[u8]], namespaces: bool) -> Result<BytesStart<'static>> {
+    let name = String::from_utf8(start.name().as_ref().to_vec())?;
+    let mut stripped = BytesStart::new(name);
+
+    for attr in start.attributes() {
+        let attr = attr?;
+        if !replaced.contains(&attr.key.local_name().as_ref()) {
+            stripped.push_attribute(attr);
+        }
+    }
+
+    if namespaces {
+        for (key, value) in NAMESPACES {
+            if start.try_get_attribute(key)?.is_none() {
+                stripped.push_attribute((key, value));
+            }
+        }
+    }
+
+    Ok(stripped)
+}
+
+fn write_properties(writer: &mut Writer<Vec<u8>>, update: &XmpUpdate) -> Result<()> {
+    if let Some(rating) = update.rating.flatten() {
+        writer.create_element("xmp:Rating").write_text_content(BytesText::new(&rating.to_string()))?;
+    }
+
+    if let Some(keywords) = update.keywords.as_ref().filter(|keywords| !keywords.is_empty()) {
+        write_bag(writer, "dc:subject", keywords)?;
+    }
+
+    if let Some(tags) = update.tags.as_ref().filter(|tags| !tags.is_empty()) {
+        write_bag(writer, "pica:Tags", tags)?;
+    }
+
+    if let Some(description) = &update.description {
+        writer.create_element("dc:description").write_inner_content(|writer| {
+            writer
+                .create_element("rdf:Alt")
+                .write_inner_content(|writer| {
+                    writer
+                        .create_element("rdf:li")
+                        .with_attribute(("xml:lang", "x-default"))
+                        .write_text_content(BytesText::new(description))?;
+
+                    Ok(())
+                })?;
+
+            Ok(())
+        })?;
+    }
+
+    if let Some(timestamp) = &update.date_time_original {
+        writer.create_element("exif:DateTimeOriginal").write_text_content(BytesText::new(timestamp))?;
+    }
+
+    if let Some((latitude, longitude)) = update.gps {
+        let latitude = gps_coordinate(latitude, 'N', 'S');
+        writer.create_element("exif:GPSLatitude").write_text_content(BytesText::new(&latitude))?;
+
+        let longitude = gps_coordinate(longitude, 'E', 'W');
+        writer.create_element("exif:GPSLongitude").write_text_content(BytesText::new(&longitude))?;
+    }
+
+    Ok(())
+}
+
+fn write_bag(writer: &mut Writer<Vec<u8>>, name: &str, values: &[String]) -> Result<()> {
+    writer.create_element(name).write_inner_content(|writer| {
+        writer.create_element("rdf:Bag").write_inner_content(|writer| {
+            for value in values {
+                writer.create_element("rdf:li").write_text_content(BytesText::new(value))?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+/// Formats a coordinate as xmp expects it, degrees and decimal minutes, e.g. `53,25.8108N`
+fn gps_coordinate(value: f64, positive: char, negative: char) -> String {
+    let direction = if value < 0.0 { negative } else { positive };
+
+    let value = value.abs();
+    let degrees = value.trunc();
+    let minutes = (value - degrees) * 60.0;
+
+    format!("{},{:.6}{}", degrees, minutes, direction)
+}
+
 pub mod gainmap {
     use serde::Deserialize;
 
@@ -117,9 +370,139 @@ pub mod primary {
     }
 }
 
+pub mod motion {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Xmp {
+        #[serde(rename = "RDF")]
+        pub rdf: Rdf,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Rdf {
+        #[serde(rename = "Description", default)]
+        pub description: Vec<Description>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Description {
+        // offset of the video from the end of the file, used by older google camera versions
+        #[serde(rename = "@MicroVideoOffset")]
+        pub micro_video_offset: Option<u64>,
+
+        #[serde(rename = "Directory")]
+        pub directory: Option<Directory>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Directory {
+        #[serde(rename = "Seq")]
+        pub seq: Seq,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Seq {
+        #[serde(rename = "li", default)]
+        pub li: Vec<Li>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Li {
+        #[serde(rename = "Item")]
+        pub item: ListItem,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct ListItem {
+        #[serde(rename = "@Semantic")]
+        pub semantic: String,
+
+        #[serde(rename = "@Length")]
+        pub length: Option<u64>,
+    }
+}
+
+pub mod metadata {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Xmp {
+        #[serde(rename = "RDF")]
+        pub rdf: Rdf,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Rdf {
+        #[serde(rename = "Description", default)]
+        pub description: Vec<Description>,
+    }
+
+    /// Simple values can be written as attribute or as element.
+    #[derive(Deserialize, Debug)]
+    pub struct Description {
+        #[serde(rename = "@Rating")]
+        pub rating_attr: Option<String>,
+
+        #[serde(rename = "Rating")]
+        pub rating: Option<String>,
+
+        #[serde(rename = "@Label")]
+        pub label_attr: Option<String>,
+
+        #[serde(rename = "Label")]
+        pub label: Option<String>,
+
+        // the keywords
+        #[serde(rename = "subject")]
+        pub subject: Option<List>,
+
+        // the keywords pica added itself
+        #[serde(rename = "Tags")]
+        pub tags: Option<List>,
+
+        #[serde(rename = "description")]
+        pub description: Option<List>,
+    }
+
+    /// An rdf container, a Bag for keywords or an Alt for localized text
+    #[derive(Deserialize, Debug)]
+    pub struct List {
+        #[serde(rename = "Bag")]
+        pub bag: Option<Items>,
+
+        #[serde(rename = "Seq")]
+        pub seq: Option<Items>,
+
+        #[serde(rename = "Alt")]
+        pub alt: Option<Items>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Items {
+        #[serde(rename = "li", default)]
+        pub li: Vec<Item>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Item {
+        #[serde(rename = "@lang")]
+        pub lang: Option<String>,
+
+        #[serde(rename = "$text", default)]
+        pub text: String,
+    }
+
+    impl List {
+        pub fn items(&self) -> impl Iterator<Item = &Item> {
+            [&self.bag, &self.seq, &self.alt].into_iter().flatten().flat_map(|items| &items.li)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::xmp::{parse_container, parse_gainmap};
+    use crate::xmp::{parse_container, parse_gainmap, parse_metadata, parse_motion};
     use crate::xmp::primary::Semantic;
 
     #[test]
@@ -188,4 +571,130 @@ mod test {
         assert_eq!(container.rdf.description.gainmap_max, 2.524697);
         assert_eq!(container.rdf.description.gainmap_min, 0.0);
     }
+
+    #[test]
+    fn test_parse_motion() {
+        const XML: &str = r#"
+            <x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 5.1.0-jc003">
+              <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+                <rdf:Description rdf:about=""
+                    xmlns:GCamera="http://ns.google.com/photos/1.0/camera/"
+                    xmlns:Container="http://ns.google.com/photos/1.0/container/"
+                    xmlns:Item="http://ns.google.com/photos/1.0/container/item/"
+                  GCamera:MotionPhoto="1"
+                  GCamera:MotionPhotoVersion="1"
+                  GCamera:MotionPhotoPresentationTimestampUs="968644">
+                  <Container:Directory>
+                    <rdf:Seq>
+                      <rdf:li rdf:parseType="Resource">
+                        <Container:Item
+                          Item:Mime="image/jpeg"
+                          Item:Semantic="Primary"
+                          Item:Length="0"
+                          Item:Padding="0"/>
+                      </rdf:li>
+                      <rdf:li rdf:parseType="Resource">
+                        <Container:Item
+                          Item:Mime="video/mp4"
+                          Item:Semantic="MotionPhoto"
+                          Item:Length="2563221"
+                          Item:Padding="0"/>
+                      </rdf:li>
+                    </rdf:Seq>
+                  </Container:Directory>
+                </rdf:Description>
+              </rdf:RDF>
+            </x:xmpmeta>
+        "#;
+
+        let motion = parse_motion(XML).expect("parse motion");
+        let directory = motion.rdf.description[0].directory.as_ref().expect("directory");
+        assert_eq!(directory.seq.li.len(), 2);
+        assert_eq!(directory.seq.li[1].item.semantic, "MotionPhoto");
+        assert_eq!(directory.seq.li[1].item.length, Some(2563221));
+    }
+
+    #[test]
+    fn test_parse_micro_video() {
+        const XML: &str = r#"
+            <x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 5.1.0-jc003">
+              <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+                <rdf:Description rdf:about=""
+                    xmlns:GCamera="http://ns.google.com/photos/1.0/camera/"
+                  GCamera:MicroVideo="1"
+                  GCamera:MicroVideoVersion="1"
+                  GCamera:MicroVideoOffset="3107523"
+                  GCamera:MicroVideoPresentationTimestampUs="1208033"/>
+              </rdf:RDF>
+            </x:xmpmeta>
+        "#;
+
+        let motion = parse_motion(XML).expect("parse motion");
+        assert_eq!(motion.rdf.description[0].micro_video_offset, Some(3107523));
+        assert!(motion.rdf.description[0].directory.is_none());
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        const XML: &str = r#"
+            <?xpacket begin="﻿" id="W5M0MpCehiHzreSzNTczkc9d"?>
+            <x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0-c000">
+             <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+              <rdf:Description rdf:about=""
+                xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+                xmlns:dc="http://purl.org/dc/elements/1.1/"
+               xmp:Rating="4"
+               xmp:Label="Red">
+               <dc:subject>
+                <rdf:Bag>
+                 <rdf:li>alps</rdf:li>
+                 <rdf:li>hiking</rdf:li>
+                </rdf:Bag>
+               </dc:subject>
+               <dc:description>
+                <rdf:Alt>
+                 <rdf:li xml:lang="x-default">View from the summit</rdf:li>
+                </rdf:Alt>
+               </dc:description>
+              </rdf:Description>
+             </rdf:RDF>
+            </x:xmpmeta>
+            <?xpacket end="w"?>
+        "#;
+
+        let xmp = parse_metadata(XML).expect("parse metadata");
+        let description = &xmp.rdf.description[0];
+        assert_eq!(description.rating_attr.as_deref(), Some("4"));
+        assert_eq!(description.label_attr.as_deref(), Some("Red"));
+
+        let keywords: Vec<_> = description.subject.iter().flat_map(|list| list.items()).map(|item| item.text.as_str()).collect();
+        assert_eq!(keywords, ["alps", "hiking"]);
+
+        let text = description.description.as_ref().and_then(|list| list.items().next()).expect("description");
+        assert_eq!(text.lang.as_deref(), Some("x-default"));
+        assert_eq!(text.text, "View from the summit");
+    }
+
+    #[test]
+    fn test_parse_metadata_elements() {
+        // darktable writes simple values as elements in a second description
+        const XML: &str = r#"
+            <x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="XMP Core 4.4.0-Exiv2">
+             <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+              <rdf:Description rdf:about=""
+                xmlns:darktable="http://darktable.sf.net/"
+               darktable:xmp_version="5"/>
+              <rdf:Description rdf:about=""
+                xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+               <xmp:Rating>2</xmp:Rating>
+              </rdf:Description>
+             </rdf:RDF>
+            </x:xmpmeta>
+        "#;
+
+        let xmp = parse_metadata(XML).expect("parse metadata");
+        assert_eq!(xmp.rdf.description.len(), 2);
+        assert_eq!(xmp.rdf.description[1].rating.as_deref(), Some("2"));
+        assert!(xmp.rdf.description[0].subject.is_none());
+    }
 }